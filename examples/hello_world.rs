@@ -1,17 +1,13 @@
 use std::sync::Arc;
 
-use http::{Method, Request, Response, StatusCode, Version};
-use http_server::{Router, HttpServer};
+use http::Method;
+use http_server::{AccessLog, AccessLogFormat, HttpServer, IntoResponse, Router, Text};
 use async_executor::Executor;
 use simple_error::SimpleResult;
 use smol::MainExecutor;
 
-async fn get_index(_executor: Arc<Executor<'static>>, _request: Request<Vec<u8>>) -> SimpleResult<Response<String>> {
-    Ok(Response::builder()
-    .status(StatusCode::OK)
-    .version(Version::HTTP_11)
-    .header("Content-Type", "text/plain")
-    .body("Hello, World!".to_string())?)
+async fn get_index() -> impl IntoResponse {
+    Text("Hello, World!")
 }
 
 async fn async_main(executor: Arc<Executor<'static>>) -> SimpleResult<()> {
@@ -24,13 +20,16 @@ async fn async_main(executor: Arc<Executor<'static>>) -> SimpleResult<()> {
 
     // build router
     let mut router = Router::new(executor.clone());
-    router.add_routes(vec![
-        (Method::GET, "/", Arc::new(move |executor, req| Box::pin(get_index(executor, req)))), // TODO: get rid of this non-async wrapper?
-    ]);
+    router.add_handler(Method::GET, "/", get_index);
+    router.add_middleware(Arc::new(AccessLog::new(AccessLogFormat::Combined)));
     let router = Arc::new(router);
 
     // run server
-    HttpServer::run_server(executor, host, port, router, None).await
+    HttpServer::builder()
+        .bind(format!("{host}:{port}"))
+        .serve(router)
+        .await?;
+    Ok(())
 }
 
 fn main() -> SimpleResult<()> {
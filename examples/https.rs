@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
-use http::{Method, Request, Response, StatusCode, Version};
-use http_server::{Router, HttpServer};
+use http::Method;
+use http_server::{HttpServer, IntoResponse, Router, Text};
 use async_executor::Executor;
 use rcgen::{Certificate, CertificateParams, DnType, PKCS_ECDSA_P256_SHA256, SanType};
 use simple_error::SimpleResult;
@@ -22,12 +22,8 @@ pub fn generate_cert_and_key() -> SimpleResult<(String, String)> {
     Ok((cert.serialize_pem()?, cert.serialize_private_key_pem()))
 }
 
-async fn get_index(_executor: Arc<Executor<'static>>, _request: Request<Vec<u8>>) -> SimpleResult<Response<String>> {
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .version(Version::HTTP_11)
-        .header("Content-Type", "text/plain")
-        .body("Hello, World!".to_string())?)
+async fn get_index() -> impl IntoResponse {
+    Text("Hello, World!")
 }
 
 async fn async_main(executor: Arc<Executor<'static>>) -> SimpleResult<()> {
@@ -37,26 +33,25 @@ async fn async_main(executor: Arc<Executor<'static>>) -> SimpleResult<()> {
     // Server settings
     let host = "0.0.0.0";
     let port = 8443;  // Standard HTTPS port
-    
+
     // TLS configuration
     let (cert_pem, key_pem) = generate_cert_and_key()?;
-    let tls_config = Some((
-        cert_pem,
-        key_pem
-    ));
 
     // Build router
-    let mut router = Router::new(executor.clone());
-    router.add_routes(vec![
-        (Method::GET, "/", Arc::new(move |executor, req| Box::pin(get_index(executor, req)))),
-    ]);
+    let router = Router::new(executor.clone());
+    router.add_handler(Method::GET, "/", get_index);
     let router = Arc::new(router);
 
     // Run HTTPS server
     println!("HTTPS server listening on https://{}:{}", host, port);
-    HttpServer::run_server(executor, host, port, router, tls_config).await
+    HttpServer::builder()
+        .bind(format!("{host}:{port}"))
+        .tls(cert_pem, key_pem)
+        .serve(router)
+        .await?;
+    Ok(())
 }
 
 fn main() -> SimpleResult<()> {
     Arc::<Executor>::with_main(|ex| smol::block_on(async_main(ex.clone())))
-}
\ No newline at end of file
+}
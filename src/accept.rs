@@ -0,0 +1,81 @@
+use http::header::{HeaderName, ACCEPT};
+use http::{HeaderMap, Request};
+
+use crate::error::{Error, Result};
+
+/// The entries of a comma separated header list with optional `;q=` weights, such as `Accept`
+/// or `Accept-Encoding`, lowercased and with weights defaulting to 1
+pub(crate) fn quality_list(headers: &HeaderMap, name: HeaderName) -> Vec<(String, f32)> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|entry| {
+            let mut params = entry.split(';');
+            let value = params.next()?.trim().to_ascii_lowercase();
+            if value.is_empty() {
+                return None;
+            }
+            let quality = params
+                .filter_map(|param| param.trim().split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .and_then(|(_, quality)| quality.trim().parse::<f32>().ok())
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+            Some((value, quality))
+        })
+        .collect()
+}
+
+/// How much the client wants a content coding per `Accept-Encoding`; 0 means not at all
+pub(crate) fn encoding_quality(accepted: &[(String, f32)], coding: &str) -> f32 {
+    accepted
+        .iter()
+        .find(|(value, _)| value == coding)
+        .or_else(|| accepted.iter().find(|(value, _)| value == "*"))
+        .map(|(_, quality)| *quality)
+        .unwrap_or(0.0)
+}
+
+/// How much the client wants `media_type` per its `Accept` ranges, taking the weight of the
+/// most specific matching range
+fn media_quality(accepted: &[(String, f32)], media_type: &str) -> f32 {
+    let essence = media_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    let main_type = essence.split('/').next().unwrap_or("");
+    let mut best: Option<(u8, f32)> = None;
+    for (range, quality) in accepted {
+        let specificity = if *range == essence {
+            2
+        } else if range.strip_suffix("/*") == Some(main_type) {
+            1
+        } else if range == "*/*" {
+            0
+        } else {
+            continue;
+        };
+        if best.is_none_or(|(best_specificity, _)| specificity > best_specificity) {
+            best = Some((specificity, *quality));
+        }
+    }
+    best.map(|(_, quality)| quality).unwrap_or(0.0)
+}
+
+/// Picks the representation of `offered` the request's `Accept` header prefers, by q-value
+/// and then by the order of `offered`. Without an `Accept` header the first is chosen; when
+/// nothing is acceptable the result is [`Error::NotAcceptable`], which handlers answer with a 406
+pub fn negotiate<'a, B>(request: &Request<B>, offered: &[&'a str]) -> Result<&'a str> {
+    let accepted = quality_list(request.headers(), ACCEPT);
+    if accepted.is_empty() {
+        return offered.first().copied().ok_or(Error::NotAcceptable);
+    }
+
+    let mut best: Option<(&'a str, f32)> = None;
+    for &media_type in offered {
+        let quality = media_quality(&accepted, media_type);
+        if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((media_type, quality));
+        }
+    }
+    best.map(|(media_type, _)| media_type).ok_or(Error::NotAcceptable)
+}
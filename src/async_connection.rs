@@ -1,6 +1,9 @@
 use futures_lite::io::{AsyncRead, AsyncWrite};
 
+/// A bidirectional byte stream the server can speak HTTP over. Implemented for every
+/// `AsyncRead + AsyncWrite + Send + Unpin` type, so TCP and Unix sockets, TLS streams, in-memory
+/// pipes and tunnels all qualify; hand one to [`HttpServer::serve_connection`](crate::HttpServer::serve_connection)
+/// to serve a transport the server doesn't listen on itself
 pub trait AsyncConnection: AsyncRead + AsyncWrite + Send + Unpin {}
 
-impl AsyncConnection for async_io::Async<std::net::TcpStream> {}
-impl AsyncConnection for async_tls::server::TlsStream<async_io::Async<std::net::TcpStream>> {}
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> AsyncConnection for S {}
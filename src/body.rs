@@ -0,0 +1,263 @@
+use std::fmt;
+use std::fs::File;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use blocking::Unblock;
+use futures_lite::io::{AsyncRead, AsyncReadExt};
+use futures_lite::stream::{self, Stream, StreamExt};
+use http::header::TRAILER;
+use http::HeaderMap;
+
+use crate::types::BoxFuture;
+
+pub type BodyStream = Pin<Box<dyn Stream<Item = io::Result<Vec<u8>>> + Send + 'static>>;
+
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Large reads keep the number of chunks, and so of socket writes, low for big files
+pub(crate) const FILE_CHUNK_SIZE: usize = 256 * 1024;
+
+/// A response body: either fully buffered, or a stream of chunks written out as they arrive
+#[derive(Default)]
+pub struct Body {
+    pub(crate) inner: BodyInner,
+    /// Trailer fields sent after the body, resolved once it has been written
+    pub(crate) trailers: Option<BoxFuture<'static, HeaderMap>>,
+}
+
+/// Trailer fields that followed a chunked request body, or an HTTP/2 or HTTP/3 one, in the
+/// request's extensions
+#[derive(Clone, Debug, Default)]
+pub struct Trailers(pub HeaderMap);
+
+pub(crate) enum BodyInner {
+    Full(Vec<u8>),
+    /// Contents shared with a cache, written without being copied
+    Shared(Arc<[u8]>),
+    /// Shared, reference counted bytes that are written without being copied
+    #[cfg(feature = "bytes")]
+    Bytes(bytes::Bytes),
+    Stream {
+        stream: BodyStream,
+        length: Option<u64>,
+    },
+    /// The first `length` bytes of a file, sent with `sendfile(2)` where the connection allows
+    /// and streamed otherwise
+    File {
+        file: File,
+        length: u64,
+    },
+}
+
+impl Default for BodyInner {
+    fn default() -> Self {
+        BodyInner::Full(Vec::new())
+    }
+}
+
+impl Body {
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// A streamed body; without a known `length` it is sent with chunked transfer coding
+    pub fn from_stream(stream: impl Stream<Item = io::Result<Vec<u8>>> + Send + 'static, length: Option<u64>) -> Self {
+        Self {
+            inner: BodyInner::Stream {
+                stream: Box::pin(stream),
+                length,
+            },
+            trailers: None,
+        }
+    }
+
+    /// Streams everything `reader` produces until EOF
+    pub fn from_reader(reader: impl AsyncRead + Send + Unpin + 'static, length: Option<u64>) -> Self {
+        Self::from_reader_with_chunk_size(reader, length, READ_CHUNK_SIZE)
+    }
+
+    /// Like [`Body::from_reader`], reading up to `chunk_size` bytes at a time
+    pub fn from_reader_with_chunk_size(reader: impl AsyncRead + Send + Unpin + 'static, length: Option<u64>, chunk_size: usize) -> Self {
+        let stream = stream::unfold(Some(reader), move |reader| async move {
+            let mut reader = reader?;
+            let mut chunk = vec![0u8; chunk_size];
+            match reader.read(&mut chunk).await {
+                Ok(0) => None,
+                Ok(read) => {
+                    chunk.truncate(read);
+                    Some((Ok(chunk), Some(reader)))
+                }
+                // end the stream after reporting the error
+                Err(err) => Some((Err(err), None)),
+            }
+        });
+        Self::from_stream(stream, length)
+    }
+
+    /// The first `length` bytes of `file`
+    pub(crate) fn from_file(file: File, length: u64) -> Self {
+        Self {
+            inner: BodyInner::File { file, length },
+            trailers: None,
+        }
+    }
+
+    /// Sends the fields `trailers` resolves to after the body, e.g. a checksum computed while
+    /// streaming it. Only fields named in the response's `Trailer` header are sent, and HTTP/1.1
+    /// responses switch to chunked transfer coding to carry them
+    pub fn with_trailers(mut self, trailers: impl Future<Output = HeaderMap> + Send + 'static) -> Self {
+        self.trailers = Some(Box::pin(trailers));
+        self
+    }
+
+    pub fn has_trailers(&self) -> bool {
+        self.trailers.is_some()
+    }
+
+    /// Exact size in bytes, when known up front
+    pub fn content_length(&self) -> Option<u64> {
+        match &self.inner {
+            BodyInner::Full(bytes) => Some(bytes.len() as u64),
+            BodyInner::Shared(bytes) => Some(bytes.len() as u64),
+            #[cfg(feature = "bytes")]
+            BodyInner::Bytes(bytes) => Some(bytes.len() as u64),
+            BodyInner::Stream { length, .. } => *length,
+            BodyInner::File { length, .. } => Some(*length),
+        }
+    }
+
+    /// The contents of a buffered body; `None` for streams
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match &self.inner {
+            BodyInner::Full(bytes) => Some(bytes),
+            BodyInner::Shared(bytes) => Some(bytes),
+            #[cfg(feature = "bytes")]
+            BodyInner::Bytes(bytes) => Some(bytes),
+            BodyInner::Stream { .. } | BodyInner::File { .. } => None,
+        }
+    }
+
+    pub fn into_stream(self) -> BodyStream {
+        match self.inner {
+            BodyInner::Full(bytes) => Box::pin(stream::once(Ok(bytes))),
+            BodyInner::Shared(bytes) => Box::pin(stream::once(Ok(bytes.to_vec()))),
+            #[cfg(feature = "bytes")]
+            BodyInner::Bytes(bytes) => Box::pin(stream::once(Ok(bytes.to_vec()))),
+            BodyInner::Stream { stream, .. } => stream,
+            BodyInner::File { file, length } => {
+                let reader = Unblock::with_capacity(FILE_CHUNK_SIZE, file).take(length);
+                Self::from_reader_with_chunk_size(reader, Some(length), FILE_CHUNK_SIZE).into_stream()
+            }
+        }
+    }
+
+    /// Buffers the whole body, draining a stream if necessary
+    pub async fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self.inner {
+            BodyInner::Full(bytes) => Ok(bytes),
+            BodyInner::Shared(bytes) => Ok(bytes.to_vec()),
+            #[cfg(feature = "bytes")]
+            BodyInner::Bytes(bytes) => Ok(bytes.into()),
+            inner => {
+                let mut stream = Self { inner, trailers: None }.into_stream();
+                let mut bytes = Vec::new();
+                while let Some(chunk) = stream.next().await {
+                    bytes.extend_from_slice(&chunk?);
+                }
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self {
+            inner: BodyInner::Full(bytes),
+            trailers: None,
+        }
+    }
+}
+
+/// Cloning an `Arc` only bumps a reference count, so contents cached in memory are sent without
+/// being copied for every response
+impl From<Arc<[u8]>> for Body {
+    fn from(bytes: Arc<[u8]>) -> Self {
+        Self {
+            inner: BodyInner::Shared(bytes),
+            trailers: None,
+        }
+    }
+}
+
+/// Cloning a `Bytes` only bumps a reference count, so one large payload can back any number of
+/// responses. Only response bodies are shared this way: request bodies reach handlers as the
+/// one `Vec<u8>` they were read into
+#[cfg(feature = "bytes")]
+impl From<bytes::Bytes> for Body {
+    fn from(bytes: bytes::Bytes) -> Self {
+        Self {
+            inner: BodyInner::Bytes(bytes),
+            trailers: None,
+        }
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Self::from(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Self {
+        Self::from(text.as_bytes().to_vec())
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Self {
+        Self::from(bytes.to_vec())
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.inner {
+            BodyInner::Full(bytes) => write!(f, "Body({} bytes)", bytes.len()),
+            BodyInner::Shared(bytes) => write!(f, "Body({} shared bytes)", bytes.len()),
+            #[cfg(feature = "bytes")]
+            BodyInner::Bytes(bytes) => write!(f, "Body({} shared bytes)", bytes.len()),
+            BodyInner::Stream { length: Some(length), .. } => write!(f, "Body(stream of {length} bytes)"),
+            BodyInner::Stream { length: None, .. } => write!(f, "Body(stream)"),
+            BodyInner::File { length, .. } => write!(f, "Body(file of {length} bytes)"),
+        }
+    }
+}
+
+/// The fields of `trailers` named in the response's `Trailer` header, the only ones sent
+pub(crate) fn declared_trailers(response_headers: &HeaderMap, trailers: HeaderMap) -> HeaderMap {
+    let declared: Vec<String> = response_headers
+        .get_all(TRAILER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    let mut kept = HeaderMap::new();
+    let mut current = None;
+    for (name, value) in trailers {
+        if let Some(name) = name {
+            current = Some(name);
+        }
+        // a line break would end the trailer section early, so such values are dropped
+        let safe = !value.as_bytes().iter().any(|byte| matches!(byte, b'\r' | b'\n' | b'\0'));
+        if let Some(name) = current.as_ref().filter(|name| safe && declared.iter().any(|declared| declared == name.as_str())) {
+            kept.append(name.clone(), value);
+        }
+    }
+    kept
+}
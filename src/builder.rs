@@ -0,0 +1,532 @@
+use std::future::Future;
+use std::io;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_io::Async;
+use futures_lite::future;
+use rustls::ServerConfig;
+
+use crate::default_headers::DefaultHeaders;
+use crate::error::Result;
+use crate::forwarded::TrustedProxies;
+use crate::keep_alive::KeepAlive;
+use crate::limits::Limits;
+use crate::listener::Listener;
+use crate::router::Router;
+use crate::middleware::ip_filter::IpFilter;
+use crate::shutdown::ShutdownHandle;
+use crate::health::HealthRegistry;
+use crate::hooks::ServerHooks;
+use crate::socket::SocketOptions;
+use crate::server::HttpServer;
+use crate::timeout::Timeouts;
+use crate::tls::{client_verifier, load_certs, load_private_key, server_config, CertSource, ClientVerifier, SniResolver, TlsHandle};
+use crate::types::BoxFuture;
+
+enum Bind {
+    Tcp {
+        addr: String,
+        tls: Option<(String, String)>,
+    },
+    Listener(TcpListener),
+    #[cfg(feature = "tokio")]
+    Tokio(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+    #[cfg(feature = "http3")]
+    Quic(String),
+}
+
+/// Collects server configuration; finish with [`HttpServerBuilder::serve`]
+#[derive(Default)]
+pub struct HttpServerBuilder {
+    binds: Vec<Bind>,
+    tls: Option<(String, String)>,
+    sni_certs: Vec<(String, String, String)>,
+    tls_files: Option<(PathBuf, PathBuf, Option<Duration>)>,
+    client_auth: Option<(String, bool)>,
+    timeouts: Timeouts,
+    limits: Limits,
+    keep_alive: KeepAlive,
+    trusted_proxies: Option<TrustedProxies>,
+    proxy_protocol: bool,
+    socket_options: SocketOptions,
+    ip_filter: Option<IpFilter>,
+    hooks: Option<Arc<dyn ServerHooks>>,
+    /// Accept ambiguous framing instead of refusing it
+    lenient_parsing: bool,
+    server_timing: bool,
+    cancel_on_eof: bool,
+    /// Accept loops per TCP bind; 0 and 1 both mean a single loop on the serving task
+    accept_loops: usize,
+    #[cfg(feature = "http2")]
+    h2c: bool,
+    /// `Alt-Svc` advertising the HTTP/3 endpoint on the TCP listeners
+    #[cfg(feature = "http3")]
+    alt_svc: Option<String>,
+    default_headers: DefaultHeaders,
+    shutdown: Option<BoxFuture<'static, ()>>,
+    shutdown_handle: ShutdownHandle,
+    health: Option<HealthRegistry>,
+    grace_period: Option<Duration>,
+}
+
+/// How long draining connections get to finish unless [`HttpServerBuilder::grace_period`] says otherwise
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+impl HttpServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Address to listen on, e.g. `"0.0.0.0:8080"`; may be called repeatedly to listen on several
+    pub fn bind(mut self, addr: impl Into<String>) -> Self {
+        self.binds.push(Bind::Tcp { addr: addr.into(), tls: None });
+        self
+    }
+
+    /// Address to listen on with its own certificate, e.g. `"0.0.0.0:443"` next to a plain `"0.0.0.0:80"`
+    pub fn bind_tls(mut self, addr: impl Into<String>, cert_pem: impl Into<String>, key_pem: impl Into<String>) -> Self {
+        self.binds.push(Bind::Tcp {
+            addr: addr.into(),
+            tls: Some((cert_pem.into(), key_pem.into())),
+        });
+        self
+    }
+
+    /// Serve on an already bound listener, e.g. one bound to port 0 or inherited via socket activation
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.binds.push(Bind::Listener(listener));
+        self
+    }
+
+    /// Serve on a listener bound through tokio, so a tokio application drives the server
+    /// without a second reactor
+    #[cfg(feature = "tokio")]
+    pub fn tokio_listener(mut self, listener: tokio::net::TcpListener) -> Self {
+        self.binds.push(Bind::Tokio(listener));
+        self
+    }
+
+    /// Listen on a Unix domain socket
+    #[cfg(unix)]
+    pub fn bind_unix(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.binds.push(Bind::Unix(path.into()));
+        self
+    }
+
+    /// Experimental: also serve HTTP/3 over QUIC on this UDP address, e.g. `"0.0.0.0:443"`, using
+    /// the certificate given to [`HttpServerBuilder::tls`]. TCP responses advertise it through
+    /// `Alt-Svc` so browsers switch over on later requests
+    #[cfg(feature = "http3")]
+    pub fn http3(mut self, addr: impl Into<String>) -> Self {
+        let addr = addr.into();
+        if let Some((_, port)) = addr.rsplit_once(':') {
+            self.alt_svc = Some(format!("h3=\":{port}\"; ma=86400"));
+        }
+        self.binds.push(Bind::Quic(addr));
+        self
+    }
+
+    /// Serve HTTPS on every bind that doesn't carry its own certificate, using the given
+    /// PEM encoded certificate chain and PKCS#8 key. With the `native-tls` feature these
+    /// handshakes use the platform's TLS stack unless SNI certificates or client
+    /// authentication are configured too
+    pub fn tls(mut self, cert_pem: impl Into<String>, key_pem: impl Into<String>) -> Self {
+        self.tls = Some((cert_pem.into(), key_pem.into()));
+        self
+    }
+
+    /// Like [`HttpServerBuilder::tls`], but reads the PEM files at startup and, given a
+    /// `poll_interval`, reloads them whenever they change on disk
+    pub fn tls_files(mut self, cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>, poll_interval: Option<Duration>) -> Self {
+        self.tls_files = Some((cert_path.into(), key_path.into(), poll_interval));
+        self
+    }
+
+    /// Ask TLS clients for a certificate signed by one of the PEM encoded CAs in `ca_pem`; with
+    /// `required` set, clients without one are refused. Verified chains reach handlers as
+    /// [`PeerCertificates`](crate::PeerCertificates) in the request extensions
+    pub fn client_auth(mut self, ca_pem: impl Into<String>, required: bool) -> Self {
+        self.client_auth = Some((ca_pem.into(), required));
+        self
+    }
+
+    /// Serve HTTPS with a certificate chosen by the SNI hostname the client requests; `hostname`
+    /// may be a wildcard like `"*.example.com"`. Clients without a matching name get the
+    /// certificate given to [`HttpServerBuilder::tls`], if any
+    pub fn add_tls_cert(mut self, hostname: impl Into<String>, cert_pem: impl Into<String>, key_pem: impl Into<String>) -> Self {
+        self.sni_certs.push((hostname.into(), cert_pem.into(), key_pem.into()));
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Reverse proxies whose forwarding header is believed when resolving the client address,
+    /// e.g. `TrustedProxies::x_forwarded_for(["10.0.0.0/8".parse()?])`
+    pub fn trusted_proxies(mut self, trusted_proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = Some(trusted_proxies);
+        self
+    }
+
+    /// Expect a PROXY protocol v1/v2 header at the start of every connection
+    pub fn proxy_protocol(mut self, proxy_protocol: bool) -> Self {
+        self.proxy_protocol = proxy_protocol;
+        self
+    }
+
+    /// Drop connections from peers `ip_filter` refuses before reading anything from them; keep
+    /// a clone to ban addresses while the server runs
+    pub fn ip_filter(mut self, ip_filter: IpFilter) -> Self {
+        self.ip_filter = Some(ip_filter);
+        self
+    }
+
+    /// Refuse requests with obsolete line folding or with both Transfer-Encoding and
+    /// Content-Length, true by default; see [`HttpServer::with_strict_parsing`]
+    pub fn strict_parsing(mut self, strict: bool) -> Self {
+        self.lenient_parsing = !strict;
+        self
+    }
+
+    /// Report the handler's duration in a `Server-Timing` header; see [`HttpServer::with_server_timing`]
+    pub fn server_timing(mut self, server_timing: bool) -> Self {
+        self.server_timing = server_timing;
+        self
+    }
+
+    /// Cancel requests whose client closes its side of the connection; see
+    /// [`HttpServer::with_cancel_on_eof`]
+    pub fn cancel_on_eof(mut self, cancel_on_eof: bool) -> Self {
+        self.cancel_on_eof = cancel_on_eof;
+        self
+    }
+
+    /// Report connections, requests and responses to `hooks`
+    pub fn hooks(mut self, hooks: impl ServerHooks) -> Self {
+        self.hooks = Some(Arc::new(hooks));
+        self
+    }
+
+    /// TCP_NODELAY, address/port reuse, keepalive and backlog for bound listeners and accepted
+    /// connections
+    pub fn socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = socket_options;
+        self
+    }
+
+    /// Runs `accept_loops` accept loops per TCP bind, each as its own task on the router's
+    /// spawner, so accepting scales across a multi-threaded executor instead of funnelling
+    /// through one task. With [`SocketOptions::reuse_port`] on Unix every loop binds its own
+    /// listener and the kernel balances connections between them; otherwise the loops share
+    /// one listener. Typically the number of cores
+    pub fn accept_loops(mut self, accept_loops: usize) -> Self {
+        self.accept_loops = accept_loops;
+        self
+    }
+
+    /// Serve HTTP/2 over plaintext listeners to clients that start with the HTTP/2 preface or
+    /// ask for it with `Upgrade: h2c`
+    #[cfg(feature = "http2")]
+    pub fn h2c(mut self, h2c: bool) -> Self {
+        self.h2c = h2c;
+        self
+    }
+
+    /// Headers added to every response that doesn't set them; `DefaultHeaders::none()` opts out
+    /// of the automatic `Date` and `Server` headers
+    pub fn default_headers(mut self, default_headers: DefaultHeaders) -> Self {
+        self.default_headers = default_headers;
+        self
+    }
+
+    /// Start draining once `signal` resolves: stop accepting, close keep-alive connections after
+    /// their in-flight response and drop whatever remains after the grace period
+    pub fn shutdown_signal(mut self, signal: impl Future<Output = ()> + Send + 'static) -> Self {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+
+    /// Drain on SIGINT or SIGTERM
+    #[cfg(feature = "signals")]
+    pub fn shutdown_on_signals(self) -> Self {
+        self.shutdown_signal(crate::shutdown::termination_signal())
+    }
+
+    /// Drain when `shutdown_handle` is triggered, e.g. from an admin endpoint
+    pub fn shutdown_handle(mut self, shutdown_handle: ShutdownHandle) -> Self {
+        self.shutdown_handle = shutdown_handle;
+        self
+    }
+
+    /// Serve `health`'s endpoints on the router, reporting not ready once shutdown begins
+    pub fn health(mut self, health: HealthRegistry) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// How long draining connections may take before they are dropped, 30 seconds by default
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = Some(grace_period);
+        self
+    }
+
+    pub fn build(&self) -> Result<HttpServer> {
+        self.build_with_tls(self.tls.as_ref())
+    }
+
+    /// The rustls config for `tls` plus any SNI certificates and client auth, if there is a
+    /// certificate to serve at all
+    fn tls_config(&self, tls: Option<&(String, String)>) -> Result<Option<(ServerConfig, Option<ClientVerifier>)>> {
+        let client_verifier = match &self.client_auth {
+            Some((ca_pem, required)) => Some(client_verifier(ca_pem, *required)?),
+            None => None,
+        };
+
+        let certs = match tls {
+            _ if !self.sni_certs.is_empty() => {
+                let mut resolver = SniResolver::default();
+                for (hostname, cert_pem, key_pem) in &self.sni_certs {
+                    resolver.add(hostname, cert_pem, key_pem)?;
+                }
+                if let Some((cert_pem, key_pem)) = tls {
+                    resolver.set_default(cert_pem, key_pem)?;
+                }
+                Some(CertSource::Resolver(Arc::new(resolver)))
+            }
+            Some((cert_pem, key_pem)) => Some(CertSource::Single(load_certs(cert_pem)?, load_private_key(key_pem)?)),
+            None => None,
+        };
+
+        match certs {
+            Some(certs) => Ok(Some((server_config(client_verifier.clone(), certs)?, client_verifier))),
+            None => Ok(None),
+        }
+    }
+
+    fn build_with_tls(&self, tls: Option<&(String, String)>) -> Result<HttpServer> {
+        // SNI certificates and client authentication are only implemented over rustls
+        #[cfg(feature = "native-tls")]
+        let native = match tls {
+            Some((cert_pem, key_pem)) if self.sni_certs.is_empty() && self.client_auth.is_none() => Some(TlsHandle::native(cert_pem, key_pem)?),
+            _ => None,
+        };
+        #[cfg(not(feature = "native-tls"))]
+        let native: Option<TlsHandle> = None;
+
+        let server = match native {
+            Some(tls) => HttpServer::with_tls_handle(tls),
+            None => match self.tls_config(tls)? {
+                Some((config, client_verifier)) => HttpServer::with_tls_handle(TlsHandle::new(config, client_verifier)),
+                None => HttpServer::new(),
+            },
+        };
+
+        let default_headers = self.default_headers.clone();
+        #[cfg(feature = "http3")]
+        let default_headers = DefaultHeaders {
+            alt_svc: default_headers.alt_svc.or_else(|| self.alt_svc.clone()),
+            ..default_headers
+        };
+
+        let server = server
+            .with_timeouts(self.timeouts)
+            .with_limits(self.limits)
+            .with_keep_alive(self.keep_alive)
+            .with_proxy_protocol(self.proxy_protocol)
+            .with_socket_options(self.socket_options)
+            .with_strict_parsing(!self.lenient_parsing)
+            .with_server_timing(self.server_timing)
+            .with_cancel_on_eof(self.cancel_on_eof)
+            .with_default_headers(default_headers)
+            .with_shutdown_handle(self.shutdown_handle.clone());
+        let server = match self.trusted_proxies.clone() {
+            Some(trusted_proxies) => server.with_trusted_proxies(trusted_proxies),
+            None => server,
+        };
+        let server = match self.ip_filter.clone() {
+            Some(ip_filter) => server.with_ip_filter(ip_filter),
+            None => server,
+        };
+        let server = match self.hooks.clone() {
+            Some(hooks) => server.with_hooks(hooks),
+            None => server,
+        };
+        #[cfg(feature = "http2")]
+        let server = server.with_h2c(self.h2c);
+
+        Ok(server)
+    }
+
+    fn accept_loop<L: Listener + 'static>(
+        server: Arc<HttpServer>,
+        listener: L,
+        router: Arc<Router>,
+    ) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async move { server.accept_loop(listener, router).await })
+    }
+
+    /// Registers `listener` with tokio's reactor when serving from inside a tokio runtime,
+    /// otherwise with async-io's
+    fn tcp_accept_loop(
+        server: Arc<HttpServer>,
+        listener: TcpListener,
+        router: Arc<Router>,
+    ) -> Result<BoxFuture<'static, Result<()>>> {
+        #[cfg(feature = "tokio")]
+        if crate::runtime::in_tokio() {
+            listener.set_nonblocking(true)?;
+            return Ok(Self::accept_loop(server, tokio::net::TcpListener::from_std(listener)?, router));
+        }
+        Ok(Self::accept_loop(server, Async::new(listener)?, router))
+    }
+
+    /// Runs `accept_loop` as its own task on the router's spawner; dropping the returned future
+    /// stops the loop, as dropping the loop itself would
+    fn spawn_accept_loop(router: &Router, accept_loop: BoxFuture<'static, Result<()>>) -> BoxFuture<'static, Result<()>> {
+        let (result_sender, result_receiver) = async_channel::bounded(1);
+        let (cancel_sender, cancel_receiver) = async_channel::bounded::<()>(1);
+        router.spawner().spawn(Box::pin(async move {
+            let cancelled = async {
+                // only ever resolves once the sender is dropped
+                let _ = cancel_receiver.recv().await;
+                Ok(())
+            };
+            let _ = result_sender.send(future::or(accept_loop, cancelled).await).await;
+        }));
+        Box::pin(async move {
+            let _cancel_sender = cancel_sender;
+            result_receiver.recv().await.unwrap_or(Ok(()))
+        })
+    }
+
+    /// The listeners for one TCP bind: a single one, or one per accept loop
+    fn tcp_listeners(&self, addr: Option<&str>, listener: Option<TcpListener>) -> Result<Vec<TcpListener>> {
+        let count = self.accept_loops.max(1);
+        let reuse_port = cfg!(unix) && self.socket_options.reuse_port;
+        let first = match (listener, addr) {
+            (Some(listener), _) => listener,
+            (None, Some(addr)) => HttpServer::bind(addr, &self.socket_options)?,
+            (None, None) => unreachable!("a TCP bind has an address or a listener"),
+        };
+
+        let mut listeners = Vec::with_capacity(count);
+        for _ in 1..count {
+            listeners.push(match addr {
+                Some(addr) if reuse_port => HttpServer::bind(addr, &self.socket_options)?,
+                // clones share the one socket and its accept queue
+                _ => first.try_clone()?,
+            });
+        }
+        listeners.insert(0, first);
+        Ok(listeners)
+    }
+
+    /// The accept loop for a bind that isn't a plain TCP listener
+    fn other_accept_loop(&self, bind: Bind, default_server: &Arc<HttpServer>, router: &Arc<Router>) -> Result<BoxFuture<'static, Result<()>>> {
+        Ok(match bind {
+            Bind::Tcp { .. } | Bind::Listener(_) => unreachable!("TCP binds are handled by the caller"),
+            #[cfg(feature = "tokio")]
+            Bind::Tokio(listener) => {
+                Self::accept_loop(default_server.clone(), listener, router.clone())
+            }
+            #[cfg(unix)]
+            Bind::Unix(path) => {
+                Self::accept_loop(default_server.clone(), HttpServer::bind_unix(&path)?, router.clone())
+            }
+            #[cfg(feature = "http3")]
+            Bind::Quic(addr) => {
+                let Some((tls_config, _)) = self.tls_config(self.tls.as_ref())? else {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "HTTP/3 needs a TLS certificate").into());
+                };
+                let server = default_server.clone();
+                let router = router.clone();
+                Box::pin(async move { server.serve_quic(&addr, tls_config, router).await })
+            }
+        })
+    }
+
+    pub async fn serve(mut self, router: Arc<Router>) -> Result<()> {
+        let binds = std::mem::take(&mut self.binds);
+        if binds.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "No bind address or listener configured").into());
+        }
+
+        if let Some((cert_path, key_path, _)) = &self.tls_files {
+            self.tls = Some((std::fs::read_to_string(cert_path)?, std::fs::read_to_string(key_path)?));
+        }
+
+        if let Some(health) = &self.health {
+            health.watch_shutdown(&self.shutdown_handle);
+            router.add_health_routes(health);
+        }
+
+        // one accept loop per bind, all feeding the same router
+        let default_server = Arc::new(self.build()?);
+
+        let mut accept_loops = Vec::with_capacity(binds.len() + 1);
+
+        // the watcher never finishes, so it runs for as long as the server does
+        if let Some((cert_path, key_path, Some(poll_interval))) = &self.tls_files {
+            let tls_watcher = default_server.tls_handle().watch_files(cert_path.clone(), key_path.clone(), *poll_interval);
+            accept_loops.push(Box::pin(async move {
+                tls_watcher.await;
+                Ok(())
+            }) as BoxFuture<'static, Result<()>>);
+        }
+        for bind in binds {
+            let (server, listeners) = match bind {
+                Bind::Tcp { addr, tls: None } => (default_server.clone(), self.tcp_listeners(Some(&addr), None)?),
+                Bind::Tcp { addr, tls: Some(tls) } => (Arc::new(self.build_with_tls(Some(&tls))?), self.tcp_listeners(Some(&addr), None)?),
+                Bind::Listener(listener) => (default_server.clone(), self.tcp_listeners(None, Some(listener))?),
+                other => {
+                    accept_loops.push(self.other_accept_loop(other, &default_server, &router)?);
+                    continue;
+                }
+            };
+            for listener in listeners {
+                let accept_loop = Self::tcp_accept_loop(server.clone(), listener, router.clone())?;
+                accept_loops.push(if self.accept_loops > 1 {
+                    Self::spawn_accept_loop(&router, accept_loop)
+                } else {
+                    accept_loop
+                });
+            }
+        }
+
+        // accept loops finish on error or once draining starts; the first one to do so ends the server
+        let first_exit = accept_loops.into_iter().fold(
+            Box::pin(future::pending()) as BoxFuture<'static, Result<()>>,
+            |first_exit, accept_loop| Box::pin(future::or(first_exit, accept_loop)),
+        );
+
+        // dropping the remaining futures cancels their accept loops
+        let shutdown = self.shutdown.take().unwrap_or_else(|| Box::pin(future::pending()));
+        future::or(first_exit, async move {
+            shutdown.await;
+            log::info!("shutdown signal received, no longer accepting connections");
+            Ok(())
+        })
+        .await?;
+
+        self.shutdown_handle.shutdown(Some(self.grace_period.unwrap_or(DEFAULT_GRACE_PERIOD))).await;
+        log::info!("all connections drained");
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+/// In the extensions of every HTTP/1 and HTTP/2 request, fired when the client goes away
+/// before the response is written: the connection fails, or is closed with
+/// [`HttpServer::with_cancel_on_eof`](crate::HttpServer::with_cancel_on_eof), or the HTTP/2
+/// stream is reset.
+/// The handler's future is dropped at that point anyway, so this is for work it handed off,
+/// e.g. to [`spawn_blocking`](crate::spawn_blocking) or another task, to stop early
+#[derive(Clone)]
+pub struct Cancellation {
+    // nothing is ever sent: closing the channel is the signal, and holding a sender keeps it
+    // open until then
+    sender: async_channel::Sender<()>,
+    receiver: async_channel::Receiver<()>,
+}
+
+impl Cancellation {
+    pub(crate) fn cancel(&self) {
+        self.sender.close();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.receiver.is_closed()
+    }
+
+    /// Resolves once the request is cancelled, e.g. to race against a slow query
+    pub async fn cancelled(&self) {
+        let _ = self.receiver.recv().await;
+    }
+}
+
+/// A token that is never cancelled, for requests the server can't watch
+impl Default for Cancellation {
+    fn default() -> Self {
+        let (sender, receiver) = async_channel::bounded(1);
+        Self { sender, receiver }
+    }
+}
@@ -0,0 +1,252 @@
+use std::io;
+
+use futures_lite::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use futures_lite::stream;
+use http::header::{HeaderName, HeaderValue};
+use http::HeaderMap;
+
+use crate::body::BodyStream;
+use crate::error::{Error, Result};
+use crate::vectored::write_all_vectored;
+
+const MAX_PIECE_SIZE: usize = 16 * 1024;
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_TRAILERS: usize = 100;
+/// Hex digits in a chunk size; 16 already spans a `u64`
+const MAX_SIZE_DIGITS: usize = 16;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Incremental state for decoding a chunked message body
+struct Decoder<R> {
+    reader: R,
+    /// Bytes left in the chunk being read
+    remaining: usize,
+    /// Whether a chunk has been read, meaning a CRLF precedes the next size line
+    started: bool,
+    /// Fields from the trailer section after the last chunk
+    trailers: HeaderMap,
+}
+
+/// Parses a chunk-size line, `1*HEXDIG [ ";" chunk-ext ]`. Anything else, like a sign or
+/// whitespace around the size, is refused rather than guessed at, since a proxy in front may
+/// have read it differently
+fn parse_chunk_size(line: &str) -> io::Result<usize> {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    let digits = line.bytes().take_while(u8::is_ascii_hexdigit).count();
+    let (size, extensions) = line.split_at(digits);
+    if digits == 0 || digits > MAX_SIZE_DIGITS || !(extensions.is_empty() || extensions.starts_with(';')) {
+        return Err(invalid_data("invalid chunk size"));
+    }
+    usize::from_str_radix(size, 16).map_err(|_| invalid_data("chunk size too large"))
+}
+
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    let read = (&mut *reader).take(MAX_LINE_LENGTH).read_line(&mut line).await?;
+    if read == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if !line.ends_with('\n') {
+        return Err(invalid_data("chunked line too long"));
+    }
+    Ok(line)
+}
+
+impl<R: AsyncBufRead + Unpin> Decoder<R> {
+    /// Reads up to the next piece of body data and returns its length, or `None` once the last
+    /// chunk and the trailers are read. Large chunks are split so nothing is allocated based on
+    /// the size a peer claims
+    async fn next_piece_len(&mut self) -> io::Result<Option<usize>> {
+        if self.remaining == 0 {
+            if self.started {
+                let line = read_line(&mut self.reader).await?;
+                if !line.trim_end_matches(['\r', '\n']).is_empty() {
+                    return Err(invalid_data("missing CRLF after chunk"));
+                }
+            }
+            self.started = true;
+
+            // chunk extensions after ';' are ignored
+            let size = parse_chunk_size(&read_line(&mut self.reader).await?)?;
+            if size == 0 {
+                self.read_trailers().await?;
+                return Ok(None);
+            }
+            self.remaining = size;
+        }
+        Ok(Some(self.remaining.min(MAX_PIECE_SIZE)))
+    }
+
+    /// Reads the piece [`Decoder::next_piece_len`] announced into `piece`
+    async fn read_piece(&mut self, piece: &mut [u8]) -> io::Result<()> {
+        self.reader.read_exact(piece).await?;
+        self.remaining -= piece.len();
+        Ok(())
+    }
+
+    /// The next piece of body data in a buffer of its own, for streaming
+    async fn next_piece(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let Some(length) = self.next_piece_len().await? else {
+            return Ok(None);
+        };
+        let mut piece = vec![0u8; length];
+        self.read_piece(&mut piece).await?;
+        Ok(Some(piece))
+    }
+
+    /// Reads trailer fields up to the blank line that ends the message
+    async fn read_trailers(&mut self) -> io::Result<()> {
+        loop {
+            let line = read_line(&mut self.reader).await?;
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                return Ok(());
+            }
+            if self.trailers.len() >= MAX_TRAILERS {
+                return Err(invalid_data("too many trailer fields"));
+            }
+            let (name, value) = line.split_once(':').ok_or_else(|| invalid_data("invalid trailer field"))?;
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid_data("invalid trailer field name"))?;
+            let value = HeaderValue::from_str(value.trim()).map_err(|_| invalid_data("invalid trailer field value"))?;
+            self.trailers.append(name, value);
+        }
+    }
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body from `reader` into a stream of data pieces, each
+/// in a buffer of its own as [`BodyStream`] hands them out
+pub(crate) fn decode<R: AsyncBufRead + Send + Unpin + 'static>(reader: R) -> BodyStream {
+    let decoder = Decoder {
+        reader,
+        remaining: 0,
+        started: false,
+        trailers: HeaderMap::new(),
+    };
+    Box::pin(stream::unfold(Some(decoder), |decoder| async move {
+        let mut decoder = decoder?;
+        match decoder.next_piece().await {
+            Ok(Some(piece)) => Some((Ok(piece), Some(decoder))),
+            Ok(None) => None,
+            // end the stream after reporting the error
+            Err(err) => Some((Err(err), None)),
+        }
+    }))
+}
+
+/// Writes one chunk; empty chunks are skipped since they would end the body
+pub(crate) async fn write_chunk<W: AsyncWrite + Unpin>(writer: &mut W, chunk: &[u8]) -> io::Result<()> {
+    if chunk.is_empty() {
+        return Ok(());
+    }
+    let size_line = format!("{:x}\r\n", chunk.len());
+    write_all_vectored(writer, [size_line.as_bytes(), chunk, &b"\r\n"[..]]).await
+}
+
+/// Ends the body, followed by `trailers`
+pub(crate) async fn write_last_chunk<W: AsyncWrite + Unpin>(writer: &mut W, trailers: Option<&HeaderMap>) -> io::Result<()> {
+    let Some(trailers) = trailers.filter(|trailers| !trailers.is_empty()) else {
+        return writer.write_all(b"0\r\n\r\n").await;
+    };
+    let mut last_chunk = b"0\r\n".to_vec();
+    for (name, value) in trailers {
+        last_chunk.extend_from_slice(name.as_str().as_bytes());
+        last_chunk.extend_from_slice(b": ");
+        last_chunk.extend_from_slice(value.as_bytes());
+        last_chunk.extend_from_slice(b"\r\n");
+    }
+    last_chunk.extend_from_slice(b"\r\n");
+    writer.write_all(&last_chunk).await
+}
+
+/// Reads a whole chunked body and its trailer fields, failing once the body grows past `max_size`
+pub(crate) async fn read_body<R: AsyncBufRead + Unpin>(reader: R, max_size: usize) -> Result<(Vec<u8>, HeaderMap)> {
+    let mut decoder = Decoder {
+        reader,
+        remaining: 0,
+        started: false,
+        trailers: HeaderMap::new(),
+    };
+    let into_error = |err: io::Error| match err.kind() {
+        io::ErrorKind::InvalidData => Error::parse(err.to_string()),
+        _ => err.into(),
+    };
+    let mut body = Vec::new();
+    loop {
+        let Some(length) = decoder.next_piece_len().await.map_err(into_error)? else {
+            return Ok((body, decoder.trailers));
+        };
+        if body.len() + length > max_size {
+            return Err(Error::BodyTooLarge);
+        }
+        // pieces are decoded straight into the body rather than into buffers of their own
+        let start = body.len();
+        body.resize(start + length, 0);
+        decoder.read_piece(&mut body[start..]).await.map_err(into_error)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+    use futures_lite::StreamExt as _;
+
+    use super::*;
+
+    fn read(body: &[u8]) -> Result<(Vec<u8>, HeaderMap)> {
+        block_on(read_body(body, 1024))
+    }
+
+    #[test]
+    fn decodes_chunks_and_trailers() {
+        let (body, trailers) = read(b"5\r\nhello\r\n7;name=value\r\n, world\r\n0\r\nChecksum: abc\r\n\r\n").unwrap();
+        assert_eq!(body, b"hello, world");
+        assert_eq!(trailers.get("checksum").unwrap(), "abc");
+    }
+
+    #[test]
+    fn accepts_uppercase_and_padded_hex() {
+        let (body, _) = read(b"000A\r\n0123456789\r\n0\r\n\r\n").unwrap();
+        assert_eq!(body, b"0123456789");
+    }
+
+    #[test]
+    fn refuses_malformed_chunk_sizes() {
+        for size in ["+5", " 5", "5 ", "5 ;ext", "-5", "0x5", "", ";ext", "5\t"] {
+            let body = format!("{size}\r\nhello\r\n0\r\n\r\n");
+            assert!(matches!(read(body.as_bytes()), Err(Error::Parse(_))), "accepted chunk size {size:?}");
+        }
+    }
+
+    #[test]
+    fn refuses_overlong_chunk_sizes() {
+        assert!(matches!(read(b"00000000000000005\r\nhello\r\n0\r\n\r\n"), Err(Error::Parse(_))));
+        assert!(matches!(read(b"ffffffffffffffffff\r\n"), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn requires_crlf_after_chunk_data() {
+        assert!(matches!(read(b"5\r\nhelloX\r\n0\r\n\r\n"), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn decodes_chunks_larger_than_a_piece() {
+        let data = vec![b'x'; MAX_PIECE_SIZE * 2 + 1];
+        let mut body = format!("{:x}\r\n", data.len()).into_bytes();
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\r\n0\r\n\r\n");
+        assert_eq!(block_on(read_body(&body[..], data.len())).unwrap().0, data);
+
+        let pieces: Vec<_> = block_on(decode(futures_lite::io::Cursor::new(body)).collect::<Vec<_>>());
+        let lengths: Vec<_> = pieces.into_iter().map(|piece| piece.unwrap().len()).collect();
+        assert_eq!(lengths, [MAX_PIECE_SIZE, MAX_PIECE_SIZE, 1]);
+    }
+
+    #[test]
+    fn stops_at_the_size_limit() {
+        assert!(matches!(block_on(read_body(&b"5\r\nhello\r\n0\r\n\r\n"[..], 4)), Err(Error::BodyTooLarge)));
+    }
+}
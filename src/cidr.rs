@@ -0,0 +1,62 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::error::Error;
+
+/// An IP network such as `10.0.0.0/8` or `2001:db8::/32`; a bare address matches only itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Option<Self> {
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        (prefix_len <= max_len).then_some(Self { addr, prefix_len })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for IpCidr {
+    fn from(addr: IpAddr) -> Self {
+        let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        Self { addr, prefix_len }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid_addr = |_| Error::parse(format!("Invalid IP address in {s:?}"));
+        match s.split_once('/') {
+            Some((addr, prefix_len)) => {
+                let addr = IpAddr::from_str(addr.trim()).map_err(invalid_addr)?;
+                let prefix_len = prefix_len.trim().parse::<u8>().map_err(|_| Error::parse("Invalid CIDR prefix length"))?;
+                Self::new(addr, prefix_len).ok_or(Error::parse("Invalid CIDR prefix length"))
+            }
+            None => Ok(Self::from(IpAddr::from_str(s.trim()).map_err(invalid_addr)?)),
+        }
+    }
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
@@ -0,0 +1,119 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http::header::{CACHE_CONTROL, CONTENT_LOCATION, DATE, ETAG, EXPIRES, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, VARY};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Version};
+
+use crate::body::Body;
+use crate::date::{parse_http_date, DateTime};
+
+/// The `ETag` and `Last-Modified` of a representation, for answering `If-None-Match` and
+/// `If-Modified-Since` with a 304, e.g.
+/// `if let Some(response) = Validators::strong(&hash).not_modified(&request) { return Ok(response) }`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Validators {
+    /// A quoted entity tag, optionally prefixed with `W/`
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+/// Weak comparison, which is what `If-None-Match` uses
+fn etag_matches(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+impl Validators {
+    /// A strong `ETag`, for content that is byte for byte identical whenever the tag is
+    pub fn strong(tag: &str) -> Self {
+        Self {
+            etag: Some(format!("\"{tag}\"")),
+            last_modified: None,
+        }
+    }
+
+    /// A weak `ETag`, for content that is only semantically equivalent whenever the tag is
+    pub fn weak(tag: &str) -> Self {
+        Self {
+            etag: Some(format!("W/\"{tag}\"")),
+            last_modified: None,
+        }
+    }
+
+    pub fn with_last_modified(mut self, last_modified: SystemTime) -> Self {
+        self.last_modified = Some(last_modified);
+        self
+    }
+
+    /// The validators a response already carries
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            etag: headers.get(ETAG).and_then(|value| value.to_str().ok()).map(str::to_string),
+            last_modified: headers.get(LAST_MODIFIED).and_then(|value| value.to_str().ok()).and_then(parse_http_date),
+        }
+    }
+
+    /// Whether a GET or HEAD with these request headers already has the current representation.
+    /// `If-None-Match` takes precedence; `If-Modified-Since` only counts without it
+    pub fn is_fresh(&self, method: &Method, headers: &HeaderMap) -> bool {
+        if !matches!(*method, Method::GET | Method::HEAD) {
+            return false;
+        }
+
+        if headers.contains_key(IF_NONE_MATCH) {
+            let Some(etag) = &self.etag else {
+                return false;
+            };
+            return headers
+                .get_all(IF_NONE_MATCH)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
+                .any(|candidate| candidate == "*" || etag_matches(candidate, etag));
+        }
+
+        let since = headers.get(IF_MODIFIED_SINCE).and_then(|value| value.to_str().ok()).and_then(parse_http_date);
+        match (self.last_modified, since) {
+            // HTTP dates only have second precision
+            (Some(last_modified), Some(since)) => unix_secs(last_modified) <= unix_secs(since),
+            _ => false,
+        }
+    }
+
+    /// A 304 carrying these validators if the request is [fresh](Validators::is_fresh)
+    pub fn not_modified<B>(&self, request: &Request<B>) -> Option<Response<Body>> {
+        if !self.is_fresh(request.method(), request.headers()) {
+            return None;
+        }
+        let mut headers = HeaderMap::new();
+        self.apply(&mut headers);
+        Some(not_modified_response(&headers))
+    }
+
+    /// Sets `ETag` and `Last-Modified` on a response
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        if let Some(etag) = self.etag.as_deref().and_then(|etag| HeaderValue::from_str(etag).ok()) {
+            headers.insert(ETAG, etag);
+        }
+        if let Some(last_modified) = self.last_modified {
+            let last_modified = DateTime::from_system_time(last_modified).to_http_date();
+            headers.insert(LAST_MODIFIED, HeaderValue::from_str(&last_modified).expect("HTTP dates are valid header values"));
+        }
+    }
+}
+
+/// A 304 keeping only the headers RFC 9110 lists for it from the full response's `headers`
+pub(crate) fn not_modified_response(headers: &HeaderMap) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::NOT_MODIFIED;
+    *response.version_mut() = Version::HTTP_11;
+    for name in [CACHE_CONTROL, CONTENT_LOCATION, DATE, ETAG, EXPIRES, LAST_MODIFIED, VARY] {
+        for value in headers.get_all(&name) {
+            response.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    response
+}
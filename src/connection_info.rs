@@ -0,0 +1,64 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use http::Extensions;
+
+use crate::tls::PeerCertificates;
+
+/// Details about the connection a request arrived on, inserted into every request's extensions
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionInfo {
+    /// Peer address; `None` for transports without one, like Unix domain sockets
+    pub remote_addr: Option<SocketAddr>,
+    /// Address the connection was accepted on
+    pub local_addr: Option<SocketAddr>,
+    /// Address of the load balancer that relayed this connection via the PROXY protocol; the
+    /// addresses above are then the ones it reported
+    pub proxied_by: Option<SocketAddr>,
+    /// Whether the connection is TLS encrypted
+    pub tls: bool,
+    /// Hostname the client sent via SNI
+    pub server_name: Option<String>,
+    /// Protocol chosen via ALPN, e.g. `b"h2"`
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// Client certificate chain verified during a mutual TLS handshake
+    pub peer_certificates: Option<PeerCertificates>,
+    /// State kept for as long as the connection is open
+    pub extensions: ConnectionExtensions,
+}
+
+/// A type map that lives as long as its connection, shared by every request on it, whether
+/// keep-alive requests in turn or concurrent HTTP/2 streams, e.g. to remember who a client
+/// certificate belongs to or count requests for rate limiting. Also inserted directly into each
+/// request's extensions; clones refer to the same map
+#[derive(Clone, Default)]
+pub struct ConnectionExtensions(Arc<Mutex<Extensions>>);
+
+impl ConnectionExtensions {
+    /// Stores `value`, returning the one of the same type it replaces
+    pub fn insert<T: Clone + Send + Sync + 'static>(&self, value: T) -> Option<T> {
+        self.0.lock().unwrap().insert(value)
+    }
+
+    /// A copy of the stored value of type `T`
+    pub fn get<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.0.lock().unwrap().get::<T>().cloned()
+    }
+
+    pub fn remove<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.0.lock().unwrap().remove::<T>()
+    }
+
+    /// Runs `f` with the map locked, e.g. to update a counter in place. Don't hold on to the
+    /// lock across an `.await`; `f` can't, being synchronous
+    pub fn with<R>(&self, f: impl FnOnce(&mut Extensions) -> R) -> R {
+        f(&mut self.0.lock().unwrap())
+    }
+}
+
+impl fmt::Debug for ConnectionExtensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionExtensions").finish_non_exhaustive()
+    }
+}
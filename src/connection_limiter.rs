@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use async_lock::{Semaphore, SemaphoreGuardArc};
+
+use crate::limits::Limits;
+
+type PeerCounts = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+/// Tracks open connections so the accept loop can apply backpressure
+pub(crate) struct ConnectionLimiter {
+    semaphore: Option<Arc<Semaphore>>,
+    max_per_ip: Option<usize>,
+    per_ip: PeerCounts,
+}
+
+/// Held by a connection task for its lifetime; releases its slots on drop
+pub(crate) struct ConnectionPermit {
+    _global: Option<SemaphoreGuardArc>,
+    peer: Option<(IpAddr, PeerCounts)>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(limits: &Limits) -> Self {
+        Self {
+            semaphore: limits.max_connections.map(|max| Arc::new(Semaphore::new(max))),
+            max_per_ip: limits.max_connections_per_ip,
+            per_ip: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Waits until a global connection slot is free
+    pub async fn reserve(&self) -> Option<SemaphoreGuardArc> {
+        match &self.semaphore {
+            Some(semaphore) => Some(semaphore.acquire_arc().await),
+            None => None,
+        }
+    }
+
+    /// Admits a connection from `ip`, or returns `None` if that peer already holds too many
+    pub fn admit(&self, global: Option<SemaphoreGuardArc>, ip: Option<IpAddr>) -> Option<ConnectionPermit> {
+        let (Some(max_per_ip), Some(ip)) = (self.max_per_ip, ip) else {
+            return Some(ConnectionPermit { _global: global, peer: None });
+        };
+
+        let mut per_ip = self.per_ip.lock().unwrap();
+        let count = per_ip.entry(ip).or_insert(0);
+        if *count >= max_per_ip {
+            return None;
+        }
+        *count += 1;
+
+        Some(ConnectionPermit {
+            _global: global,
+            peer: Some((ip, self.per_ip.clone())),
+        })
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        if let Some((ip, per_ip)) = self.peer.take() {
+            let mut per_ip = per_ip.lock().unwrap();
+            if let Some(count) = per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http::header::{ALT_SVC, DATE, SERVER};
+use http::{HeaderMap, HeaderValue};
+
+use crate::date::DateTime;
+
+/// Headers the server adds to every response that doesn't set them itself
+#[derive(Clone, Debug)]
+pub struct DefaultHeaders {
+    /// Send `Date`, as RFC 9110 asks of origin servers with a clock
+    pub date: bool,
+    /// Value for `Server`, or `None` to leave it out
+    pub server: Option<String>,
+    /// Value for `Alt-Svc`, advertising another endpoint such as HTTP/3, e.g. `h3=":443"; ma=86400`
+    pub alt_svc: Option<String>,
+}
+
+impl Default for DefaultHeaders {
+    fn default() -> Self {
+        Self {
+            date: true,
+            server: Some(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).to_string()),
+            alt_svc: None,
+        }
+    }
+}
+
+impl DefaultHeaders {
+    pub fn none() -> Self {
+        Self {
+            date: false,
+            server: None,
+            alt_svc: None,
+        }
+    }
+
+    pub(crate) fn apply(&self, headers: &mut HeaderMap) {
+        if self.date && !headers.contains_key(DATE) {
+            headers.insert(DATE, cached_date());
+        }
+        if let Some(server) = &self.server {
+            if !headers.contains_key(SERVER) {
+                if let Ok(server) = HeaderValue::from_str(server) {
+                    headers.insert(SERVER, server);
+                }
+            }
+        }
+        if let Some(alt_svc) = &self.alt_svc {
+            if !headers.contains_key(ALT_SVC) {
+                if let Ok(alt_svc) = HeaderValue::from_str(alt_svc) {
+                    headers.insert(ALT_SVC, alt_svc);
+                }
+            }
+        }
+    }
+}
+
+/// The current `Date` value, formatted at most once per second
+fn cached_date() -> HeaderValue {
+    static CACHE: Mutex<Option<(u64, HeaderValue)>> = Mutex::new(None);
+
+    let now = SystemTime::now();
+    let second = now.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+    let mut cache = CACHE.lock().unwrap();
+    match &*cache {
+        Some((cached_second, value)) if *cached_second == second => value.clone(),
+        _ => {
+            let value = HeaderValue::from_str(&DateTime::from_system_time(now).to_http_date())
+                .expect("HTTP dates are plain ASCII");
+            *cache = Some((second, value.clone()));
+            value
+        }
+    }
+}
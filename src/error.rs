@@ -0,0 +1,142 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+use http::StatusCode;
+
+use crate::limits::LimitError;
+use crate::timeout::TimeoutError;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything the server itself can fail with; handler and middleware errors arrive as
+/// [`Error::Handler`]
+#[derive(Debug)]
+pub enum Error {
+    /// A malformed request, or invalid configuration input such as an unparsable URL
+    Parse(String),
+    Io(io::Error),
+    /// Certificate loading or TLS configuration failed
+    Tls(rustls::Error),
+    Timeout(TimeoutError),
+    BodyTooLarge,
+    /// The request line named an HTTP version other than 1.0 or 1.1
+    UnsupportedVersion,
+    /// Request line or header limits from [`Limits`](crate::Limits) were exceeded
+    Limit(LimitError),
+    /// A request body in a format the handler doesn't accept, e.g. non-JSON sent to a `Json` extractor
+    UnsupportedMediaType,
+    /// None of the representations a handler offers is acceptable to the client
+    NotAcceptable,
+    Handler(Box<dyn StdError + Send + Sync>),
+}
+
+impl Error {
+    pub(crate) fn parse(message: impl Into<String>) -> Self {
+        Error::Parse(message.into())
+    }
+
+    /// Status code a client should see for this failure
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Parse(_) => StatusCode::BAD_REQUEST,
+            Error::Timeout(_) => StatusCode::REQUEST_TIMEOUT,
+            Error::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::UnsupportedVersion => StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            Error::Limit(limit_error) => limit_error.status(),
+            Error::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::NotAcceptable => StatusCode::NOT_ACCEPTABLE,
+            Error::Io(_) | Error::Tls(_) | Error::Handler(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(message) => write!(f, "parse error: {message}"),
+            Error::Io(err) => write!(f, "I/O error: {err}"),
+            Error::Tls(err) => write!(f, "TLS error: {err}"),
+            Error::Timeout(err) => err.fmt(f),
+            Error::BodyTooLarge => write!(f, "request body too large"),
+            Error::UnsupportedVersion => write!(f, "unsupported HTTP version"),
+            Error::Limit(err) => err.fmt(f),
+            Error::UnsupportedMediaType => write!(f, "unsupported media type"),
+            Error::NotAcceptable => write!(f, "no acceptable representation"),
+            Error::Handler(err) => write!(f, "handler error: {err}"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Tls(err) => Some(err),
+            Error::Timeout(err) => Some(err),
+            Error::Limit(err) => Some(err),
+            Error::Handler(err) => Some(err.as_ref()),
+            Error::Parse(_) | Error::BodyTooLarge | Error::UnsupportedVersion | Error::UnsupportedMediaType | Error::NotAcceptable => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<rustls::Error> for Error {
+    fn from(err: rustls::Error) -> Self {
+        Error::Tls(err)
+    }
+}
+
+impl From<TimeoutError> for Error {
+    fn from(err: TimeoutError) -> Self {
+        Error::Timeout(err)
+    }
+}
+
+impl From<LimitError> for Error {
+    fn from(err: LimitError) -> Self {
+        match err {
+            LimitError::BodyTooLarge => Error::BodyTooLarge,
+            err => Error::Limit(err),
+        }
+    }
+}
+
+impl From<http::Error> for Error {
+    fn from(err: http::Error) -> Self {
+        Error::Parse(err.to_string())
+    }
+}
+
+/// Recovers the kind of a boxed error from internal helpers and user code; anything
+/// unrecognised is a [`Error::Handler`]
+impl From<Box<dyn StdError + Send + Sync>> for Error {
+    fn from(err: Box<dyn StdError + Send + Sync>) -> Self {
+        let err = match err.downcast::<Error>() {
+            Ok(err) => return *err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<io::Error>() {
+            Ok(err) => return Error::Io(*err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<TimeoutError>() {
+            Ok(err) => return Error::Timeout(*err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<LimitError>() {
+            Ok(err) => return Error::from(*err),
+            Err(err) => err,
+        };
+        match err.downcast::<rustls::Error>() {
+            Ok(err) => Error::Tls(*err),
+            Err(err) => Error::Handler(err),
+        }
+    }
+}
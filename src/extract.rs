@@ -0,0 +1,146 @@
+use http::{HeaderMap, Method, Request, Uri};
+
+use crate::cancellation::Cancellation;
+use crate::connection_info::{ConnectionExtensions, ConnectionInfo};
+use crate::error::{Error, Result};
+use crate::path_params::{PathParams, RequestPathExt as _};
+
+/// A handler argument built from the request, e.g. `Path<u64>` or `Json<Payload>`. A failure
+/// is answered with the error's status, so a malformed argument becomes a 400 without the
+/// handler running
+pub trait FromRequest: Sized {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self>;
+}
+
+impl FromRequest for HeaderMap {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self> {
+        Ok(request.headers().clone())
+    }
+}
+
+impl FromRequest for Method {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self> {
+        Ok(request.method().clone())
+    }
+}
+
+impl FromRequest for Uri {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self> {
+        Ok(request.uri().clone())
+    }
+}
+
+impl FromRequest for PathParams {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self> {
+        Ok(request.path_params().clone())
+    }
+}
+
+/// Never cancelled on requests the server doesn't watch, such as HTTP/3 ones
+impl FromRequest for Cancellation {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self> {
+        Ok(request.extensions().get::<Cancellation>().cloned().unwrap_or_default())
+    }
+}
+
+impl FromRequest for ConnectionExtensions {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self> {
+        request
+            .extensions()
+            .get::<ConnectionExtensions>()
+            .cloned()
+            .ok_or_else(|| Error::Handler("no ConnectionExtensions on the request".into()))
+    }
+}
+
+impl FromRequest for ConnectionInfo {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self> {
+        request
+            .extensions()
+            .get::<ConnectionInfo>()
+            .cloned()
+            .ok_or_else(|| Error::Handler("no ConnectionInfo on the request".into()))
+    }
+}
+
+/// Shared state added with [`Router::add_state`](crate::Router::add_state)
+#[derive(Clone, Debug, Default)]
+pub struct State<T>(pub T);
+
+impl<T: Clone + Send + Sync + 'static> FromRequest for State<T> {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self> {
+        request
+            .extensions()
+            .get::<State<T>>()
+            .cloned()
+            .ok_or_else(|| Error::Handler(format!("no state of type {} was added to the router", std::any::type_name::<T>()).into()))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> FromRequest for crate::path_params::Path<T> {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self> {
+        Self::extract(request)
+    }
+}
+
+/// The query string deserialized into `T`, usually a struct with a field per parameter
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self> {
+        let mut params = Vec::new();
+        for pair in request.uri().query().unwrap_or("").split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match (crate::percent::decode(name, true), crate::percent::decode(value, true)) {
+                (Some(name), Some(value)) => params.push((name, value)),
+                _ => return Err(Error::parse("invalid percent-encoding in query string")),
+            }
+        }
+        T::deserialize(crate::de::ParamsDeserializer::new(&params))
+            .map(Query)
+            .map_err(|err| Error::parse(format!("invalid query string: {err}")))
+    }
+}
+
+/// A JSON request body, or a JSON response when returned from a handler
+#[cfg(feature = "json")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "json")]
+impl<T: serde::de::DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self> {
+        let content_type = request
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase());
+        let is_json = content_type.is_some_and(|value| value == "application/json" || (value.starts_with("application/") && value.ends_with("+json")));
+        if !is_json {
+            return Err(Error::UnsupportedMediaType);
+        }
+        serde_json::from_slice(request.body())
+            .map(Json)
+            .map_err(|err| Error::parse(format!("invalid JSON body: {err}")))
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize> crate::response::IntoResponse for Json<T> {
+    fn into_response(self) -> http::Response<crate::body::Body> {
+        match serde_json::to_vec(&self.0) {
+            Ok(body) => {
+                let mut response = http::Response::new(crate::body::Body::from(body));
+                *response.version_mut() = http::Version::HTTP_11;
+                response.headers_mut().insert(http::header::CONTENT_TYPE, http::HeaderValue::from_static("application/json"));
+                response
+            }
+            Err(err) => Error::Handler(Box::new(err)).into_response(),
+        }
+    }
+}
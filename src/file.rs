@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, VARY};
+use http::{HeaderValue, Request, Response, StatusCode};
+
+use crate::accept::{encoding_quality, quality_list};
+use crate::body::Body;
+use crate::conditional::Validators;
+use crate::mime;
+use crate::response::IntoResponse;
+
+/// Content codings with precompressed siblings looked for on disk, in order of preference
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// Where a [`FileResponse`]'s bytes come from
+enum FileSource {
+    Disk(std::fs::File),
+    /// The contents held by a [`FileCache`]
+    Memory(Arc<[u8]>),
+}
+
+/// A file sent from disk, or served from a [`FileCache`], with `Content-Type` guessed from its
+/// extension and `Content-Length` taken from its metadata. On Linux, plain TCP connections get
+/// it copied to the socket by the kernel with `sendfile(2)`; TLS and other connections have it
+/// streamed in large chunks
+pub struct FileResponse {
+    source: FileSource,
+    path: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+    content_type: Option<HeaderValue>,
+    /// The `Content-Encoding` of a precompressed variant
+    encoding: Option<&'static str>,
+    /// Whether the choice depended on `Accept-Encoding`
+    negotiated: bool,
+}
+
+async fn open_file(path: PathBuf) -> io::Result<(std::fs::File, std::fs::Metadata)> {
+    let (file, metadata) = blocking::unblock(move || {
+        let file = std::fs::File::open(&path)?;
+        let metadata = file.metadata()?;
+        Ok::<_, io::Error>((file, metadata))
+    })
+    .await?;
+    if !metadata.is_file() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a regular file"));
+    }
+    Ok((file, metadata))
+}
+
+/// Opens `path`, from `cache` when one is given
+async fn open_source(path: PathBuf, cache: Option<&FileCache>) -> io::Result<(FileSource, u64, Option<SystemTime>)> {
+    match cache {
+        Some(cache) => cache.load(path).await,
+        None => {
+            let (file, metadata) = open_file(path).await?;
+            Ok((FileSource::Disk(file), metadata.len(), metadata.modified().ok()))
+        }
+    }
+}
+
+impl FileResponse {
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_from(path.as_ref().to_path_buf(), None).await
+    }
+
+    async fn open_from(path: PathBuf, cache: Option<&FileCache>) -> io::Result<Self> {
+        let (source, len, modified) = open_source(path.clone(), cache).await?;
+
+        Ok(Self {
+            source,
+            path,
+            len,
+            modified,
+            content_type: None,
+            encoding: None,
+            negotiated: false,
+        })
+    }
+
+    /// Like [`FileResponse::open`], but serves a `.br` or `.gz` sibling of `path`, e.g.
+    /// `app.js.br`, when one exists and the request's `Accept-Encoding` allows it. The content
+    /// type still comes from `path`
+    pub async fn open_precompressed<B>(path: impl AsRef<Path>, request: &Request<B>) -> io::Result<Self> {
+        Self::open_precompressed_from(path.as_ref(), request, None).await
+    }
+
+    async fn open_precompressed_from<B>(path: &Path, request: &Request<B>, cache: Option<&FileCache>) -> io::Result<Self> {
+        let accepted = quality_list(request.headers(), ACCEPT_ENCODING);
+        let mut candidates = PRECOMPRESSED
+            .iter()
+            .map(|(coding, extension)| (*coding, *extension, encoding_quality(&accepted, coding)))
+            .filter(|(_, _, quality)| *quality > 0.0)
+            .collect::<Vec<_>>();
+        // stable, so equally weighted codings keep their order of preference
+        candidates.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+        for (coding, extension, _) in candidates {
+            let mut variant = path.as_os_str().to_owned();
+            variant.push(".");
+            variant.push(extension);
+            if let Ok((source, len, modified)) = open_source(PathBuf::from(variant), cache).await {
+                return Ok(Self {
+                    source,
+                    path: path.to_path_buf(),
+                    len,
+                    modified,
+                    content_type: None,
+                    encoding: Some(coding),
+                    negotiated: true,
+                });
+            }
+        }
+
+        let mut response = Self::open_from(path.to_path_buf(), cache).await?;
+        response.negotiated = true;
+        Ok(response)
+    }
+
+    /// Overrides the guessed content type
+    pub fn content_type(mut self, content_type: HeaderValue) -> Self {
+        self.content_type = Some(content_type);
+        self
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    /// A strong `ETag` built from the modification time and size, as nginx does, plus
+    /// `Last-Modified`; both are set on the response
+    pub fn validators(&self) -> Validators {
+        let Some(modified) = self.modified else {
+            return Validators::default();
+        };
+        let mtime = modified.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+        // each encoding is a different representation, so it needs its own tag
+        let suffix = self.encoding.map(|encoding| format!("-{encoding}")).unwrap_or_default();
+        Validators::strong(&format!("{mtime:x}-{:x}{suffix}", self.len)).with_last_modified(modified)
+    }
+}
+
+impl IntoResponse for FileResponse {
+    fn into_response(self) -> Response<Body> {
+        let validators = self.validators();
+        let content_type = self.content_type.unwrap_or_else(|| HeaderValue::from_static(mime::from_path(&self.path)));
+        let mut response = StatusCode::OK.into_response();
+        *response.body_mut() = match self.source {
+            FileSource::Disk(file) => Body::from_file(file, self.len),
+            FileSource::Memory(contents) => Body::from(contents),
+        };
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+        validators.apply(response.headers_mut());
+        if let Some(encoding) = self.encoding {
+            response.headers_mut().insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        if self.negotiated {
+            response.headers_mut().append(VARY, HeaderValue::from_static("Accept-Encoding"));
+        }
+        response
+    }
+}
+
+struct CachedFile {
+    contents: Arc<[u8]>,
+    modified: SystemTime,
+    /// When the entry was last served, in ticks of [`FileCacheState::clock`]
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct FileCacheState {
+    files: HashMap<PathBuf, CachedFile>,
+    total_size: usize,
+    clock: u64,
+}
+
+/// Keeps small files in memory so hot assets are served without opening, reading or copying them
+/// every time. Each hit still checks the file's metadata, and an entry whose modification
+/// time or size changed is read again. Once full, the least recently served files are
+/// dropped first. Clones share the same cache
+#[derive(Clone)]
+pub struct FileCache {
+    state: Arc<Mutex<FileCacheState>>,
+    max_total_size: usize,
+    max_file_size: usize,
+}
+
+impl Default for FileCache {
+    fn default() -> Self {
+        Self::new(64 * 1024 * 1024, 1024 * 1024)
+    }
+}
+
+impl FileCache {
+    /// Holds at most `max_total_size` bytes of file contents, and no file larger than
+    /// `max_file_size`; larger files are always streamed from disk
+    pub fn new(max_total_size: usize, max_file_size: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(FileCacheState::default())),
+            max_total_size,
+            max_file_size: max_file_size.min(max_total_size),
+        }
+    }
+
+    /// [`FileResponse::open`] through the cache
+    pub async fn open(&self, path: impl AsRef<Path>) -> io::Result<FileResponse> {
+        FileResponse::open_from(path.as_ref().to_path_buf(), Some(self)).await
+    }
+
+    /// [`FileResponse::open_precompressed`] through the cache, which holds each variant separately
+    pub async fn open_precompressed<B>(&self, path: impl AsRef<Path>, request: &Request<B>) -> io::Result<FileResponse> {
+        FileResponse::open_precompressed_from(path.as_ref(), request, Some(self)).await
+    }
+
+    /// Drops every cached file
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.files.clear();
+        state.total_size = 0;
+    }
+
+    /// Bytes of file contents currently held
+    pub fn total_size(&self) -> usize {
+        self.state.lock().unwrap().total_size
+    }
+
+    async fn load(&self, path: PathBuf) -> io::Result<(FileSource, u64, Option<SystemTime>)> {
+        let metadata = blocking::unblock({
+            let path = path.clone();
+            move || std::fs::metadata(path)
+        })
+        .await?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a regular file"));
+        }
+        // without a modification time there'd be no telling when an entry went stale
+        let Ok(modified) = metadata.modified() else {
+            let (file, metadata) = open_file(path).await?;
+            return Ok((FileSource::Disk(file), metadata.len(), metadata.modified().ok()));
+        };
+        if let Some(contents) = self.lookup(&path, modified, metadata.len()) {
+            return Ok((FileSource::Memory(contents), metadata.len(), Some(modified)));
+        }
+        if metadata.len() > self.max_file_size as u64 {
+            let (file, metadata) = open_file(path).await?;
+            return Ok((FileSource::Disk(file), metadata.len(), metadata.modified().ok()));
+        }
+
+        let contents: Arc<[u8]> = blocking::unblock({
+            let path = path.clone();
+            move || std::fs::read(path)
+        })
+        .await?
+        .into();
+        // a file being rewritten as we read it is served as read, but not kept
+        if contents.len() as u64 == metadata.len() {
+            self.insert(path, contents.clone(), modified);
+        }
+        let len = contents.len() as u64;
+        Ok((FileSource::Memory(contents), len, Some(modified)))
+    }
+
+    fn lookup(&self, path: &Path, modified: SystemTime, len: u64) -> Option<Arc<[u8]>> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        let cached = state.files.get_mut(path)?;
+        if cached.modified == modified && cached.contents.len() as u64 == len {
+            cached.last_used = clock;
+            return Some(cached.contents.clone());
+        }
+        if let Some(stale) = state.files.remove(path) {
+            state.total_size -= stale.contents.len();
+        }
+        None
+    }
+
+    fn insert(&self, path: PathBuf, contents: Arc<[u8]>, modified: SystemTime) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(replaced) = state.files.remove(&path) {
+            state.total_size -= replaced.contents.len();
+        }
+        while state.total_size + contents.len() > self.max_total_size {
+            let Some(oldest) = state.files.iter().min_by_key(|(_, cached)| cached.last_used).map(|(path, _)| path.clone()) else {
+                break;
+            };
+            if let Some(evicted) = state.files.remove(&oldest) {
+                state.total_size -= evicted.contents.len();
+            }
+        }
+        state.clock += 1;
+        let last_used = state.clock;
+        state.total_size += contents.len();
+        state.files.insert(path, CachedFile { contents, modified, last_used });
+    }
+}
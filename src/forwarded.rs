@@ -0,0 +1,236 @@
+use std::net::{IpAddr, SocketAddr};
+
+use http::header::FORWARDED;
+use http::Request;
+
+use crate::cidr::IpCidr;
+use crate::connection_info::ConnectionInfo;
+
+/// The originating client as seen through any trusted reverse proxies, inserted into every
+/// request's extensions. Without trusted proxies this is simply the peer address and the
+/// scheme of the connection itself
+#[derive(Clone, Debug)]
+pub struct ClientAddr {
+    pub ip: Option<IpAddr>,
+    /// `"http"` or `"https"`, as reported by the proxy nearest to the client we trust
+    pub scheme: String,
+}
+
+/// The reverse proxies in front of the server and the one header they report clients in. Only
+/// that header is read: the other can be sent by the client and passed along untouched, so
+/// believing it would let clients forge their address
+#[derive(Clone, Debug)]
+pub struct TrustedProxies {
+    header: ForwardingHeader,
+    networks: Vec<IpCidr>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ForwardingHeader {
+    Forwarded,
+    XForwardedFor,
+}
+
+impl TrustedProxies {
+    /// Proxies in `networks` that report clients in RFC 7239 `Forwarded` headers
+    pub fn forwarded(networks: impl IntoIterator<Item = IpCidr>) -> Self {
+        Self {
+            header: ForwardingHeader::Forwarded,
+            networks: networks.into_iter().collect(),
+        }
+    }
+
+    /// Proxies in `networks` that report clients in `X-Forwarded-For`, and their scheme in
+    /// `X-Forwarded-Proto`
+    pub fn x_forwarded_for(networks: impl IntoIterator<Item = IpCidr>) -> Self {
+        Self {
+            header: ForwardingHeader::XForwardedFor,
+            networks: networks.into_iter().collect(),
+        }
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+struct Hop {
+    ip: Option<IpAddr>,
+    proto: Option<String>,
+}
+
+/// Parses a `for=` node or an `X-Forwarded-For` entry: `ip`, `ip:port` or `[ipv6]:port`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// RFC 7239 `Forwarded` elements, nearest-to-client first
+fn forwarded_hops<B>(request: &Request<B>) -> Vec<Hop> {
+    request
+        .headers()
+        .get_all(FORWARDED)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|element| {
+            let mut hop = Hop { ip: None, proto: None };
+            for pair in element.split(';') {
+                let Some((key, value)) = pair.split_once('=') else {
+                    continue;
+                };
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "for" => hop.ip = parse_node(value),
+                    "proto" => hop.proto = Some(value.trim().trim_matches('"').to_ascii_lowercase()),
+                    _ => {}
+                }
+            }
+            hop
+        })
+        .collect()
+}
+
+/// `X-Forwarded-For` entries, with the last `X-Forwarded-Proto` value applied to them
+fn x_forwarded_hops<B>(request: &Request<B>) -> Vec<Hop> {
+    let proto = request
+        .headers()
+        .get_all("x-forwarded-proto")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .last()
+        .map(|proto| proto.trim().to_ascii_lowercase());
+
+    request
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|node| Hop {
+            ip: parse_node(node),
+            proto: proto.clone(),
+        })
+        .collect()
+}
+
+/// Exposes who connected, and how, to handlers: the [`ConnectionInfo`], the resolved
+/// [`ClientAddr`] and any client certificates
+pub(crate) fn insert_connection_extensions<B>(request: &mut Request<B>, connection_info: &ConnectionInfo, trusted_proxies: Option<&TrustedProxies>) {
+    if let Some(peer_certificates) = connection_info.peer_certificates.clone() {
+        request.extensions_mut().insert(peer_certificates);
+    }
+    let client_addr = resolve_client(request, connection_info, trusted_proxies);
+    request.extensions_mut().insert(client_addr);
+    request.extensions_mut().insert(connection_info.extensions.clone());
+    request.extensions_mut().insert(connection_info.clone());
+}
+
+pub(crate) fn resolve_client<B>(
+    request: &Request<B>,
+    connection_info: &ConnectionInfo,
+    trusted_proxies: Option<&TrustedProxies>,
+) -> ClientAddr {
+    let mut client = ClientAddr {
+        ip: connection_info.remote_addr.map(|remote_addr| remote_addr.ip().to_canonical()),
+        scheme: if connection_info.tls { "https" } else { "http" }.to_string(),
+    };
+
+    // forwarding headers are only believed when they come from a proxy we trust
+    let Some(trusted_proxies) = trusted_proxies else {
+        return client;
+    };
+    if !client.ip.is_some_and(|ip| trusted_proxies.contains(ip)) {
+        return client;
+    }
+
+    let hops = match trusted_proxies.header {
+        ForwardingHeader::Forwarded => forwarded_hops(request),
+        ForwardingHeader::XForwardedFor => x_forwarded_hops(request),
+    };
+
+    // walk back from the nearest proxy until we reach an address we don't trust
+    for hop in hops.iter().rev() {
+        let Some(ip) = hop.ip else {
+            break;
+        };
+        client.ip = Some(ip);
+        if let Some(proto) = &hop.proto {
+            client.scheme = proto.clone();
+        }
+        if !trusted_proxies.contains(ip) {
+            break;
+        }
+    }
+
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    const PROXY: &str = "10.0.0.1:443";
+
+    fn resolve(trusted_proxies: &TrustedProxies, headers: &[(&str, &str)]) -> ClientAddr {
+        let mut request = Request::builder();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let connection_info = ConnectionInfo {
+            remote_addr: Some(PROXY.parse::<SocketAddr>().unwrap()),
+            ..ConnectionInfo::default()
+        };
+        resolve_client(&request.body(()).unwrap(), &connection_info, Some(trusted_proxies))
+    }
+
+    fn networks() -> Vec<IpCidr> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    #[test]
+    fn ignores_a_forged_forwarded_header_next_to_x_forwarded_for() {
+        let client = resolve(
+            &TrustedProxies::x_forwarded_for(networks()),
+            &[("forwarded", "for=1.1.1.1;proto=https"), ("x-forwarded-for", "203.0.113.7")],
+        );
+        assert_eq!(client.ip, Some([203, 0, 113, 7].into()));
+        assert_eq!(client.scheme, "http");
+    }
+
+    #[test]
+    fn ignores_a_forged_x_forwarded_for_next_to_forwarded() {
+        let client = resolve(
+            &TrustedProxies::forwarded(networks()),
+            &[("x-forwarded-for", "1.1.1.1"), ("forwarded", "for=203.0.113.7;proto=https")],
+        );
+        assert_eq!(client.ip, Some([203, 0, 113, 7].into()));
+        assert_eq!(client.scheme, "https");
+    }
+
+    #[test]
+    fn stops_at_the_first_untrusted_hop_from_the_right() {
+        // the client prepended 1.1.1.1 itself; 198.51.100.2 is the first hop no trusted proxy vouches for
+        let client = resolve(
+            &TrustedProxies::x_forwarded_for(networks()),
+            &[("x-forwarded-for", "1.1.1.1, 198.51.100.2, 10.0.0.2")],
+        );
+        assert_eq!(client.ip, Some([198, 51, 100, 2].into()));
+    }
+
+    #[test]
+    fn ignores_forwarding_headers_from_untrusted_peers() {
+        let client = resolve(
+            &TrustedProxies::x_forwarded_for(vec!["192.168.0.0/16".parse().unwrap()]),
+            &[("x-forwarded-for", "203.0.113.7")],
+        );
+        assert_eq!(client.ip, Some([10, 0, 0, 1].into()));
+    }
+}
@@ -0,0 +1,64 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use http::{Request, Response};
+use simple_error::SimpleResult;
+
+use crate::body::Body;
+use crate::extract::FromRequest;
+use crate::response::IntoResponse;
+use crate::router::RouteHandler;
+use crate::spawner::Spawner;
+use crate::types::BoxFuture;
+
+/// An async function whose arguments all implement [`FromRequest`] and whose output implements
+/// [`IntoResponse`], e.g. `async fn(Path<u64>, Json<Payload>) -> impl IntoResponse`. `Args` is
+/// the tuple of argument types and only exists to tell the implementations apart
+pub trait Handler<Args>: Send + Sync + 'static {
+    fn call(&self, request: Request<Vec<u8>>) -> BoxFuture<'static, Response<Body>>;
+}
+
+macro_rules! impl_handler {
+    ($($arg:ident),*) => {
+        impl<F, Fut, R, $($arg,)*> Handler<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = R> + Send + 'static,
+            R: IntoResponse,
+            $($arg: FromRequest,)*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn call(&self, request: Request<Vec<u8>>) -> BoxFuture<'static, Response<Body>> {
+                $(
+                    let $arg = match <$arg as FromRequest>::from_request(&request) {
+                        Ok(value) => value,
+                        Err(err) => {
+                            let response = err.into_response();
+                            return Box::pin(async move { response });
+                        }
+                    };
+                )*
+                let future = self($($arg),*);
+                Box::pin(async move { future.await.into_response() })
+            }
+        }
+    };
+}
+
+impl_handler!();
+impl_handler!(T1);
+impl_handler!(T1, T2);
+impl_handler!(T1, T2, T3);
+impl_handler!(T1, T2, T3, T4);
+impl_handler!(T1, T2, T3, T4, T5);
+impl_handler!(T1, T2, T3, T4, T5, T6);
+impl_handler!(T1, T2, T3, T4, T5, T6, T7);
+impl_handler!(T1, T2, T3, T4, T5, T6, T7, T8);
+
+/// Adapts a [`Handler`] for [`Router::add_route`](crate::Router::add_route)
+pub fn handler<H: Handler<Args>, Args>(handler: H) -> Arc<RouteHandler> {
+    Arc::new(move |_spawner: Arc<dyn Spawner>, request: Request<Vec<u8>>| -> BoxFuture<'static, SimpleResult<Response<Body>>> {
+        let future = handler.call(request);
+        Box::pin(async move { Ok(future.await) })
+    })
+}
@@ -0,0 +1,118 @@
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+
+use http::header::CACHE_CONTROL;
+use http::{HeaderValue, Response, StatusCode};
+
+use crate::body::Body;
+use crate::response::{IntoResponse as _, Text};
+use crate::shutdown::ShutdownHandle;
+use crate::types::BoxFuture;
+
+type Check = dyn Fn() -> BoxFuture<'static, Result<(), String>> + Send + Sync;
+
+#[derive(Default)]
+struct Checks {
+    liveness: RwLock<Vec<(String, Arc<Check>)>>,
+    readiness: RwLock<Vec<(String, Arc<Check>)>>,
+    shutdown: RwLock<Option<ShutdownHandle>>,
+}
+
+/// Checks behind the liveness and readiness endpoints that
+/// [`Router::add_health_routes`](crate::Router::add_health_routes) serves, `/healthz` and
+/// `/readyz` by default. Either answers 200 while all of its checks pass and 503 with the failures
+/// otherwise; readiness also fails as soon as the server starts draining, so load balancers stop
+/// sending it traffic. Clones share the same checks, so more can be added while serving
+#[derive(Clone)]
+pub struct HealthRegistry {
+    pub(crate) health_path: String,
+    pub(crate) ready_path: String,
+    checks: Arc<Checks>,
+}
+
+impl Default for HealthRegistry {
+    fn default() -> Self {
+        Self {
+            health_path: "/healthz".to_string(),
+            ready_path: "/readyz".to_string(),
+            checks: Arc::default(),
+        }
+    }
+}
+
+impl HealthRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn health_path(mut self, path: impl Into<String>) -> Self {
+        self.health_path = path.into();
+        self
+    }
+
+    pub fn ready_path(mut self, path: impl Into<String>) -> Self {
+        self.ready_path = path.into();
+        self
+    }
+
+    /// Adds a check for whether the process is working at all; a failing liveness check usually
+    /// gets it restarted, so keep these to things a restart would fix
+    pub fn add_liveness_check<F, Fut>(&self, name: impl Into<String>, check: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let check: Arc<Check> = Arc::new(move || Box::pin(check()));
+        self.checks.liveness.write().unwrap().push((name.into(), check));
+    }
+
+    /// Adds a check for whether requests can be served right now, e.g. a database ping
+    pub fn add_readiness_check<F, Fut>(&self, name: impl Into<String>, check: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let check: Arc<Check> = Arc::new(move || Box::pin(check()));
+        self.checks.readiness.write().unwrap().push((name.into(), check));
+    }
+
+    /// Reports not ready once `shutdown_handle` starts draining.
+    /// [`HttpServerBuilder::health`](crate::HttpServerBuilder::health) does this with the
+    /// server's own handle
+    pub fn watch_shutdown(&self, shutdown_handle: &ShutdownHandle) {
+        *self.checks.shutdown.write().unwrap() = Some(shutdown_handle.clone());
+    }
+
+    pub(crate) async fn liveness(&self) -> Response<Body> {
+        let checks = self.checks.liveness.read().unwrap().clone();
+        report(checks, false).await
+    }
+
+    pub(crate) async fn readiness(&self) -> Response<Body> {
+        let draining = self.checks.shutdown.read().unwrap().as_ref().is_some_and(ShutdownHandle::is_draining);
+        let checks = self.checks.readiness.read().unwrap().clone();
+        report(checks, draining).await
+    }
+}
+
+/// Runs `checks` one after another, listing each failure in a plain text body
+async fn report(checks: Vec<(String, Arc<Check>)>, draining: bool) -> Response<Body> {
+    let mut failures = String::new();
+    if draining {
+        failures.push_str("shutting down\n");
+    }
+    for (name, check) in checks {
+        if let Err(err) = check().await {
+            log::warn!("health check {name} failed: {err}");
+            failures.push_str(&format!("{name}: {err}\n"));
+        }
+    }
+
+    let mut response = if failures.is_empty() {
+        Text("ok\n").into_response()
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Text(failures)).into_response()
+    };
+    response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use http::{Method, Request, StatusCode};
+
+use crate::connection_info::ConnectionInfo;
+use crate::metrics::RequestMetrics;
+
+/// Observes the server at each stage of a connection's life, e.g. for accounting or audit logs,
+/// installed with [`HttpServerBuilder::hooks`](crate::HttpServerBuilder::hooks). Every method
+/// defaults to doing nothing. Hooks run on the connection's task, so they should return quickly
+pub trait ServerHooks: Send + Sync + 'static {
+    /// A connection was accepted and, for TLS, completed its handshake
+    fn on_connect(&self, _connection: &ConnectionInfo) {}
+
+    /// A request was read in full and is about to be routed; extensions inserted here are
+    /// visible to middleware and handlers
+    fn on_request(&self, _request: &mut Request<Vec<u8>>, _connection: &ConnectionInfo) {}
+
+    /// The response to a request was written; `metrics` has the bytes each way and how long
+    /// the handler and the whole exchange took
+    fn on_response(&self, _connection: &ConnectionInfo, _method: &Method, _path: &str, _status: StatusCode, _metrics: &RequestMetrics) {}
+
+    /// The connection closed after being open for `duration`
+    fn on_disconnect(&self, _connection: &ConnectionInfo, _duration: Duration) {}
+}
+
+/// Calls [`ServerHooks::on_connect`] when created and [`ServerHooks::on_disconnect`] when
+/// dropped, so connections cut off by shutdown are reported too
+pub(crate) struct ConnectionHooks {
+    hooks: Arc<dyn ServerHooks>,
+    connection_info: ConnectionInfo,
+    connected_at: Instant,
+}
+
+impl ConnectionHooks {
+    pub(crate) fn connect(hooks: &Arc<dyn ServerHooks>, connection_info: &ConnectionInfo) -> Self {
+        hooks.on_connect(connection_info);
+        Self {
+            hooks: hooks.clone(),
+            connection_info: connection_info.clone(),
+            connected_at: Instant::now(),
+        }
+    }
+}
+
+impl Drop for ConnectionHooks {
+    fn drop(&mut self) {
+        self.hooks.on_disconnect(&self.connection_info, self.connected_at.elapsed());
+    }
+}
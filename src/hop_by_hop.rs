@@ -0,0 +1,47 @@
+use http::header::{HeaderName, CONNECTION, TE, TRANSFER_ENCODING, UPGRADE};
+use http::HeaderMap;
+
+/// Headers that only describe a single connection and are never forwarded
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Names listed in the `Connection` header, which are hop-by-hop as well
+fn connection_listed(headers: &HeaderMap) -> impl Iterator<Item = &str> {
+    headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+}
+
+/// Hop-by-hop headers: the fixed set plus anything named by `Connection`
+pub(crate) fn is_hop_by_hop(name: &HeaderName, headers: &HeaderMap) -> bool {
+    HOP_BY_HOP.contains(&name.as_str()) || connection_listed(headers).any(|listed| listed.eq_ignore_ascii_case(name.as_str()))
+}
+
+/// Removes the connection-level headers a handler may have copied onto its response, e.g.
+/// from an upstream one, since the server frames and manages the connection itself. `Trailer`
+/// and `Proxy-Authenticate` describe this response to this client and are kept
+pub(crate) fn strip_from_response(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = connection_listed(headers)
+        .filter_map(|listed| HeaderName::from_bytes(listed.as_bytes()).ok())
+        .collect();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in [CONNECTION, TE, TRANSFER_ENCODING, UPGRADE] {
+        headers.remove(name);
+    }
+    headers.remove("keep-alive");
+    headers.remove("proxy-connection");
+}
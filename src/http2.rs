@@ -0,0 +1,338 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_compat::Compat;
+use bytes::Bytes;
+use futures_lite::future;
+use futures_lite::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _, BufReader};
+use futures_lite::StreamExt as _;
+use h2::server::SendResponse;
+use h2::RecvStream;
+use http::header::{HeaderName, CONNECTION, HOST, TRAILER, UPGRADE};
+use http::{HeaderMap, Method, Request, Response, StatusCode, Version};
+use simple_error::SimpleResult;
+
+use crate::body::{declared_trailers, Body, BodyInner, Trailers};
+use crate::cancellation::Cancellation;
+use crate::connection_info::ConnectionInfo;
+use crate::error::{Error, Result};
+use crate::forwarded::insert_connection_extensions;
+use crate::hop_by_hop;
+use crate::metrics::{append_server_timing, RequestMetrics};
+use crate::rewind::Rewind;
+use crate::router::Router;
+use crate::timeout::with_timeout;
+use crate::trace::in_request_span;
+use crate::server::ConnectionConfig;
+
+pub(crate) const ALPN_H2: &[u8] = b"h2";
+
+/// What a client opens an HTTP/2 connection with, ahead of its first SETTINGS frame
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const HTTP2_SETTINGS: HeaderName = HeaderName::from_static("http2-settings");
+
+const FRAME_HEADER_LEN: usize = 9;
+/// The initial SETTINGS_MAX_FRAME_SIZE, which h2 keeps unless configured otherwise
+const MAX_FRAME_SIZE: usize = 16_384;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_CONTINUATION: u8 = 0x9;
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+
+/// Drives an HTTP/2 connection, dispatching every stream to `router` on its own task
+pub(crate) async fn serve_connection<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    router: Arc<Router>,
+    stream: S,
+    config: Arc<ConnectionConfig>,
+    connection_info: ConnectionInfo,
+) -> SimpleResult<()> {
+    let mut connection = h2::server::handshake(Compat::new(stream)).await?;
+
+    // accepting also drives the connection, so this loop must keep running while streams are open
+    let mut going_away = false;
+    loop {
+        let accepted = if going_away {
+            connection.accept().await
+        } else {
+            let accept = async { Some(connection.accept().await) };
+            let draining = async {
+                config.shutdown.draining().await;
+                None
+            };
+            match future::or(accept, draining).await {
+                Some(accepted) => accepted,
+                None => {
+                    // GOAWAY lets in-flight streams finish while the client opens new ones elsewhere
+                    connection.graceful_shutdown();
+                    going_away = true;
+                    continue;
+                }
+            }
+        };
+        let Some(result) = accepted else {
+            break;
+        };
+        let (request, respond) = result?;
+        router.spawner().spawn(Box::pin({
+            let router = router.clone();
+            let config = config.clone();
+            let connection_info = connection_info.clone();
+            async move {
+                if let Err(err) = handle_stream(router, request, respond, config, connection_info).await {
+                    log::error!("error handling http2 stream err = {err:?}");
+                }
+            }
+        }));
+    }
+
+    Ok(())
+}
+
+/// Whether `name` lists `token` among its comma-separated values
+fn lists_token(headers: &HeaderMap, name: HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|listed| listed.trim().eq_ignore_ascii_case(token))
+}
+
+/// Whether `request` asks to switch to HTTP/2 with `Upgrade: h2c` (RFC 7540 section 3.2).
+/// Requests with a body keep HTTP/1.1, as servers may ignore an upgrade: replaying the body
+/// into HTTP/2 could overrun the flow-control window h2 grants the stream
+pub(crate) fn wants_h2c_upgrade(request: &Request<Vec<u8>>) -> bool {
+    let headers = request.headers();
+    request.version() == Version::HTTP_11
+        && request.method() != Method::CONNECT
+        && request.body().is_empty()
+        && lists_token(headers, UPGRADE, "h2c")
+        && lists_token(headers, CONNECTION, "upgrade")
+        && lists_token(headers, CONNECTION, "http2-settings")
+        && headers.get_all(HTTP2_SETTINGS).iter().count() == 1
+}
+
+/// Appends an HPACK literal field without indexing or Huffman coding, which every decoder
+/// accepts and which leaves the dynamic table alone
+fn encode_field(block: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    block.push(0);
+    for string in [name, value] {
+        // string length as an integer with a 7-bit prefix
+        let mut length = string.len();
+        if length < 0x7f {
+            block.push(length as u8);
+        } else {
+            block.push(0x7f);
+            length -= 0x7f;
+            while length >= 0x80 {
+                block.push((length & 0x7f) as u8 | 0x80);
+                length >>= 7;
+            }
+            block.push(length as u8);
+        }
+        block.extend_from_slice(string);
+    }
+}
+
+/// The HEADERS frame, followed by CONTINUATION frames if it doesn't fit in one, that opens
+/// stream 1 with the head of the upgrade request, which the client doesn't send again
+fn upgrade_request_frames(request: &Request<Vec<u8>>) -> Vec<u8> {
+    let headers = request.headers();
+    let authority = match request.uri().authority() {
+        Some(authority) => Some(authority.as_str().as_bytes()),
+        None => headers.get(HOST).map(|host| host.as_bytes()),
+    };
+    let mut block = Vec::new();
+    encode_field(&mut block, b":method", request.method().as_str().as_bytes());
+    encode_field(&mut block, b":scheme", b"http");
+    if let Some(authority) = authority {
+        encode_field(&mut block, b":authority", authority);
+    }
+    encode_field(&mut block, b":path", request.uri().path_and_query().map_or("/", |target| target.as_str()).as_bytes());
+    // connection-specific headers are malformed in HTTP/2, and Host became :authority
+    for (name, value) in headers {
+        if name != HOST && name != HTTP2_SETTINGS && !hop_by_hop::is_hop_by_hop(name, headers) {
+            encode_field(&mut block, name.as_str().as_bytes(), value.as_bytes());
+        }
+    }
+
+    let mut frames = Vec::with_capacity(block.len() + FRAME_HEADER_LEN);
+    let mut fragments = block.chunks(MAX_FRAME_SIZE).peekable();
+    let (mut frame_type, mut flags) = (FRAME_HEADERS, FLAG_END_STREAM);
+    while let Some(fragment) = fragments.next() {
+        if fragments.peek().is_none() {
+            flags |= FLAG_END_HEADERS;
+        }
+        frames.extend_from_slice(&(fragment.len() as u32).to_be_bytes()[1..]);
+        frames.extend_from_slice(&[frame_type, flags]);
+        frames.extend_from_slice(&1u32.to_be_bytes());
+        frames.extend_from_slice(fragment);
+        (frame_type, flags) = (FRAME_CONTINUATION, 0);
+    }
+    frames
+}
+
+/// Reads the client's connection preface, which starts HTTP/2 once it has seen the `101`,
+/// through its SETTINGS frame
+async fn read_preface<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut preface = vec![0; PREFACE.len() + FRAME_HEADER_LEN];
+    reader.read_exact(&mut preface).await?;
+    let frame_header = &preface[PREFACE.len()..];
+    if !preface.starts_with(PREFACE) || frame_header[3] != FRAME_SETTINGS {
+        return Err(Error::parse("h2c upgrade not followed by the HTTP/2 connection preface"));
+    }
+    let length = u32::from_be_bytes([0, frame_header[0], frame_header[1], frame_header[2]]) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(Error::parse("oversized SETTINGS frame in the HTTP/2 connection preface"));
+    }
+    let settings = preface.len();
+    preface.resize(settings + length, 0);
+    reader.read_exact(&mut preface[settings..]).await?;
+    Ok(preface)
+}
+
+/// Switches a plaintext connection to HTTP/2 after [`wants_h2c_upgrade`]: confirms with `101
+/// Switching Protocols`, then serves `request` as stream 1, spliced in after the client's
+/// preface as if it had been sent over HTTP/2, and the client's own streams after it
+pub(crate) async fn serve_upgraded_connection<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    router: Arc<Router>,
+    mut reader: BufReader<S>,
+    request: Request<Vec<u8>>,
+    config: Arc<ConnectionConfig>,
+    connection_info: ConnectionInfo,
+) -> Result<()> {
+    let stream = reader.get_mut();
+    stream.write_all(b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n").await?;
+    stream.flush().await?;
+
+    let mut replayed = with_timeout(config.timeouts.header_read, "h2c preface", read_preface(&mut reader)).await?;
+    replayed.extend_from_slice(&upgrade_request_frames(&request));
+    replayed.extend_from_slice(reader.buffer());
+    serve_connection(router, Rewind::new(replayed, reader.into_inner()), config, connection_info).await.map_err(Error::from)
+}
+
+async fn handle_stream(
+    router: Arc<Router>,
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    config: Arc<ConnectionConfig>,
+    connection_info: ConnectionInfo,
+) -> SimpleResult<()> {
+    let started = Instant::now();
+
+    let (parts, mut body) = request.into_parts();
+    if parts.uri.path_and_query().map_or(0, |target| target.as_str().len()) > config.limits.max_uri_length {
+        respond.send_response(Response::builder().status(StatusCode::URI_TOO_LONG).body(())?, true)?;
+        return Ok(());
+    }
+
+    // Buffer the request body, up to the limit of its route, releasing flow control capacity as we go
+    let max_body_size = router
+        .route_options(&parts.method, parts.uri.path())
+        .max_body_size
+        .unwrap_or(config.limits.max_body_size);
+    let mut request_body = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        body.flow_control().release_capacity(chunk.len())?;
+        request_body.extend_from_slice(&chunk);
+        if request_body.len() > max_body_size {
+            let response = Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .body(())?;
+            respond.send_response(response, true)?;
+            return Ok(());
+        }
+    }
+    let trailers = body.trailers().await?;
+    let mut request = Request::from_parts(parts, request_body);
+    if let Some(trailers) = trailers.filter(|trailers| !trailers.is_empty()) {
+        request.extensions_mut().insert(Trailers(trailers));
+    }
+    insert_connection_extensions(&mut request, &connection_info, config.trusted_proxies.as_ref());
+    if let Some(hooks) = &config.hooks {
+        hooks.on_request(&mut request, &connection_info);
+    }
+
+    let cancellation = Cancellation::default();
+    request.extensions_mut().insert(cancellation.clone());
+
+    // Route requests by method + path; a client that resets the stream no longer wants the
+    // response, so then the handler is dropped
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let bytes_read = request.body().len() as u64;
+    let handler_started = Instant::now();
+    let routed = async { Some(in_request_span(&method, &path, router.route(request)).await) };
+    let reset = async {
+        let _ = future::poll_fn(|cx| respond.poll_reset(cx)).await;
+        None
+    };
+    let Some(response) = future::or(routed, reset).await else {
+        log::debug!("stream reset before the response to {method} {path}");
+        cancellation.cancel();
+        return Ok(());
+    };
+    let mut response = response?;
+    let handler_duration = handler_started.elapsed();
+    if config.server_timing {
+        append_server_timing(response.headers_mut(), handler_duration);
+    }
+
+    // Connection-specific headers are forbidden in HTTP/2
+    let (mut parts, mut response_body) = response.into_parts();
+    parts.version = Version::HTTP_2;
+    hop_by_hop::strip_from_response(&mut parts.headers);
+    config.default_headers.apply(&mut parts.headers);
+
+    let status = parts.status;
+    let response_headers = parts.headers.clone();
+    let trailers = response_body.trailers.take().filter(|_| response_headers.contains_key(TRAILER));
+    let end_of_stream = response_body.content_length() == Some(0) && trailers.is_none();
+    let mut bytes_written = 0;
+    let mut send_stream = respond.send_response(Response::from_parts(parts, ()), end_of_stream)?;
+    if !end_of_stream {
+        // buffered bodies are handed to h2 without another copy; trailers, if any, end the stream instead
+        let end_with_data = trailers.is_none();
+        match response_body.inner {
+            BodyInner::Full(bytes) => {
+                bytes_written = bytes.len() as u64;
+                send_stream.send_data(Bytes::from(bytes), end_with_data)?;
+            }
+            BodyInner::Shared(bytes) => {
+                bytes_written = bytes.len() as u64;
+                send_stream.send_data(Bytes::from_owner(bytes), end_with_data)?;
+            }
+            BodyInner::Bytes(bytes) => {
+                bytes_written = bytes.len() as u64;
+                send_stream.send_data(bytes, end_with_data)?;
+            }
+            inner => {
+                let mut chunks = Body { inner, trailers: None }.into_stream();
+                while let Some(chunk) = chunks.next().await {
+                    let chunk = chunk?;
+                    bytes_written += chunk.len() as u64;
+                    send_stream.send_data(Bytes::from(chunk), false)?;
+                }
+                if end_with_data {
+                    send_stream.send_data(Bytes::new(), true)?;
+                }
+            }
+        }
+        if let Some(trailers) = trailers {
+            send_stream.send_trailers(declared_trailers(&response_headers, trailers.await))?;
+        }
+    }
+    if let Some(hooks) = &config.hooks {
+        let metrics = RequestMetrics {
+            bytes_read,
+            bytes_written,
+            handler_duration,
+            elapsed: started.elapsed(),
+        };
+        hooks.on_response(&connection_info, &method, &path, status, &metrics);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,284 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use bytes::{Buf as _, Bytes};
+use futures_lite::future;
+use futures_lite::StreamExt as _;
+use h3::server::RequestStream;
+use http::header::{HeaderName, TRAILER};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Version};
+use rustls::ServerConfig;
+use simple_error::SimpleResult;
+
+use crate::body::{declared_trailers, Body, BodyInner, Trailers};
+use crate::connection_info::ConnectionInfo;
+use crate::error::Result;
+use crate::forwarded::insert_connection_extensions;
+use crate::hop_by_hop;
+use crate::hooks::ConnectionHooks;
+use crate::metrics::{append_server_timing, RequestMetrics};
+use crate::router::Router;
+use crate::server::ConnectionConfig;
+use crate::trace::{in_connection_span, in_request_span};
+
+pub(crate) const ALPN_H3: &[u8] = b"h3";
+
+type BidiStream = h3_quinn::BidiStream<Bytes>;
+
+// h3 is built on http 0.2 while the rest of the crate is on http 1, so requests, responses and
+// trailers are converted as they cross into and out of it
+
+fn headers_from_h3(headers: &http02::HeaderMap) -> SimpleResult<HeaderMap> {
+    let mut converted = HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        converted.append(HeaderName::from_bytes(name.as_str().as_bytes())?, HeaderValue::from_bytes(value.as_bytes())?);
+    }
+    Ok(converted)
+}
+
+fn headers_to_h3(headers: &HeaderMap) -> SimpleResult<http02::HeaderMap> {
+    let mut converted = http02::HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        converted.append(http02::header::HeaderName::from_bytes(name.as_str().as_bytes())?, http02::HeaderValue::from_bytes(value.as_bytes())?);
+    }
+    Ok(converted)
+}
+
+fn request_from_h3(request: http02::Request<()>) -> SimpleResult<Request<()>> {
+    let (parts, ()) = request.into_parts();
+    let mut request = Request::builder()
+        .method(Method::from_bytes(parts.method.as_str().as_bytes())?)
+        .uri(parts.uri.to_string())
+        .version(Version::HTTP_3)
+        .body(())?;
+    *request.headers_mut() = headers_from_h3(&parts.headers)?;
+    Ok(request)
+}
+
+fn response_to_h3(response: Response<()>) -> SimpleResult<http02::Response<()>> {
+    let (parts, ()) = response.into_parts();
+    let mut response = http02::Response::builder()
+        .status(parts.status.as_u16())
+        .version(http02::Version::HTTP_3)
+        .body(())?;
+    *response.headers_mut() = headers_to_h3(&parts.headers)?;
+    Ok(response)
+}
+
+/// Accepts QUIC connections on `addr` until the server starts draining, serving each on the
+/// router's spawner. `tls_config` is the TCP listeners' config; only its ALPN is changed
+pub(crate) async fn serve(
+    addr: SocketAddr,
+    mut tls_config: ServerConfig,
+    router: Arc<Router>,
+    config: Arc<ConnectionConfig>,
+) -> Result<()> {
+    tls_config.alpn_protocols = vec![ALPN_H3.to_vec()];
+    // QUIC requires either no early data or the maximum
+    tls_config.max_early_data_size = u32::MAX;
+    let endpoint = quinn::Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(tls_config)), addr)?;
+    let local_addr = endpoint.local_addr().ok();
+    log::info!("listening for HTTP/3 on {addr}");
+
+    loop {
+        let accepted = future::or(async { endpoint.accept().await }, async {
+            config.shutdown.draining().await;
+            None
+        });
+        let Some(connecting) = accepted.await else {
+            log::info!("draining, no longer accepting QUIC connections");
+            return Ok(());
+        };
+        let Some(connection_guard) = config.shutdown.track_connection() else {
+            continue;
+        };
+
+        let connection_info = ConnectionInfo {
+            remote_addr: Some(connecting.remote_address()),
+            local_addr,
+            tls: true,
+            alpn_protocol: Some(ALPN_H3.to_vec()),
+            ..ConnectionInfo::default()
+        };
+        router.spawner().spawn(Box::pin({
+            let router = router.clone();
+            let config = config.clone();
+            let span_info = connection_info.clone();
+            in_connection_span(&span_info, async move {
+                let _connection_guard = connection_guard;
+                if let Err(err) = serve_connection(router, connecting, config, connection_info).await {
+                    log::error!("error handling http3 connection err = {err:?}");
+                }
+            })
+        }));
+    }
+}
+
+async fn serve_connection(
+    router: Arc<Router>,
+    connecting: quinn::Connecting,
+    config: Arc<ConnectionConfig>,
+    mut connection_info: ConnectionInfo,
+) -> SimpleResult<()> {
+    let connection = connecting.await?;
+    connection_info.server_name = connection
+        .handshake_data()
+        .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+        .and_then(|data| data.server_name);
+    let mut connection = h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(connection)).await?;
+    let _connection_hooks = config.hooks.as_ref().map(|hooks| ConnectionHooks::connect(hooks, &connection_info));
+
+    let mut going_away = false;
+    loop {
+        let accepted = if going_away {
+            connection.accept().await?
+        } else {
+            let accept = async { Some(connection.accept().await) };
+            let draining = async {
+                config.shutdown.draining().await;
+                None
+            };
+            match future::or(accept, draining).await {
+                Some(accepted) => accepted?,
+                None => {
+                    // GOAWAY lets in-flight requests finish while the client opens new ones elsewhere
+                    connection.shutdown(0).await?;
+                    going_away = true;
+                    continue;
+                }
+            }
+        };
+        let Some((request, stream)) = accepted else {
+            break;
+        };
+        router.spawner().spawn(Box::pin({
+            let router = router.clone();
+            let config = config.clone();
+            let connection_info = connection_info.clone();
+            async move {
+                if let Err(err) = handle_request(router, request, stream, config, connection_info).await {
+                    log::error!("error handling http3 request err = {err:?}");
+                }
+            }
+        }));
+    }
+
+    Ok(())
+}
+
+async fn handle_request(
+    router: Arc<Router>,
+    request: http02::Request<()>,
+    mut stream: RequestStream<BidiStream, Bytes>,
+    config: Arc<ConnectionConfig>,
+    connection_info: ConnectionInfo,
+) -> SimpleResult<()> {
+    let started = Instant::now();
+    let request = request_from_h3(request)?;
+
+    if request.uri().path_and_query().map_or(0, |target| target.as_str().len()) > config.limits.max_uri_length {
+        stream.send_response(response_to_h3(Response::builder().status(StatusCode::URI_TOO_LONG).body(())?)?).await?;
+        stream.finish().await?;
+        return Ok(());
+    }
+
+    // Buffer the request body, up to the limit of the route it is headed for
+    let max_body_size = router
+        .route_options(request.method(), request.uri().path())
+        .max_body_size
+        .unwrap_or(config.limits.max_body_size);
+    let mut request_body = Vec::new();
+    while let Some(mut chunk) = stream.recv_data().await? {
+        while chunk.has_remaining() {
+            let bytes = chunk.chunk();
+            request_body.extend_from_slice(bytes);
+            let read = bytes.len();
+            chunk.advance(read);
+        }
+        if request_body.len() > max_body_size {
+            let response = Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .body(())?;
+            stream.send_response(response_to_h3(response)?).await?;
+            stream.finish().await?;
+            return Ok(());
+        }
+    }
+    let trailers = stream.recv_trailers().await?;
+    let (parts, ()) = request.into_parts();
+    let mut request = Request::from_parts(parts, request_body);
+    if let Some(trailers) = trailers.filter(|trailers| !trailers.is_empty()) {
+        request.extensions_mut().insert(Trailers(headers_from_h3(&trailers)?));
+    }
+    insert_connection_extensions(&mut request, &connection_info, config.trusted_proxies.as_ref());
+    if let Some(hooks) = &config.hooks {
+        hooks.on_request(&mut request, &connection_info);
+    }
+
+    // Route requests by method + path
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let bytes_read = request.body().len() as u64;
+    let handler_started = Instant::now();
+    let mut response = in_request_span(&method, &path, router.route(request)).await?;
+    let handler_duration = handler_started.elapsed();
+    if config.server_timing {
+        append_server_timing(response.headers_mut(), handler_duration);
+    }
+
+    // Connection-specific headers are forbidden in HTTP/3 as in HTTP/2
+    let (mut parts, mut response_body) = response.into_parts();
+    parts.version = Version::HTTP_3;
+    hop_by_hop::strip_from_response(&mut parts.headers);
+    config.default_headers.apply(&mut parts.headers);
+    let status = parts.status;
+    let response_headers = parts.headers.clone();
+    let trailers = response_body.trailers.take().filter(|_| response_headers.contains_key(TRAILER));
+    stream.send_response(response_to_h3(Response::from_parts(parts, ()))?).await?;
+
+    let mut bytes_written = 0;
+    match response_body.inner {
+        BodyInner::Full(bytes) => {
+            bytes_written = bytes.len() as u64;
+            if !bytes.is_empty() {
+                stream.send_data(Bytes::from(bytes)).await?;
+            }
+        }
+        BodyInner::Shared(bytes) => {
+            bytes_written = bytes.len() as u64;
+            if !bytes.is_empty() {
+                stream.send_data(Bytes::from_owner(bytes)).await?;
+            }
+        }
+        BodyInner::Bytes(bytes) => {
+            bytes_written = bytes.len() as u64;
+            if !bytes.is_empty() {
+                stream.send_data(bytes).await?;
+            }
+        }
+        inner => {
+            let mut chunks = Body { inner, trailers: None }.into_stream();
+            while let Some(chunk) = chunks.next().await {
+                let chunk = chunk?;
+                bytes_written += chunk.len() as u64;
+                stream.send_data(Bytes::from(chunk)).await?;
+            }
+        }
+    }
+    if let Some(trailers) = trailers {
+        stream.send_trailers(headers_to_h3(&declared_trailers(&response_headers, trailers.await))?).await?;
+    }
+    stream.finish().await?;
+    if let Some(hooks) = &config.hooks {
+        let metrics = RequestMetrics {
+            bytes_read,
+            bytes_written,
+            handler_duration,
+            elapsed: started.elapsed(),
+        };
+        hooks.on_response(&connection_info, &method, &path, status, &metrics);
+    }
+
+    Ok(())
+}
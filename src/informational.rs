@@ -0,0 +1,42 @@
+use http::header::LINK;
+use http::{HeaderMap, HeaderValue, StatusCode};
+
+/// In the extensions of HTTP/1.1 requests, for sending `1xx` responses ahead of the final
+/// one, e.g. `103 Early Hints` so the client can start fetching stylesheets while the page is
+/// still being rendered. Absent on HTTP/1.0 connections, which can't receive them, and on
+/// HTTP/2 and HTTP/3
+#[derive(Clone)]
+pub struct Informational {
+    sender: async_channel::Sender<(StatusCode, HeaderMap)>,
+}
+
+impl Informational {
+    pub(crate) fn channel() -> (Self, async_channel::Receiver<(StatusCode, HeaderMap)>) {
+        let (sender, receiver) = async_channel::unbounded();
+        (Self { sender }, receiver)
+    }
+
+    /// Queues an informational response; the server writes it before the final response.
+    /// Returns false if `status` isn't `1xx` (other than `101`, which is reserved for
+    /// [`Upgrade`](crate::Upgrade)) or the final response has already been written
+    pub fn send(&self, status: StatusCode, headers: HeaderMap) -> bool {
+        if !status.is_informational() || status == StatusCode::SWITCHING_PROTOCOLS {
+            return false;
+        }
+        self.sender.try_send((status, headers)).is_ok()
+    }
+
+    /// Sends `103 Early Hints` with a `Link` header per value, e.g. `</style.css>; rel=preload; as=style`
+    pub fn early_hints<'l>(&self, links: impl IntoIterator<Item = &'l str>) -> bool {
+        let mut headers = HeaderMap::new();
+        for link in links {
+            match HeaderValue::from_str(link) {
+                Ok(link) => {
+                    headers.append(LINK, link);
+                }
+                Err(_) => log::warn!("invalid Link header value {link:?}"),
+            }
+        }
+        !headers.is_empty() && self.send(StatusCode::EARLY_HINTS, headers)
+    }
+}
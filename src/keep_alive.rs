@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use http::header::CONNECTION;
+use http::{HeaderMap, Request, Response, Version};
+
+#[derive(Clone, Copy, Debug)]
+pub struct KeepAlive {
+    /// Whether connections may be reused for more than one request
+    pub enabled: bool,
+    /// How long an idle connection waits for its next request before being closed
+    pub idle_timeout: Option<Duration>,
+    /// Requests served on one connection before it is closed
+    pub max_requests: Option<usize>,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_timeout: Some(Duration::from_secs(30)),
+            max_requests: Some(1000),
+        }
+    }
+}
+
+impl KeepAlive {
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+}
+
+fn has_connection_token(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+/// Whether the client asked for the connection to stay open after this request
+pub(crate) fn request_wants_keep_alive<B>(request: &Request<B>) -> bool {
+    match request.version() {
+        Version::HTTP_10 => has_connection_token(request.headers(), "keep-alive"),
+        _ => !has_connection_token(request.headers(), "close"),
+    }
+}
+
+/// Whether the handler asked for the connection to be closed after this response
+pub(crate) fn response_wants_close<B>(response: &Response<B>) -> bool {
+    has_connection_token(response.headers(), "close")
+}
@@ -1,7 +1,125 @@
 mod router;
+mod route_tree;
 mod types;
 mod server;
 mod async_connection;
+mod timeout;
+mod limits;
+mod connection_limiter;
+mod keep_alive;
+mod builder;
+mod listener;
+mod socket;
+mod tls;
+mod tls_stream;
+mod connection_info;
+mod cidr;
+mod forwarded;
+mod hop_by_hop;
+mod proxy_protocol;
+mod date;
+mod default_headers;
+mod base64;
+mod middleware;
+mod trace;
+mod body;
+mod error;
+mod chunked;
+mod parse;
+mod proxy;
+mod response;
+mod runtime;
+mod spawner;
+mod shutdown;
+mod health;
+mod hooks;
+mod metrics;
+mod vectored;
+mod rewind;
+mod upgrade;
+mod informational;
+mod cancellation;
+mod websocket;
+mod upload;
+mod mime;
+mod file;
+mod sendfile;
+mod static_files;
+mod conditional;
+mod accept;
+mod template;
+#[cfg(feature = "openapi")]
+mod openapi;
+mod percent;
+mod path_params;
+mod extract;
+mod handler;
+#[cfg(feature = "serde")]
+mod de;
+#[cfg(feature = "http2")]
+mod http2;
+#[cfg(feature = "http3")]
+mod http3;
 
 pub use router::*;
 pub use server::*;
+pub use timeout::{Timeouts, TimeoutError};
+pub use limits::{Limits, LimitError};
+pub use keep_alive::KeepAlive;
+pub use socket::SocketOptions;
+pub use default_headers::DefaultHeaders;
+pub use builder::HttpServerBuilder;
+pub use tls::{PeerCertificates, TlsHandle};
+pub use connection_info::{ConnectionExtensions, ConnectionInfo};
+pub use cidr::IpCidr;
+pub use forwarded::{ClientAddr, TrustedProxies};
+pub use body::{Body, BodyStream, Trailers};
+pub use error::{Error, Result};
+pub use proxy::Proxy;
+pub use path_params::{PathParams, RequestPathExt};
+pub use extract::{FromRequest, State};
+#[cfg(feature = "serde")]
+pub use extract::Query;
+#[cfg(feature = "json")]
+pub use extract::Json;
+pub use handler::{handler, Handler};
+#[cfg(feature = "serde")]
+pub use path_params::Path;
+pub use spawner::{Spawner, ThreadPoolSpawner};
+pub use runtime::spawn_blocking;
+pub use shutdown::ShutdownHandle;
+pub use health::HealthRegistry;
+pub use hooks::ServerHooks;
+pub use metrics::RequestMetrics;
+pub use file::{FileCache, FileResponse};
+pub use static_files::StaticFiles;
+pub use conditional::Validators;
+pub use accept::negotiate;
+pub use template::{Rendered, Template};
+#[cfg(feature = "minijinja")]
+pub use template::MinijinjaTemplate;
+pub use upload::{UploadOptions, UploadedFile, Uploads};
+#[cfg(feature = "signals")]
+pub use shutdown::termination_signal;
+pub use response::{Html, IntoResponse, Redirect, Text};
+#[cfg(feature = "json")]
+pub use response::Ndjson;
+pub use upgrade::Upgrade;
+pub use informational::Informational;
+pub use cancellation::Cancellation;
+pub use websocket::{Message, WebSocket, WebSocketUpgrade};
+pub use async_connection::AsyncConnection;
+pub use middleware::{Middleware, Next};
+pub use middleware::access_log::{AccessLog, AccessLogFormat};
+pub use middleware::conditional::ConditionalGet;
+pub use middleware::connect::ConnectTunnel;
+pub use middleware::early_hints::EarlyHints;
+pub use middleware::ip_filter::IpFilter;
+pub use middleware::response_headers::{MapResponse, ResponseHeaders};
+pub use middleware::cache::ResponseCache;
+#[cfg(feature = "deflate")]
+pub use middleware::compression::Compression;
+pub use middleware::auth::{BasicAuth, BearerAuth, DigestAuth, Identity, TokenVerifier};
+pub use middleware::session::{MemoryStore, RequestSessionExt, Session, SessionData, SessionLayer, SessionStore};
+#[cfg(feature = "jwt")]
+pub use middleware::auth::JwtVerifier;
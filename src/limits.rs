@@ -0,0 +1,84 @@
+use std::fmt;
+
+use futures_lite::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+use http::StatusCode;
+
+use crate::error::{Error, Result};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Longest accepted request line (method, target and version), in bytes
+    pub max_request_line: usize,
+    /// Longest accepted request target, in bytes; longer ones get `414 URI Too Long`
+    pub max_uri_length: usize,
+    /// Combined size of all header lines, in bytes
+    pub max_header_size: usize,
+    /// Number of header fields accepted per request
+    pub max_header_count: usize,
+    /// Largest accepted Content-Length, in bytes
+    pub max_body_size: usize,
+    /// Open connections across all peers; the accept loop waits once this is reached
+    pub max_connections: Option<usize>,
+    /// Open connections from a single IP address; extra connections are closed immediately
+    pub max_connections_per_ip: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_request_line: 8 * 1024,
+            max_uri_length: 8 * 1024,
+            max_header_size: 64 * 1024,
+            max_header_count: 100,
+            max_body_size: 10 * 1024 * 1024,
+            max_connections: Some(10_000),
+            max_connections_per_ip: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LimitError {
+    UriTooLong,
+    HeaderTooLarge,
+    TooManyHeaders,
+    BodyTooLarge,
+}
+
+impl LimitError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            LimitError::UriTooLong => StatusCode::URI_TOO_LONG,
+            LimitError::HeaderTooLarge | LimitError::TooManyHeaders => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            LimitError::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::UriTooLong => write!(f, "request target too long"),
+            LimitError::HeaderTooLarge => write!(f, "request headers too large"),
+            LimitError::TooManyHeaders => write!(f, "too many request headers"),
+            LimitError::BodyTooLarge => write!(f, "request body too large"),
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+/// Reads a single line, which needn't be UTF-8, onto the end of `buf`, failing with `error` once
+/// more than `max` bytes arrive without a newline
+pub(crate) async fn read_until_limited<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    max: usize,
+    error: LimitError,
+) -> Result<usize> {
+    let read = (&mut *reader).take(max as u64 + 1).read_until(b'\n', buf).await?;
+    if read > max && buf.last() != Some(&b'\n') {
+        return Err(Error::from(error));
+    }
+    Ok(read)
+}
@@ -0,0 +1,117 @@
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use async_io::Async;
+
+use crate::async_connection::AsyncConnection;
+use crate::connection_info::ConnectionInfo;
+use crate::sendfile::SendFileSocket;
+use crate::socket::SocketOptions;
+use crate::types::BoxFuture;
+
+/// How long the accept loop waits after running out of file descriptors or memory, for
+/// connections being served to finish and free some
+const EXHAUSTED_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How long to wait before accepting again after `err`, or `None` when the listener itself is
+/// unusable. Most accept failures concern only the connection being accepted, which the peer
+/// may already have given up on, or are resource limits that connections closing will lift
+pub(crate) fn accept_retry_delay(err: &io::Error) -> Option<Duration> {
+    #[cfg(unix)]
+    if let Some(code) = err.raw_os_error() {
+        match code {
+            libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM => return Some(EXHAUSTED_BACKOFF),
+            // network errors pending on the new connection, which Linux reports through accept(2)
+            libc::EPROTO | libc::ENOPROTOOPT | libc::ENETDOWN | libc::ENETUNREACH | libc::EHOSTDOWN | libc::EHOSTUNREACH => {
+                return Some(Duration::ZERO)
+            }
+            _ => {}
+        }
+    }
+    match err.kind() {
+        io::ErrorKind::OutOfMemory => Some(EXHAUSTED_BACKOFF),
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::TimedOut => Some(Duration::ZERO),
+        _ => None,
+    }
+}
+
+/// A bound socket the accept loop can pull connections from
+pub(crate) trait Listener: Send + Sync {
+    type Stream: AsyncConnection + 'static;
+
+    /// Accepts the next connection along with the addresses the transport knows about
+    fn accept_stream(&self) -> BoxFuture<'_, io::Result<(Self::Stream, ConnectionInfo)>>;
+
+    /// Applies the per-connection socket options; transports without any ignore them
+    fn configure_stream(_stream: &Self::Stream, _options: &SocketOptions) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// The socket file bodies can be sent to with `sendfile(2)`, if the transport has one
+    fn sendfile_socket(_stream: &Self::Stream) -> Option<SendFileSocket> {
+        None
+    }
+}
+
+impl Listener for Async<TcpListener> {
+    type Stream = Async<TcpStream>;
+
+    fn accept_stream(&self) -> BoxFuture<'_, io::Result<(Self::Stream, ConnectionInfo)>> {
+        Box::pin(async move {
+            let (stream, peer_addr) = self.accept().await?;
+            let connection_info = ConnectionInfo {
+                remote_addr: Some(peer_addr),
+                local_addr: stream.get_ref().local_addr().ok(),
+                ..ConnectionInfo::default()
+            };
+            Ok((stream, connection_info))
+        })
+    }
+
+    fn configure_stream(stream: &Self::Stream, options: &SocketOptions) -> io::Result<()> {
+        options.configure(socket2::SockRef::from(stream.get_ref()))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn sendfile_socket(stream: &Self::Stream) -> Option<SendFileSocket> {
+        Some(SendFileSocket::new(stream))
+    }
+}
+
+#[cfg(unix)]
+impl Listener for Async<std::os::unix::net::UnixListener> {
+    type Stream = Async<std::os::unix::net::UnixStream>;
+
+    fn accept_stream(&self) -> BoxFuture<'_, io::Result<(Self::Stream, ConnectionInfo)>> {
+        Box::pin(async move {
+            let (stream, _) = self.accept().await?;
+            Ok((stream, ConnectionInfo::default()))
+        })
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Listener for tokio::net::TcpListener {
+    type Stream = async_compat::Compat<tokio::net::TcpStream>;
+
+    fn accept_stream(&self) -> BoxFuture<'_, io::Result<(Self::Stream, ConnectionInfo)>> {
+        Box::pin(async move {
+            let (stream, peer_addr) = self.accept().await?;
+            let connection_info = ConnectionInfo {
+                remote_addr: Some(peer_addr),
+                local_addr: stream.local_addr().ok(),
+                ..ConnectionInfo::default()
+            };
+            Ok((async_compat::Compat::new(stream), connection_info))
+        })
+    }
+
+    fn configure_stream(stream: &Self::Stream, options: &SocketOptions) -> io::Result<()> {
+        options.configure(socket2::SockRef::from(stream.get_ref()))
+    }
+}
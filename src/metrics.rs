@@ -0,0 +1,97 @@
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_lite::io::{AsyncRead, AsyncWrite};
+use http::header::HeaderName;
+use http::{HeaderMap, HeaderValue};
+
+const SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+
+/// Sizes and timings of one request and its response, reported to
+/// [`ServerHooks::on_response`](crate::ServerHooks::on_response)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RequestMetrics {
+    /// Bytes of the request as received. Over HTTP/1 this is the whole message, framing
+    /// included; over HTTP/2 and HTTP/3, whose headers are compressed, only the body
+    pub bytes_read: u64,
+    /// Bytes of the response, counted the same way, plus any 1xx responses before it
+    pub bytes_written: u64,
+    /// Time spent in middleware and the handler
+    pub handler_duration: Duration,
+    /// From when the request started arriving to when the last of the response was handed to
+    /// the connection
+    pub elapsed: Duration,
+}
+
+/// Adds the handler's duration to a `Server-Timing` header, after any metrics the handler
+/// reported itself, so browser devtools show it alongside the network timings
+pub(crate) fn append_server_timing(headers: &mut HeaderMap, handler_duration: Duration) {
+    let value = format!("handler;dur={:.3}", handler_duration.as_secs_f64() * 1000.0);
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.append(SERVER_TIMING, value);
+    }
+}
+
+/// Counts the bytes read from and written to a connection
+pub(crate) struct CountingStream<S> {
+    inner: S,
+    read: u64,
+    written: u64,
+}
+
+impl<S> CountingStream<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self { inner, read: 0, written: 0 }
+    }
+
+    pub(crate) fn bytes_read(&self) -> u64 {
+        self.read
+    }
+
+    pub(crate) fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    /// Counts bytes written to the connection's socket directly, around this stream
+    pub(crate) fn count_written(&mut self, written: u64) {
+        self.written += written;
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(read)) = &poll {
+            self.read += *read as u64;
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = &poll {
+            self.written += *written as u64;
+        }
+        poll
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = &poll {
+            self.written += *written as u64;
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
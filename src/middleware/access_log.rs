@@ -0,0 +1,157 @@
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+use http::header::{HeaderName, REFERER, USER_AGENT};
+use http::{Request, Response, StatusCode};
+use simple_error::SimpleResult;
+
+use crate::body::Body;
+use crate::connection_info::ConnectionInfo;
+use crate::date::DateTime;
+use crate::forwarded::ClientAddr;
+use crate::middleware::{Middleware, Next};
+use crate::types::BoxFuture;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// `host - - [date] "request line" status bytes`
+    #[default]
+    Common,
+    /// Common, plus the `Referer` and `User-Agent` headers
+    Combined,
+    /// One JSON object per line
+    Json,
+}
+
+/// Emits one line per request through the `log` crate under the `http_server::access` target
+#[derive(Clone, Debug, Default)]
+pub struct AccessLog {
+    format: AccessLogFormat,
+}
+
+/// What we need from the request, captured before it moves down the chain
+struct RequestSummary {
+    client_ip: String,
+    method: String,
+    target: String,
+    version: String,
+    referer: String,
+    user_agent: String,
+    body_bytes: usize,
+}
+
+impl RequestSummary {
+    fn new(request: &Request<Vec<u8>>) -> Self {
+        let client_ip = request
+            .extensions()
+            .get::<ClientAddr>()
+            .and_then(|client_addr| client_addr.ip)
+            .or_else(|| {
+                let connection_info = request.extensions().get::<ConnectionInfo>()?;
+                connection_info.remote_addr.map(|remote_addr| remote_addr.ip())
+            })
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let header = |name: HeaderName| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("-")
+                .to_string()
+        };
+
+        Self {
+            client_ip,
+            method: request.method().to_string(),
+            target: request.uri().path_and_query().map(|target| target.to_string()).unwrap_or_else(|| "/".to_string()),
+            version: format!("{:?}", request.version()),
+            referer: header(REFERER),
+            user_agent: header(USER_AGENT),
+            body_bytes: request.body().len(),
+        }
+    }
+}
+
+pub(crate) fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl AccessLog {
+    pub fn new(format: AccessLogFormat) -> Self {
+        Self { format }
+    }
+
+    fn format_line(&self, request: &RequestSummary, status: StatusCode, bytes: usize, latency: Duration) -> String {
+        let now = DateTime::now();
+        match self.format {
+            AccessLogFormat::Common => format!(
+                "{} - - [{}] \"{} {} {}\" {} {}",
+                request.client_ip,
+                now.to_common_log(),
+                request.method,
+                request.target,
+                request.version,
+                status.as_u16(),
+                bytes
+            ),
+            AccessLogFormat::Combined => format!(
+                "{} - - [{}] \"{} {} {}\" {} {} \"{}\" \"{}\"",
+                request.client_ip,
+                now.to_common_log(),
+                request.method,
+                request.target,
+                request.version,
+                status.as_u16(),
+                bytes,
+                request.referer,
+                request.user_agent
+            ),
+            AccessLogFormat::Json => format!(
+                "{{\"time\":\"{}\",\"client_ip\":\"{}\",\"method\":\"{}\",\"path\":\"{}\",\"status\":{},\"bytes_in\":{},\"bytes\":{},\"latency_ms\":{:.3},\"user_agent\":\"{}\"}}",
+                now.to_rfc3339(),
+                json_escape(&request.client_ip),
+                json_escape(&request.method),
+                json_escape(&request.target),
+                status.as_u16(),
+                request.body_bytes,
+                bytes,
+                latency.as_secs_f64() * 1000.0,
+                json_escape(&request.user_agent)
+            ),
+        }
+    }
+}
+
+impl Middleware for AccessLog {
+    fn handle<'a>(&'a self, request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            let summary = RequestSummary::new(&request);
+            let started = Instant::now();
+
+            let result = next.run(request).await;
+
+            let (status, bytes) = match &result {
+                Ok(response) => (response.status(), response.body().content_length().unwrap_or(0) as usize),
+                Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, 0),
+            };
+            log::info!(target: "http_server::access", "{}", self.format_line(&summary, status, bytes, started.elapsed()));
+
+            result
+        })
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use http::{Request, Response, StatusCode, Uri, Version};
+use ring::rand::{SecureRandom as _, SystemRandom};
+use ring::{digest, hmac};
+use simple_error::SimpleResult;
+
+use crate::base64;
+use crate::body::Body;
+use crate::middleware::session::hex;
+use crate::middleware::{Middleware, Next};
+use crate::types::BoxFuture;
+
+/// Who made the request, inserted into request extensions once authentication succeeds
+#[derive(Clone, Debug, Default)]
+pub struct Identity {
+    pub subject: String,
+    pub attributes: HashMap<String, String>,
+}
+
+impl Identity {
+    pub fn new(subject: impl Into<String>) -> Self {
+        Self {
+            subject: subject.into(),
+            attributes: HashMap::new(),
+        }
+    }
+}
+
+/// Compares without short-circuiting, so response timing doesn't reveal how much of a secret matched
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut diff = a.len() ^ b.len();
+    for i in 0..a.len().max(b.len()) {
+        diff |= (a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0)) as usize;
+    }
+    diff == 0
+}
+
+/// Credentials from an `Authorization` header using `scheme`, e.g. the token in `Bearer <token>`
+fn credentials<'r>(request: &'r Request<Vec<u8>>, scheme: &str) -> Option<&'r str> {
+    let value = request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (request_scheme, credentials) = value.split_once(' ')?;
+    request_scheme.eq_ignore_ascii_case(scheme).then(|| credentials.trim())
+}
+
+fn unauthorized(challenge: String) -> SimpleResult<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .version(Version::HTTP_11)
+        .header(WWW_AUTHENTICATE, challenge)
+        .header("Content-Type", "text/plain")
+        .body(Body::from("Unauthorized"))?)
+}
+
+type PasswordLookup = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+/// HTTP Basic authentication. The lookup returns the expected password for a username, which
+/// is then compared in constant time
+pub struct BasicAuth {
+    realm: String,
+    lookup: Arc<PasswordLookup>,
+}
+
+impl BasicAuth {
+    pub fn new(realm: impl Into<String>, lookup: impl Fn(&str) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self {
+            realm: realm.into(),
+            lookup: Arc::new(lookup),
+        }
+    }
+
+    /// A single fixed username and password
+    pub fn with_credentials(realm: impl Into<String>, username: impl Into<String>, password: impl Into<String>) -> Self {
+        let username = username.into();
+        let password = password.into();
+        Self::new(realm, move |candidate| (candidate == username).then(|| password.clone()))
+    }
+
+    fn authenticate(&self, request: &Request<Vec<u8>>) -> Option<Identity> {
+        let decoded = base64::decode(credentials(request, "Basic")?)?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (username, password) = decoded.split_once(':')?;
+        let expected = (self.lookup)(username)?;
+        constant_time_eq(password.as_bytes(), expected.as_bytes()).then(|| Identity::new(username))
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle<'a>(&'a self, mut request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            match self.authenticate(&request) {
+                Some(identity) => {
+                    request.extensions_mut().insert(identity);
+                    next.run(request).await
+                }
+                None => unauthorized(format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm)),
+            }
+        })
+    }
+}
+
+/// Why a Digest `Authorization` header was refused
+enum DigestRejection {
+    Invalid,
+    /// The response was right but computed over an expired nonce, so the client may retry
+    /// without asking the user again
+    StaleNonce,
+}
+
+/// HTTP Digest authentication (RFC 7616) with SHA-256 and `qop=auth`, for clients that won't
+/// send a password over plain HTTP. The lookup returns the expected password for a username.
+/// Nonces are stateless: a timestamp signed with a key generated per instance, accepted until
+/// the nonce lifetime passes
+pub struct DigestAuth {
+    realm: String,
+    lookup: Arc<PasswordLookup>,
+    key: hmac::Key,
+    rng: SystemRandom,
+    opaque: String,
+    nonce_lifetime: Duration,
+}
+
+impl DigestAuth {
+    pub fn new(realm: impl Into<String>, lookup: impl Fn(&str) -> Option<String> + Send + Sync + 'static) -> Self {
+        let rng = SystemRandom::new();
+        let key = hmac::Key::generate(hmac::HMAC_SHA256, &rng).expect("system randomness is unavailable");
+        let mut opaque = [0u8; 16];
+        rng.fill(&mut opaque).expect("system randomness is unavailable");
+        Self {
+            realm: realm.into(),
+            lookup: Arc::new(lookup),
+            key,
+            rng,
+            opaque: hex(&opaque),
+            nonce_lifetime: Duration::from_secs(300),
+        }
+    }
+
+    /// A single fixed username and password
+    pub fn with_credentials(realm: impl Into<String>, username: impl Into<String>, password: impl Into<String>) -> Self {
+        let username = username.into();
+        let password = password.into();
+        Self::new(realm, move |candidate| (candidate == username).then(|| password.clone()))
+    }
+
+    /// How long an issued nonce is accepted, 5 minutes by default
+    pub fn nonce_lifetime(mut self, lifetime: Duration) -> Self {
+        self.nonce_lifetime = lifetime;
+        self
+    }
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+    }
+
+    fn nonce(&self) -> String {
+        let mut salt = [0u8; 8];
+        let _ = self.rng.fill(&mut salt);
+        let payload = format!("{:x}:{}", Self::now(), hex(&salt));
+        let signature = hex(hmac::sign(&self.key, payload.as_bytes()).as_ref());
+        format!("{payload}:{signature}")
+    }
+
+    /// Whether `nonce` was issued by us and is still fresh, or `None` if it's forged
+    fn nonce_is_fresh(&self, nonce: &str) -> Option<bool> {
+        let (payload, signature) = nonce.rsplit_once(':')?;
+        let expected = hex(hmac::sign(&self.key, payload.as_bytes()).as_ref());
+        if !constant_time_eq(signature.as_bytes(), expected.as_bytes()) {
+            return None;
+        }
+        let (issued, _salt) = payload.split_once(':')?;
+        let issued = u64::from_str_radix(issued, 16).ok()?;
+        Some(Self::now().saturating_sub(issued) <= self.nonce_lifetime.as_secs())
+    }
+
+    fn challenge(&self, stale: bool) -> String {
+        let mut challenge = format!(
+            "Digest realm=\"{}\", qop=\"auth\", algorithm=SHA-256, nonce=\"{}\", opaque=\"{}\"",
+            self.realm,
+            self.nonce(),
+            self.opaque
+        );
+        if stale {
+            challenge.push_str(", stale=true");
+        }
+        challenge
+    }
+
+    fn authenticate(&self, request: &Request<Vec<u8>>) -> Result<Identity, DigestRejection> {
+        use DigestRejection::{Invalid, StaleNonce};
+
+        let params = digest_params(credentials(request, "Digest").ok_or(Invalid)?);
+        let param = |name: &str| params.get(name).map(String::as_str).ok_or(Invalid);
+        let (username, nonce, uri, nc, cnonce, response) =
+            (param("username")?, param("nonce")?, param("uri")?, param("nc")?, param("cnonce")?, param("response")?);
+
+        if param("realm")? != self.realm || param("qop")? != "auth" {
+            return Err(Invalid);
+        }
+        if params.get("algorithm").is_some_and(|algorithm| !algorithm.eq_ignore_ascii_case("SHA-256")) {
+            return Err(Invalid);
+        }
+        if params.get("opaque").is_some_and(|opaque| *opaque != self.opaque) {
+            return Err(Invalid);
+        }
+        // the digest covers `uri`, so it must be the target actually requested
+        let target = request.uri().path_and_query().map_or("/", |target| target.as_str());
+        if uri != target && uri.parse::<Uri>().ok().as_ref() != Some(request.uri()) {
+            return Err(Invalid);
+        }
+        let fresh = self.nonce_is_fresh(nonce).ok_or(Invalid)?;
+
+        let password = (self.lookup)(username).ok_or(Invalid)?;
+        let ha1 = sha256_hex(&format!("{username}:{}:{password}", self.realm));
+        let ha2 = sha256_hex(&format!("{}:{uri}", request.method()));
+        let expected = sha256_hex(&format!("{ha1}:{nonce}:{nc}:{cnonce}:auth:{ha2}"));
+        if !constant_time_eq(response.to_ascii_lowercase().as_bytes(), expected.as_bytes()) {
+            return Err(Invalid);
+        }
+        if !fresh {
+            return Err(StaleNonce);
+        }
+        Ok(Identity::new(username))
+    }
+}
+
+fn sha256_hex(input: &str) -> String {
+    hex(digest::digest(&digest::SHA256, input.as_bytes()).as_ref())
+}
+
+/// Splits the `name=value` pairs of a Digest `Authorization` header, unquoting quoted values.
+/// Names are lowercased
+fn digest_params(input: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = input;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+        let Some((name, after)) = rest.split_once('=') else {
+            return params;
+        };
+        let after = after.trim_start();
+        let value = if let Some(quoted) = after.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((index, c)) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next().map(|(_, escaped)| escaped)),
+                    '"' => {
+                        end = index + 1;
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+            rest = &quoted[end..];
+            value
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            rest = &after[end..];
+            after[..end].trim().to_string()
+        };
+        params.insert(name.trim().to_ascii_lowercase(), value);
+    }
+}
+
+impl Middleware for DigestAuth {
+    fn handle<'a>(&'a self, mut request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            match self.authenticate(&request) {
+                Ok(identity) => {
+                    request.extensions_mut().insert(identity);
+                    next.run(request).await
+                }
+                Err(DigestRejection::StaleNonce) => unauthorized(self.challenge(true)),
+                Err(DigestRejection::Invalid) => unauthorized(self.challenge(false)),
+            }
+        })
+    }
+}
+
+/// Validates bearer tokens, returning who they identify
+pub trait TokenVerifier: Send + Sync {
+    fn verify(&self, token: &str) -> Option<Identity>;
+}
+
+impl<F: Fn(&str) -> Option<Identity> + Send + Sync> TokenVerifier for F {
+    fn verify(&self, token: &str) -> Option<Identity> {
+        self(token)
+    }
+}
+
+/// Bearer token authentication (RFC 6750) with a pluggable verifier
+pub struct BearerAuth {
+    realm: String,
+    verifier: Arc<dyn TokenVerifier>,
+}
+
+impl BearerAuth {
+    pub fn new(realm: impl Into<String>, verifier: impl TokenVerifier + 'static) -> Self {
+        Self {
+            realm: realm.into(),
+            verifier: Arc::new(verifier),
+        }
+    }
+}
+
+impl Middleware for BearerAuth {
+    fn handle<'a>(&'a self, mut request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            let Some(token) = credentials(&request, "Bearer") else {
+                return unauthorized(format!("Bearer realm=\"{}\"", self.realm));
+            };
+            match self.verifier.verify(token) {
+                Some(identity) => {
+                    request.extensions_mut().insert(identity);
+                    next.run(request).await
+                }
+                None => unauthorized(format!("Bearer realm=\"{}\", error=\"invalid_token\"", self.realm)),
+            }
+        })
+    }
+}
+
+/// Verifies HMAC signed JWTs, mapping the `sub` claim to [`Identity::subject`] and the other
+/// scalar claims to its attributes
+#[cfg(feature = "jwt")]
+pub struct JwtVerifier {
+    key: jsonwebtoken::DecodingKey,
+    validation: jsonwebtoken::Validation,
+}
+
+#[cfg(feature = "jwt")]
+impl JwtVerifier {
+    pub fn hs256(secret: &[u8]) -> Self {
+        Self {
+            key: jsonwebtoken::DecodingKey::from_secret(secret),
+            validation: jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256),
+        }
+    }
+}
+
+#[cfg(feature = "jwt")]
+impl TokenVerifier for JwtVerifier {
+    fn verify(&self, token: &str) -> Option<Identity> {
+        let token_data = jsonwebtoken::decode::<serde_json::Map<String, serde_json::Value>>(token, &self.key, &self.validation).ok()?;
+        let mut identity = Identity::default();
+        for (name, value) in token_data.claims {
+            let value = match value {
+                serde_json::Value::String(value) => value,
+                serde_json::Value::Number(value) => value.to_string(),
+                serde_json::Value::Bool(value) => value.to_string(),
+                _ => continue,
+            };
+            if name == "sub" {
+                identity.subject = value;
+            } else {
+                identity.attributes.insert(name, value);
+            }
+        }
+        Some(identity)
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use http::header::{HeaderName, AGE, CACHE_CONTROL};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Version};
+use simple_error::SimpleResult;
+
+use crate::body::Body;
+use crate::middleware::{Middleware, Next};
+use crate::types::BoxFuture;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct CacheKey {
+    method: Method,
+    target: String,
+    /// Values of the headers the cache varies on, in configuration order
+    varied: Vec<Option<HeaderValue>>,
+}
+
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    stored: Instant,
+    expires: Instant,
+}
+
+/// Serves repeated GET and HEAD requests from memory. Only buffered 200 responses are stored,
+/// for the `max-age` / `s-maxage` their `Cache-Control` gives or the default TTL otherwise;
+/// `no-store`, `no-cache` and `private` responses are never stored
+pub struct ResponseCache {
+    entries: Mutex<HashMap<CacheKey, CachedResponse>>,
+    ttl: Duration,
+    vary: Vec<HeaderName>,
+    max_entries: usize,
+    max_entry_size: usize,
+}
+
+/// Directives from a `Cache-Control` header, lowercased, with any `=value` kept
+fn cache_directives(headers: &HeaderMap) -> Vec<(String, Option<String>)> {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| match directive.split_once('=') {
+            Some((name, value)) => (name.trim().to_ascii_lowercase(), Some(value.trim().trim_matches('"').to_string())),
+            None => (directive.trim().to_ascii_lowercase(), None),
+        })
+        .collect()
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            vary: Vec::new(),
+            max_entries: 1024,
+            max_entry_size: 1024 * 1024,
+        }
+    }
+
+    /// Keep separate entries per value of `header`, e.g. `Accept-Encoding` or `Authorization`
+    pub fn vary_on(mut self, header: HeaderName) -> Self {
+        self.vary.push(header);
+        self
+    }
+
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Larger response bodies are passed through without being stored
+    pub fn max_entry_size(mut self, max_entry_size: usize) -> Self {
+        self.max_entry_size = max_entry_size;
+        self
+    }
+
+    fn key(&self, request: &Request<Vec<u8>>) -> CacheKey {
+        CacheKey {
+            method: request.method().clone(),
+            target: request.uri().path_and_query().map(|target| target.to_string()).unwrap_or_default(),
+            varied: self.vary.iter().map(|name| request.headers().get(name).cloned()).collect(),
+        }
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Option<Response<Body>> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key).filter(|entry| entry.expires > Instant::now())?;
+        let mut response = Response::new(Body::from(entry.body.clone()));
+        *response.status_mut() = entry.status;
+        *response.version_mut() = Version::HTTP_11;
+        *response.headers_mut() = entry.headers.clone();
+        response.headers_mut().insert(AGE, HeaderValue::from(entry.stored.elapsed().as_secs()));
+        Some(response)
+    }
+
+    /// How long `response` may be stored, or `None` if it mustn't be
+    fn lifetime(&self, response: &Response<Body>) -> Option<Duration> {
+        let directives = cache_directives(response.headers());
+        if directives.iter().any(|(name, _)| matches!(name.as_str(), "no-store" | "no-cache" | "private")) {
+            return None;
+        }
+        let max_age = |directive: &str| {
+            directives
+                .iter()
+                .find(|(name, _)| name == directive)
+                .and_then(|(_, value)| value.as_deref()?.parse::<u64>().ok())
+        };
+        let ttl = max_age("s-maxage").or_else(|| max_age("max-age")).map(Duration::from_secs).unwrap_or(self.ttl);
+        (!ttl.is_zero()).then_some(ttl)
+    }
+
+    fn store(&self, key: CacheKey, response: &Response<Body>, ttl: Duration, body: &[u8]) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            entries.retain(|_, entry| entry.expires > now);
+        }
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            // still full of live entries, so make room by dropping the one closest to expiring
+            let soonest = entries.iter().min_by_key(|(_, entry)| entry.expires).map(|(key, _)| key.clone());
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+        entries.insert(key, CachedResponse {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: body.to_vec(),
+            stored: now,
+            expires: now + ttl,
+        });
+    }
+}
+
+impl Middleware for ResponseCache {
+    fn handle<'a>(&'a self, request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            if !matches!(*request.method(), Method::GET | Method::HEAD) || self.max_entries == 0 {
+                return next.run(request).await;
+            }
+
+            // a client asking for a fresh copy skips the lookup, but still refreshes the entry
+            let key = self.key(&request);
+            let revalidate = cache_directives(request.headers()).iter().any(|(name, _)| name == "no-cache");
+            if !revalidate {
+                if let Some(response) = self.lookup(&key) {
+                    return Ok(response);
+                }
+            }
+
+            let response = next.run(request).await?;
+            if response.status() == StatusCode::OK {
+                let cacheable = response.body().as_bytes().filter(|body| body.len() <= self.max_entry_size);
+                if let (Some(body), Some(ttl)) = (cacheable, self.lifetime(&response)) {
+                    self.store(key, &response, ttl, body);
+                }
+            }
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,294 @@
+use std::io::{self, Write as _};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use futures_lite::Stream;
+use http::header::{ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, VARY};
+use http::{HeaderValue, Method, Request, Response, StatusCode};
+use simple_error::SimpleResult;
+
+use crate::accept::{encoding_quality, quality_list};
+use crate::body::{Body, BodyInner, BodyStream};
+use crate::middleware::{Middleware, Next};
+use crate::types::BoxFuture;
+use crate::upgrade::OnUpgrade;
+
+/// Types that compress well by default; images, video and archives are already compressed
+const DEFAULT_CONTENT_TYPES: [&str; 8] = [
+    "text/",
+    "application/json",
+    "application/x-ndjson",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "image/svg+xml",
+    "application/manifest+json",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Coding {
+    Gzip,
+    Deflate,
+}
+
+impl Coding {
+    fn name(self) -> &'static str {
+        match self {
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        }
+    }
+}
+
+/// A compressor writing into memory; `deflate` in HTTP means the zlib format (RFC 9110 §8.4.1.2)
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(coding: Coding, level: flate2::Compression) -> Self {
+        match coding {
+            Coding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), level)),
+            Coding::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new(), level)),
+        }
+    }
+
+    fn write_all(&mut self, input: &[u8]) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write_all(input),
+            Encoder::Deflate(encoder) => encoder.write_all(input),
+        }
+    }
+
+    /// Sync-flushes everything written so far, so the client can decompress it without
+    /// waiting for more, and takes the output
+    fn flush(&mut self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => {
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Encoder::Deflate(encoder) => {
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Compresses a streamed body chunk by chunk. Output is flushed once `flush_threshold` bytes
+/// of input have gone in since the last flush, and whenever the body has nothing more ready,
+/// so a slow stream like SSE or NDJSON isn't held back inside the compressor
+struct CompressedStream {
+    inner: BodyStream,
+    /// `None` once the body has ended
+    encoder: Option<Encoder>,
+    unflushed: usize,
+    flush_threshold: usize,
+}
+
+impl CompressedStream {
+    fn flush(&mut self) -> io::Result<Vec<u8>> {
+        self.unflushed = 0;
+        match &mut self.encoder {
+            Some(encoder) => encoder.flush(),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl Stream for CompressedStream {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            let Some(encoder) = &mut this.encoder else {
+                return Poll::Ready(None);
+            };
+            match this.inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    if let Err(err) = encoder.write_all(&chunk) {
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                    this.unflushed += chunk.len();
+                    if this.unflushed >= this.flush_threshold {
+                        return Poll::Ready(Some(this.flush()));
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => {
+                    let encoder = this.encoder.take().expect("checked above");
+                    return Poll::Ready(Some(encoder.finish()));
+                }
+                Poll::Pending if this.unflushed > 0 => return Poll::Ready(Some(this.flush())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Compresses response bodies with gzip or deflate, whichever the client's `Accept-Encoding`
+/// prefers, for the content types enabled. Buffered bodies under the minimum size are left
+/// alone; streamed ones are compressed as they are written, flushing as described on
+/// [`Compression::flush_threshold`]
+#[derive(Clone, Debug)]
+pub struct Compression {
+    content_types: Vec<String>,
+    min_size: usize,
+    flush_threshold: usize,
+    level: flate2::Compression,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            content_types: DEFAULT_CONTENT_TYPES.iter().map(|content_type| content_type.to_string()).collect(),
+            min_size: 1024,
+            flush_threshold: 8 * 1024,
+            level: flate2::Compression::default(),
+        }
+    }
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the content types compressed, each matched as a prefix of `Content-Type`,
+    /// e.g. `text/` or `application/json`
+    pub fn content_types<I: IntoIterator<Item = S>, S: Into<String>>(mut self, content_types: I) -> Self {
+        self.content_types = content_types.into_iter().map(|content_type| content_type.into().to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Buffered bodies smaller than this, in bytes, are sent uncompressed; 1 KiB by default
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// How much of a streamed body, in bytes, is compressed before the output is flushed to
+    /// the client, 8 KiB by default. The output is also flushed whenever the stream has
+    /// nothing more ready, so this only batches chunks that arrive back to back; 0 flushes
+    /// after every chunk
+    pub fn flush_threshold(mut self, flush_threshold: usize) -> Self {
+        self.flush_threshold = flush_threshold;
+        self
+    }
+
+    /// 0 (none) to 9 (best), 6 by default
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = flate2::Compression::new(level.min(9));
+        self
+    }
+
+    fn coding<B>(request: &Request<B>) -> Option<Coding> {
+        let accepted = quality_list(request.headers(), ACCEPT_ENCODING);
+        let gzip = encoding_quality(&accepted, "gzip");
+        let deflate = encoding_quality(&accepted, "deflate");
+        match (gzip, deflate) {
+            (gzip, deflate) if gzip > 0.0 && gzip >= deflate => Some(Coding::Gzip),
+            (_, deflate) if deflate > 0.0 => Some(Coding::Deflate),
+            _ => None,
+        }
+    }
+
+    fn should_compress(&self, response: &Response<Body>) -> bool {
+        let status = response.status();
+        if status.is_informational() || status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED || status == StatusCode::PARTIAL_CONTENT {
+            return false;
+        }
+        let headers = response.headers();
+        if headers.contains_key(CONTENT_ENCODING) || headers.contains_key(CONTENT_RANGE) || response.extensions().get::<OnUpgrade>().is_some() {
+            return false;
+        }
+        let no_transform = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"));
+        if no_transform {
+            return false;
+        }
+        let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok()) else {
+            return false;
+        };
+        let content_type = content_type.to_ascii_lowercase();
+        if !self.content_types.iter().any(|prefix| content_type.starts_with(prefix.as_str())) {
+            return false;
+        }
+        match response.body().content_length() {
+            Some(length) => length >= self.min_size as u64,
+            None => true,
+        }
+    }
+
+    fn compress(&self, response: Response<Body>, coding: Coding) -> SimpleResult<Response<Body>> {
+        let (mut parts, mut body) = response.into_parts();
+        let trailers = body.trailers.take();
+        let mut encoder = Encoder::new(coding, self.level);
+        let mut compressed = match body.inner {
+            BodyInner::Full(bytes) => {
+                encoder.write_all(&bytes)?;
+                Body::from(encoder.finish()?)
+            }
+            BodyInner::Shared(bytes) => {
+                encoder.write_all(&bytes)?;
+                Body::from(encoder.finish()?)
+            }
+            #[cfg(feature = "bytes")]
+            BodyInner::Bytes(bytes) => {
+                encoder.write_all(&bytes)?;
+                Body::from(encoder.finish()?)
+            }
+            inner => Body::from_stream(
+                CompressedStream {
+                    inner: Body { inner, trailers: None }.into_stream(),
+                    encoder: Some(encoder),
+                    unflushed: 0,
+                    flush_threshold: self.flush_threshold,
+                },
+                None,
+            ),
+        };
+        compressed.trailers = trailers;
+
+        parts.headers.remove(CONTENT_LENGTH);
+        parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(coding.name()));
+        parts.headers.append(VARY, HeaderValue::from_static("Accept-Encoding"));
+        // the compressed representation isn't byte for byte what a strong validator promised
+        if let Some(etag) = parts.headers.get(ETAG).filter(|etag| !etag.as_bytes().starts_with(b"W/")) {
+            let mut weak = b"W/".to_vec();
+            weak.extend_from_slice(etag.as_bytes());
+            if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+                parts.headers.insert(ETAG, weak);
+            }
+        }
+        Ok(Response::from_parts(parts, compressed))
+    }
+}
+
+impl Middleware for Compression {
+    fn handle<'a>(&'a self, request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            let coding = if *request.method() == Method::HEAD { None } else { Self::coding(&request) };
+            let response = next.run(request).await?;
+            match coding {
+                Some(coding) if self.should_compress(&response) => self.compress(response, coding),
+                _ => Ok(response),
+            }
+        })
+    }
+}
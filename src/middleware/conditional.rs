@@ -0,0 +1,38 @@
+use http::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use http::{HeaderMap, Request, Response, StatusCode};
+use simple_error::SimpleResult;
+
+use crate::body::Body;
+use crate::conditional::{not_modified_response, Validators};
+use crate::middleware::{Middleware, Next};
+use crate::types::BoxFuture;
+
+/// Turns a 200 response into a 304 when the request's `If-None-Match` or `If-Modified-Since`
+/// shows the client already has it, judged by the `ETag` and `Last-Modified` headers the
+/// handler set. [`FileResponse`](crate::FileResponse) sets both automatically
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConditionalGet;
+
+impl Middleware for ConditionalGet {
+    fn handle<'a>(&'a self, request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            let method = request.method().clone();
+            let mut conditions = HeaderMap::new();
+            for name in [IF_NONE_MATCH, IF_MODIFIED_SINCE] {
+                for value in request.headers().get_all(&name) {
+                    conditions.append(name.clone(), value.clone());
+                }
+            }
+
+            let response = next.run(request).await?;
+            if conditions.is_empty() || response.status() != StatusCode::OK {
+                return Ok(response);
+            }
+            if Validators::from_headers(response.headers()).is_fresh(&method, &conditions) {
+                // the body is dropped unread, so a streamed file is never read from disk
+                return Ok(not_modified_response(response.headers()));
+            }
+            Ok(response)
+        })
+    }
+}
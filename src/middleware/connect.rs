@@ -0,0 +1,110 @@
+use std::net::{SocketAddr, ToSocketAddrs as _};
+use std::time::Duration;
+
+use futures_lite::future;
+use futures_lite::io::{self, AsyncWriteExt as _};
+use http::{Method, Request, Response, StatusCode};
+use simple_error::SimpleResult;
+
+use crate::async_connection::AsyncConnection;
+use crate::body::Body;
+use crate::error::{Error, Result};
+use crate::middleware::{Middleware, Next};
+use crate::response::{IntoResponse as _, Text};
+use crate::runtime;
+use crate::timeout::with_timeout;
+use crate::types::BoxFuture;
+use crate::upgrade::OnUpgrade;
+
+type Policy = dyn Fn(&str, u16) -> bool + Send + Sync;
+
+/// Answers `CONNECT host:port` by opening a TCP connection upstream and piping bytes both
+/// ways, so the server can act as a forward proxy for TLS tunnels. Only targets `allow` accepts
+/// are dialed, e.g. `ConnectTunnel::new(|host, port| port == 443 && host.ends_with(".example.com"))`;
+/// anything else gets a 403. Other methods pass through untouched
+pub struct ConnectTunnel {
+    allow: Box<Policy>,
+    connect_timeout: Duration,
+}
+
+impl ConnectTunnel {
+    pub fn new(allow: impl Fn(&str, u16) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            allow: Box::new(allow),
+            connect_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// How long dialing the upstream may take before the client gets a 504; 10 seconds by default
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    async fn connect(host: &str, port: u16) -> Result<Box<dyn AsyncConnection>> {
+        // name resolution blocks, so keep it off the async threads
+        let target = (host.to_string(), port);
+        let addrs: Vec<SocketAddr> = blocking::unblock(move || (target.0.as_str(), target.1).to_socket_addrs().map(|addrs| addrs.collect())).await?;
+
+        let mut last_err = None;
+        for addr in addrs {
+            match runtime::connect_tcp(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Upstream host did not resolve")).into())
+    }
+}
+
+/// Copies bytes both ways until each side has finished sending, passing on half-closes
+async fn pipe(client: Box<dyn AsyncConnection>, upstream: Box<dyn AsyncConnection>) {
+    let (mut client_reader, mut client_writer) = io::split(client);
+    let (mut upstream_reader, mut upstream_writer) = io::split(upstream);
+    let to_upstream = async {
+        let copied = io::copy(&mut client_reader, &mut upstream_writer).await;
+        let _ = upstream_writer.close().await;
+        copied
+    };
+    let to_client = async {
+        let copied = io::copy(&mut upstream_reader, &mut client_writer).await;
+        let _ = client_writer.close().await;
+        copied
+    };
+    match future::zip(to_upstream, to_client).await {
+        (Ok(sent), Ok(received)) => log::debug!("tunnel closed after {sent} bytes up, {received} bytes down"),
+        (Err(err), _) | (_, Err(err)) => log::debug!("tunnel closed err = {err:?}"),
+    }
+}
+
+impl Middleware for ConnectTunnel {
+    fn handle<'a>(&'a self, request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            if request.method() != Method::CONNECT {
+                return next.run(request).await;
+            }
+
+            // CONNECT targets are always `host:port`
+            let Some((host, port)) = request.uri().authority().and_then(|authority| Some((authority.host().to_string(), authority.port_u16()?))) else {
+                return Ok(Error::parse("CONNECT target must be host:port").into_response());
+            };
+            if !(self.allow)(&host, port) {
+                log::warn!("CONNECT to {host}:{port} refused by policy");
+                return Ok((StatusCode::FORBIDDEN, Text("Forbidden")).into_response());
+            }
+
+            let upstream = match with_timeout(Some(self.connect_timeout), "CONNECT upstream", Self::connect(&host, port)).await {
+                Ok(upstream) => upstream,
+                Err(Error::Timeout(_)) => return Ok((StatusCode::GATEWAY_TIMEOUT, Text("Gateway Timeout")).into_response()),
+                Err(err) => {
+                    log::warn!("CONNECT to {host}:{port} failed err = {err:?}");
+                    return Ok((StatusCode::BAD_GATEWAY, Text("Bad Gateway")).into_response());
+                }
+            };
+
+            let mut response = StatusCode::OK.into_response();
+            response.extensions_mut().insert(OnUpgrade::new(move |client| pipe(client, upstream)));
+            Ok(response)
+        })
+    }
+}
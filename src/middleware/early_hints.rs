@@ -0,0 +1,47 @@
+use http::{Method, Request, Response};
+use simple_error::SimpleResult;
+
+use crate::body::Body;
+use crate::informational::Informational;
+use crate::middleware::{Middleware, Next};
+use crate::types::BoxFuture;
+
+/// Sends `103 Early Hints` for `GET` requests under a path prefix before running the handler,
+/// e.g. `EarlyHints::new().preload("/", "</app.css>; rel=preload; as=style")` so browsers fetch
+/// the stylesheet while the page renders. Only HTTP/1.1 clients get the hints
+#[derive(Clone, Debug, Default)]
+pub struct EarlyHints {
+    /// Path prefixes with the `Link` values sent for them
+    links: Vec<(String, String)>,
+}
+
+impl EarlyHints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hints `link` to requests whose path starts with `path_prefix`
+    pub fn preload(mut self, path_prefix: impl Into<String>, link: impl Into<String>) -> Self {
+        self.links.push((path_prefix.into(), link.into()));
+        self
+    }
+}
+
+impl Middleware for EarlyHints {
+    fn handle<'a>(&'a self, request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            if request.method() == Method::GET {
+                if let Some(informational) = request.extensions().get::<Informational>() {
+                    let path = request.uri().path();
+                    let links = self
+                        .links
+                        .iter()
+                        .filter(|(path_prefix, _)| path.starts_with(path_prefix.as_str()))
+                        .map(|(_, link)| link.as_str());
+                    informational.early_hints(links);
+                }
+            }
+            next.run(request).await
+        })
+    }
+}
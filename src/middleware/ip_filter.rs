@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use http::{Request, Response, StatusCode};
+use simple_error::SimpleResult;
+
+use crate::body::Body;
+use crate::cidr::IpCidr;
+use crate::connection_info::ConnectionInfo;
+use crate::forwarded::ClientAddr;
+use crate::middleware::{Middleware, Next};
+use crate::response::{IntoResponse as _, Text};
+use crate::types::BoxFuture;
+
+#[derive(Default)]
+struct Rules {
+    allow: Vec<IpCidr>,
+    deny: Vec<IpCidr>,
+    /// Addresses banned at runtime, until the given time or for good
+    banned: HashMap<IpAddr, Option<Instant>>,
+}
+
+/// CIDR allow and deny lists plus runtime bans, e.g.
+/// `IpFilter::new().allow("10.0.0.0/8".parse()?)` for an internal-only admin router. As
+/// middleware it answers disallowed clients with 403, judging by the [`ClientAddr`] resolved
+/// through trusted proxies; given to [`HttpServerBuilder::ip_filter`](crate::HttpServerBuilder::ip_filter)
+/// it drops disallowed peers as soon as they connect, before any HTTP is parsed. Clones share
+/// the same lists, so bans made through one take effect everywhere
+#[derive(Clone, Default)]
+pub struct IpFilter {
+    rules: Arc<RwLock<Rules>>,
+}
+
+impl IpFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Once anything is allowed, addresses outside every allowed network are refused
+    pub fn allow(self, network: IpCidr) -> Self {
+        self.rules.write().unwrap().allow.push(network);
+        self
+    }
+
+    /// Refused even if also allowed
+    pub fn deny(self, network: IpCidr) -> Self {
+        self.rules.write().unwrap().deny.push(network);
+        self
+    }
+
+    /// Refuses `ip` for `duration`, or until [`IpFilter::unban`] without one
+    pub fn ban(&self, ip: IpAddr, duration: Option<Duration>) {
+        let until = duration.map(|duration| Instant::now() + duration);
+        self.rules.write().unwrap().banned.insert(ip.to_canonical(), until);
+    }
+
+    pub fn unban(&self, ip: IpAddr) {
+        self.rules.write().unwrap().banned.remove(&ip.to_canonical());
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        let rules = self.rules.read().unwrap();
+        match rules.banned.get(&ip) {
+            Some(None) => return false,
+            Some(Some(until)) if *until > Instant::now() => return false,
+            _ => {}
+        }
+        if rules.deny.iter().any(|network| network.contains(ip)) {
+            return false;
+        }
+        rules.allow.is_empty() || rules.allow.iter().any(|network| network.contains(ip))
+    }
+}
+
+impl Middleware for IpFilter {
+    fn handle<'a>(&'a self, request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            let client_ip = request
+                .extensions()
+                .get::<ClientAddr>()
+                .and_then(|client_addr| client_addr.ip)
+                .or_else(|| request.extensions().get::<ConnectionInfo>()?.remote_addr.map(|remote_addr| remote_addr.ip()));
+            // transports without addresses, like Unix sockets, are local and not filtered
+            if let Some(ip) = client_ip {
+                if !self.is_allowed(ip) {
+                    log::warn!("refused request from {ip}");
+                    return Ok((StatusCode::FORBIDDEN, Text("Forbidden")).into_response());
+                }
+            }
+            next.run(request).await
+        })
+    }
+}
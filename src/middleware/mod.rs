@@ -0,0 +1,129 @@
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+use futures_lite::FutureExt as _;
+
+use http::{Request, Response, StatusCode, Version};
+use simple_error::SimpleResult;
+
+use crate::body::Body;
+use crate::error::Error;
+use crate::router::RouteHandler;
+use crate::spawner::Spawner;
+use crate::types::BoxFuture;
+
+pub mod access_log;
+pub mod auth;
+pub mod cache;
+#[cfg(feature = "deflate")]
+pub mod compression;
+pub mod conditional;
+pub mod connect;
+pub mod early_hints;
+pub mod ip_filter;
+pub mod response_headers;
+pub mod session;
+
+/// A layer wrapped around every request, whether or not a route matched. Middleware runs in
+/// the order it was added to the [`Router`](crate::Router); call `next.run(request)` to pass
+/// the request inwards, or return a response directly to short-circuit
+pub trait Middleware: Send + Sync {
+    fn handle<'a>(&'a self, request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>>;
+
+    /// How the layer shows up in [`Router::routes`](crate::Router::routes)
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// The rest of the middleware chain, ending in the matched route handler
+pub struct Next<'a> {
+    pub(crate) spawner: &'a Arc<dyn Spawner>,
+    pub(crate) middleware: &'a [Arc<dyn Middleware>],
+    /// The handler along with the route it was registered under
+    pub(crate) handler: Option<(&'a RouteHandler, &'a str)>,
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+impl<'a> Next<'a> {
+    pub fn run(self, request: Request<Vec<u8>>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => middleware.handle(request, Next { middleware: rest, ..self }),
+            None => Box::pin(self.call_handler(request)),
+        }
+    }
+
+    async fn call_handler(self, request: Request<Vec<u8>>) -> SimpleResult<Response<Body>> {
+        let Some((handler, route)) = self.handler else {
+            // No matching route found
+            log::warn!("Route not found: ({:?}, {})", request.method(), request.uri().path());
+            let response_body = "Not Found".to_string();
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .version(Version::HTTP_11)
+                .header("Content-Type", "text/plain")
+                .header("Content-Length", response_body.len().to_string())
+                .body(Body::from(response_body))
+                .unwrap());
+        };
+
+        let method = request.method().clone();
+        // a panicking handler only loses its own request rather than the whole connection task
+        let result = match AssertUnwindSafe(async { handler(self.spawner.clone(), request).await }).catch_unwind().await {
+            Ok(result) => result,
+            Err(payload) => {
+                log::error!("Handler panicked: ({:?}, {}) {}", method, route, panic_message(payload.as_ref()));
+                let response_body = "Internal Server Error".to_string();
+                return Ok(Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .version(Version::HTTP_11)
+                    .header("Content-Type", "text/plain")
+                    .header("Content-Length", response_body.len().to_string())
+                    .body(Body::from(response_body))
+                    .unwrap());
+            }
+        };
+
+        match result {
+            Ok(response) => {
+                log::debug!("Response: {:?}", response);
+                Ok(response)
+            },
+            Err(err) => {
+                // Crate errors such as a rejected path param keep their client error status, e.g. 400
+                let rejection = err.downcast_ref::<Error>().map(Error::status).filter(StatusCode::is_client_error);
+                if let Some(status) = rejection {
+                    log::debug!("Rejected request: {}", err);
+                    let response_body = err.to_string();
+                    return Ok(Response::builder()
+                        .status(status)
+                        .version(Version::HTTP_11)
+                        .header("Content-Type", "text/plain")
+                        .header("Content-Length", response_body.len().to_string())
+                        .body(Body::from(response_body))
+                        .unwrap());
+                }
+
+                log::error!("Controller error: {:?}", err);
+                let response_body = format!("{:?}", err);
+                Ok(Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .version(Version::HTTP_11)
+                    .header("Content-Type", "text/plain")
+                    .header("Content-Length", response_body.len().to_string())
+                    .body(Body::from(response_body))
+                    .unwrap())
+            },
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use http::header::HeaderName;
+use http::{HeaderValue, Request, Response};
+use simple_error::SimpleResult;
+
+use crate::body::Body;
+use crate::middleware::{Middleware, Next};
+use crate::types::BoxFuture;
+
+/// Runs a function over every response once the handler and inner middleware are done, before
+/// it is written, with the head of the request it answers, e.g.
+/// `MapResponse::new(|request, response| { if request.uri().path().starts_with("/api") { ... } })`.
+/// The building block for layers such as CORS or security headers
+pub struct MapResponse<F> {
+    map: F,
+}
+
+impl<F> MapResponse<F>
+where
+    F: Fn(&Request<()>, &mut Response<Body>) + Send + Sync,
+{
+    pub fn new(map: F) -> Self {
+        Self { map }
+    }
+}
+
+impl<F> Middleware for MapResponse<F>
+where
+    F: Fn(&Request<()>, &mut Response<Body>) + Send + Sync,
+{
+    fn handle<'a>(&'a self, request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            let mut head = Request::new(());
+            *head.method_mut() = request.method().clone();
+            *head.uri_mut() = request.uri().clone();
+            *head.version_mut() = request.version();
+            *head.headers_mut() = request.headers().clone();
+
+            let mut response = next.run(request).await?;
+            (self.map)(&head, &mut response);
+            Ok(response)
+        })
+    }
+
+    fn name(&self) -> &str {
+        "MapResponse"
+    }
+}
+
+#[derive(Clone, Debug)]
+enum HeaderEdit {
+    Set(HeaderName, HeaderValue),
+    SetIfAbsent(HeaderName, HeaderValue),
+    Append(HeaderName, HeaderValue),
+    Remove(HeaderName),
+}
+
+/// Sets, appends or removes response headers, applied in the order given, e.g.
+/// `ResponseHeaders::new().set_if_absent(CACHE_CONTROL, HeaderValue::from_static("no-store")).remove(SERVER)`.
+/// Headers the server adds itself, like `Date` and `Server` from
+/// [`DefaultHeaders`](crate::DefaultHeaders), come later and are only added when still absent
+#[derive(Clone, Debug, Default)]
+pub struct ResponseHeaders {
+    edits: Vec<HeaderEdit>,
+}
+
+impl ResponseHeaders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces any values the handler set for `name`
+    pub fn set(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.edits.push(HeaderEdit::Set(name, value));
+        self
+    }
+
+    /// Sets `name` unless the handler already did
+    pub fn set_if_absent(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.edits.push(HeaderEdit::SetIfAbsent(name, value));
+        self
+    }
+
+    /// Adds a value for `name`, keeping any already there
+    pub fn append(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.edits.push(HeaderEdit::Append(name, value));
+        self
+    }
+
+    pub fn remove(mut self, name: HeaderName) -> Self {
+        self.edits.push(HeaderEdit::Remove(name));
+        self
+    }
+
+    fn apply(&self, response: &mut Response<Body>) {
+        let headers = response.headers_mut();
+        for edit in &self.edits {
+            match edit {
+                HeaderEdit::Set(name, value) => {
+                    headers.insert(name.clone(), value.clone());
+                }
+                HeaderEdit::SetIfAbsent(name, value) => {
+                    if !headers.contains_key(name) {
+                        headers.insert(name.clone(), value.clone());
+                    }
+                }
+                HeaderEdit::Append(name, value) => {
+                    headers.append(name.clone(), value.clone());
+                }
+                HeaderEdit::Remove(name) => {
+                    headers.remove(name);
+                }
+            }
+        }
+    }
+}
+
+impl Middleware for ResponseHeaders {
+    fn handle<'a>(&'a self, request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            let mut response = next.run(request).await?;
+            self.apply(&mut response);
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use http::header::{COOKIE, SET_COOKIE};
+use http::{HeaderValue, Request, Response};
+use ring::hmac;
+use ring::rand::{SecureRandom as _, SystemRandom};
+use simple_error::{box_err, SimpleResult};
+
+use crate::body::Body;
+use crate::middleware::{Middleware, Next};
+use crate::types::BoxFuture;
+
+pub type SessionData = HashMap<String, String>;
+
+/// Persists session data by session ID
+pub trait SessionStore: Send + Sync {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, SimpleResult<Option<SessionData>>>;
+    fn save<'a>(&'a self, id: &'a str, data: SessionData, ttl: Duration) -> BoxFuture<'a, SimpleResult<()>>;
+    fn destroy<'a>(&'a self, id: &'a str) -> BoxFuture<'a, SimpleResult<()>>;
+}
+
+/// Process-local store; sessions are lost on restart and not shared between instances
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (SessionData, Instant)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, SimpleResult<Option<SessionData>>> {
+        Box::pin(async move {
+            let mut sessions = self.sessions.lock().unwrap();
+            // drop expired sessions lazily as they're looked up
+            sessions.retain(|_, (_, expires_at)| *expires_at > Instant::now());
+            Ok(sessions.get(id).map(|(data, _)| data.clone()))
+        })
+    }
+
+    fn save<'a>(&'a self, id: &'a str, data: SessionData, ttl: Duration) -> BoxFuture<'a, SimpleResult<()>> {
+        Box::pin(async move {
+            let expires_at = Instant::now() + ttl;
+            self.sessions.lock().unwrap().insert(id.to_string(), (data, expires_at));
+            Ok(())
+        })
+    }
+
+    fn destroy<'a>(&'a self, id: &'a str) -> BoxFuture<'a, SimpleResult<()>> {
+        Box::pin(async move {
+            self.sessions.lock().unwrap().remove(id);
+            Ok(())
+        })
+    }
+}
+
+#[derive(Default)]
+struct SessionState {
+    data: SessionData,
+    modified: bool,
+    destroyed: bool,
+}
+
+/// The current request's session, available from request extensions or [`RequestSessionExt`].
+/// Clones share state, so changes made by a handler are saved once it returns
+#[derive(Clone, Default)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+impl Session {
+    fn from_data(data: SessionData) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SessionState {
+                data,
+                ..SessionState::default()
+            })),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state.lock().unwrap().data.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: impl Into<String>, value: impl Into<String>) {
+        let mut state = self.state.lock().unwrap();
+        state.data.insert(key.into(), value.into());
+        state.modified = true;
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        state.modified = true;
+        state.data.remove(key)
+    }
+
+    /// Deletes the session from the store and expires the cookie, e.g. on logout
+    pub fn destroy(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.data.clear();
+        state.destroyed = true;
+    }
+}
+
+pub trait RequestSessionExt {
+    /// The session, if a [`SessionLayer`] is installed
+    fn session(&self) -> Option<&Session>;
+}
+
+impl<B> RequestSessionExt for Request<B> {
+    fn session(&self) -> Option<&Session> {
+        self.extensions().get::<Session>()
+    }
+}
+
+/// Loads a [`Session`] for every request from a signed session ID cookie and saves it after
+/// the handler runs
+pub struct SessionLayer {
+    store: Arc<dyn SessionStore>,
+    key: hmac::Key,
+    rng: SystemRandom,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(output, "{byte:02x}");
+    }
+    output
+}
+
+fn unhex(input: &str) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..input.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(input.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Looks a cookie up by name across all `Cookie` headers
+pub(crate) fn cookie_value<'r, B>(request: &'r Request<B>, name: &str) -> Option<&'r str> {
+    request
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value)
+}
+
+impl SessionLayer {
+    /// `secret` signs session cookies and should be at least 32 random bytes
+    pub fn new(store: impl SessionStore + 'static, secret: &[u8]) -> Self {
+        Self {
+            store: Arc::new(store),
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+            rng: SystemRandom::new(),
+            cookie_name: "session_id".to_string(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            secure: false,
+        }
+    }
+
+    pub fn with_cookie_name(mut self, cookie_name: impl Into<String>) -> Self {
+        self.cookie_name = cookie_name.into();
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Only send the cookie over HTTPS
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    fn sign(&self, id: &str) -> String {
+        format!("{id}.{}", hex(hmac::sign(&self.key, id.as_bytes()).as_ref()))
+    }
+
+    /// The session ID from a cookie value, if its signature checks out
+    fn verify<'v>(&self, cookie_value: &'v str) -> Option<&'v str> {
+        let (id, signature) = cookie_value.split_once('.')?;
+        hmac::verify(&self.key, id.as_bytes(), &unhex(signature)?).ok()?;
+        Some(id)
+    }
+
+    fn generate_id(&self) -> SimpleResult<String> {
+        let mut id = [0u8; 32];
+        self.rng.fill(&mut id).map_err(|_| box_err!("Failed to generate session ID"))?;
+        Ok(hex(&id))
+    }
+
+    fn cookie(&self, value: &str, max_age: u64) -> SimpleResult<HeaderValue> {
+        let mut cookie = format!("{}={value}; Path=/; Max-Age={max_age}; HttpOnly; SameSite=Lax", self.cookie_name);
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        Ok(HeaderValue::from_str(&cookie)?)
+    }
+}
+
+impl Middleware for SessionLayer {
+    fn handle<'a>(&'a self, mut request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+        Box::pin(async move {
+            // resume the session named by a validly signed cookie, if it still exists
+            let existing_id = cookie_value(&request, &self.cookie_name)
+                .and_then(|value| self.verify(value))
+                .map(|id| id.to_string());
+            let data = match &existing_id {
+                Some(id) => self.store.load(id).await?,
+                None => None,
+            };
+            let existing_id = existing_id.filter(|_| data.is_some());
+            let session = Session::from_data(data.unwrap_or_default());
+            request.extensions_mut().insert(session.clone());
+
+            let mut response = next.run(request).await?;
+
+            let (data, modified, destroyed) = {
+                let state = session.state.lock().unwrap();
+                (state.data.clone(), state.modified, state.destroyed)
+            };
+            if destroyed {
+                if let Some(id) = &existing_id {
+                    self.store.destroy(id).await?;
+                    response.headers_mut().append(SET_COOKIE, self.cookie("", 0)?);
+                }
+            } else if modified {
+                let id = match existing_id {
+                    Some(id) => id,
+                    None => self.generate_id()?,
+                };
+                self.store.save(&id, data, self.ttl).await?;
+                response.headers_mut().append(SET_COOKIE, self.cookie(&self.sign(&id), self.ttl.as_secs())?);
+            }
+
+            Ok(response)
+        })
+    }
+}
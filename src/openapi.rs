@@ -0,0 +1,99 @@
+use schemars::gen::SchemaSettings;
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+use crate::router::RouteMeta;
+
+/// A JSON schema for a request or response body, with the definitions it refers to through
+/// `#/components/schemas/`
+#[derive(Clone, Debug)]
+pub(crate) struct Schema {
+    schema: Value,
+    definitions: Map<String, Value>,
+}
+
+impl Schema {
+    pub(crate) fn of<T: JsonSchema>() -> Self {
+        let mut generator = SchemaSettings::openapi3().into_generator();
+        let schema = generator.subschema_for::<T>();
+        let definitions = generator
+            .take_definitions()
+            .into_iter()
+            .map(|(name, schema)| (name, serde_json::to_value(schema).unwrap_or(Value::Null)))
+            .collect();
+        Self {
+            schema: serde_json::to_value(schema).unwrap_or(Value::Null),
+            definitions,
+        }
+    }
+}
+
+/// One route as the document sees it
+pub(crate) struct Operation<'r> {
+    pub method: &'r str,
+    pub path: &'r str,
+    pub path_params: &'r [String],
+    pub meta: &'r RouteMeta,
+}
+
+/// `/users/:id` and `/files/*path` become `/users/{id}` and `/files/{path}`
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':').or_else(|| segment.strip_prefix('*')) {
+            Some(name) => format!("{{{name}}}"),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn json_content(schema: &Schema) -> Value {
+    json!({ "application/json": { "schema": schema.schema } })
+}
+
+/// An OpenAPI 3.0 document describing `operations`
+pub(crate) fn document(title: &str, version: &str, operations: &[Operation<'_>]) -> Value {
+    let mut paths = Map::new();
+    let mut schemas = Map::new();
+    for operation in operations {
+        let meta = operation.meta;
+        let mut entry = Map::new();
+        if let Some(summary) = &meta.summary {
+            entry.insert("summary".to_string(), json!(summary));
+        }
+        if let Some(description) = &meta.description {
+            entry.insert("description".to_string(), json!(description));
+        }
+        if !meta.tags.is_empty() {
+            entry.insert("tags".to_string(), json!(meta.tags));
+        }
+        if !operation.path_params.is_empty() {
+            let parameters = operation
+                .path_params
+                .iter()
+                .map(|name| json!({ "name": name, "in": "path", "required": true, "schema": { "type": "string" } }))
+                .collect::<Vec<_>>();
+            entry.insert("parameters".to_string(), Value::Array(parameters));
+        }
+        if let Some(schema) = &meta.request_schema {
+            entry.insert("requestBody".to_string(), json!({ "required": true, "content": json_content(schema) }));
+            schemas.extend(schema.definitions.clone());
+        }
+        let mut ok = json!({ "description": "OK" });
+        if let Some(schema) = &meta.response_schema {
+            ok["content"] = json_content(schema);
+            schemas.extend(schema.definitions.clone());
+        }
+        entry.insert("responses".to_string(), json!({ "200": ok }));
+
+        let path_item = paths.entry(openapi_path(operation.path)).or_insert_with(|| json!({}));
+        path_item[operation.method.to_ascii_lowercase()] = Value::Object(entry);
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": title, "version": version },
+        "paths": paths,
+        "components": { "schemas": schemas },
+    })
+}
@@ -0,0 +1,320 @@
+use http::header::{HeaderName, CONTENT_LENGTH, HOST, TRANSFER_ENCODING};
+use http::uri::{Authority, Scheme};
+use http::{HeaderMap, HeaderValue, Method, Request, Uri, Version};
+
+use crate::error::{Error, Result};
+use crate::limits::LimitError;
+
+/// How the body following a request head is delimited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BodyFraming {
+    Length(usize),
+    Chunked,
+}
+
+/// `tchar` from RFC 9110 §5.6.2
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// Visible characters, spaces, tabs and obs-text; everything else (CR, LF, NUL, other
+/// controls) is refused
+fn is_field_value_byte(byte: u8) -> bool {
+    byte == b'\t' || (0x20..0x7f).contains(&byte) || byte >= 0x80
+}
+
+fn trim_whitespace(mut bytes: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t', rest @ ..] = bytes {
+        bytes = rest;
+    }
+    while let [rest @ .., b' ' | b'\t'] = bytes {
+        bytes = rest;
+    }
+    bytes
+}
+
+/// Parses the header section of a request (everything after the request line, without the
+/// blank line ending it). Repeated fields are kept as separate values. Obsolete line folding
+/// is refused when `strict`, since intermediaries disagree on it, and otherwise unfolded into a
+/// single space, as RFC 9112 §5.2 allows
+pub(crate) fn parse_header_block(block: &[u8], max_count: usize, strict: bool) -> Result<HeaderMap> {
+    let mut fields: Vec<(HeaderName, Vec<u8>)> = Vec::new();
+
+    for line in block.split_inclusive(|&byte| byte == b'\n') {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        // obs-fold: a continuation of the previous field's value
+        if let [b' ' | b'\t', ..] = line {
+            if strict {
+                return Err(Error::parse("Obsolete line folding in header"));
+            }
+            let (_, value) = fields.last_mut().ok_or(Error::parse("Header block starts with a continuation line"))?;
+            let continuation = trim_whitespace(line);
+            if !continuation.iter().all(|&byte| is_field_value_byte(byte)) {
+                return Err(Error::parse("Invalid character in header value"));
+            }
+            if !continuation.is_empty() {
+                value.push(b' ');
+                value.extend_from_slice(continuation);
+            }
+            continue;
+        }
+
+        if fields.len() == max_count {
+            return Err(Error::Limit(LimitError::TooManyHeaders));
+        }
+
+        // no whitespace is allowed between the name and the colon (RFC 9112 §5.1)
+        let colon = line.iter().position(|&byte| byte == b':').ok_or(Error::parse("Header line without a colon"))?;
+        let (name, value) = (&line[..colon], trim_whitespace(&line[colon + 1..]));
+        if name.is_empty() || !name.iter().all(|&byte| is_token_byte(byte)) {
+            return Err(Error::parse("Invalid header name"));
+        }
+        if !value.iter().all(|&byte| is_field_value_byte(byte)) {
+            return Err(Error::parse("Invalid character in header value"));
+        }
+
+        let name = HeaderName::from_bytes(name).map_err(|_| Error::parse("Invalid header name"))?;
+        fields.push((name, value.to_vec()));
+    }
+
+    let mut headers = HeaderMap::with_capacity(fields.len());
+    for (name, value) in fields {
+        let value = HeaderValue::from_bytes(&value).map_err(|_| Error::parse("Invalid header value"))?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+/// Host names with user info (`user@host`) are refused as RFC 9110 §4.2.4 asks, since they
+/// are mostly used to disguise where a link points
+fn parse_authority(value: &[u8]) -> Result<Authority> {
+    let authority = Authority::try_from(value).map_err(|_| Error::parse("Invalid Host"))?;
+    if authority.as_str().contains('@') {
+        return Err(Error::parse("Host with user info"));
+    }
+    // `Authority` takes anything after the colon as the port
+    let port = &authority.as_str()[authority.host().len()..];
+    if !port.strip_prefix(':').unwrap_or(port).bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(Error::parse("Invalid Host port"));
+    }
+    Ok(authority)
+}
+
+/// Validates `Host` as RFC 9112 §3.2 requires: HTTP/1.1 requests must carry exactly one, and
+/// it must be a valid authority or empty. An absolute-form target (`GET http://host/path`)
+/// takes precedence, replacing `Host` with its authority
+pub(crate) fn validate_host(uri: &Uri, headers: &mut HeaderMap, version: Version) -> Result<()> {
+    let mut hosts = headers.get_all(HOST).iter();
+    let host = hosts.next();
+    if hosts.next().is_some() {
+        return Err(Error::parse("Multiple Host headers"));
+    }
+    match host {
+        // HTTP/1.0 predates Host
+        None if version == Version::HTTP_11 => return Err(Error::parse("HTTP/1.1 request without a Host header")),
+        Some(host) if !host.is_empty() => {
+            parse_authority(host.as_bytes())?;
+        }
+        _ => {}
+    }
+
+    if let Some(scheme) = uri.scheme() {
+        if *scheme != Scheme::HTTP && *scheme != Scheme::HTTPS {
+            return Err(Error::parse("Unsupported request target scheme"));
+        }
+        let authority = uri.authority().ok_or(Error::parse("Absolute request target without a host"))?;
+        let authority = parse_authority(authority.as_str().as_bytes())?;
+        let host = HeaderValue::from_str(authority.as_str()).map_err(|_| Error::parse("Invalid Host"))?;
+        headers.insert(HOST, host);
+    }
+    Ok(())
+}
+
+/// Gives an origin-form target (`/path?query`) the connection's scheme and the authority from
+/// `Host`, so handlers see the full URI whichever form the client sent. CONNECT's authority-form
+/// and `OPTIONS *` are left as they are
+pub(crate) fn complete_uri<B>(request: &mut Request<B>, tls: bool) {
+    if request.uri().scheme().is_some() || request.method() == Method::CONNECT || request.uri() == "*" {
+        return;
+    }
+    let Some(authority) = request.headers().get(HOST).and_then(|host| parse_authority(host.as_bytes()).ok()) else {
+        return;
+    };
+    let mut parts = request.uri().clone().into_parts();
+    parts.scheme = Some(if tls { Scheme::HTTPS } else { Scheme::HTTP });
+    parts.authority = Some(authority);
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *request.uri_mut() = uri;
+    }
+}
+
+/// Works out how the request body is framed, refusing the ambiguous combinations request
+/// smuggling relies on: `Transfer-Encoding` alongside `Content-Length`, and `Content-Length`
+/// values that disagree. Outside strict parsing the caller drops `Content-Length` first
+pub(crate) fn request_framing(headers: &HeaderMap, version: Version) -> Result<BodyFraming> {
+    if headers.contains_key(TRANSFER_ENCODING) {
+        if headers.contains_key(CONTENT_LENGTH) {
+            return Err(Error::parse("Both Transfer-Encoding and Content-Length present"));
+        }
+        if version == Version::HTTP_10 {
+            return Err(Error::parse("Transfer-Encoding in an HTTP/1.0 request"));
+        }
+
+        // chunked is the only transfer coding we decode, and it must be the final one
+        let codings = headers
+            .get_all(TRANSFER_ENCODING)
+            .iter()
+            .map(|value| value.to_str().map_err(|_| Error::parse("Invalid Transfer-Encoding")))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flat_map(|value| value.split(','))
+            .map(|coding| coding.trim().to_ascii_lowercase())
+            .filter(|coding| !coding.is_empty())
+            .collect::<Vec<_>>();
+        return match codings.as_slice() {
+            [coding] if coding == "chunked" => Ok(BodyFraming::Chunked),
+            _ => Err(Error::parse("Unsupported Transfer-Encoding")),
+        };
+    }
+
+    let mut length = None;
+    for value in headers.get_all(CONTENT_LENGTH) {
+        let value = value.to_str().map_err(|_| Error::parse("Invalid Content-Length header"))?;
+        // a list of identical values is tolerated, e.g. `Content-Length: 42, 42`
+        for item in value.split(',') {
+            let item = item.trim();
+            if item.is_empty() || !item.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(Error::parse("Content-Length is not a valid number"));
+            }
+            let item = item.parse::<usize>().map_err(|_| Error::BodyTooLarge)?;
+            if length.is_some_and(|length| length != item) {
+                return Err(Error::parse("Conflicting Content-Length values"));
+            }
+            length = Some(item);
+        }
+    }
+    Ok(BodyFraming::Length(length.unwrap_or(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(fields: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in fields {
+            headers.append(HeaderName::from_bytes(name.as_bytes()).unwrap(), HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn framing(fields: &[(&str, &str)]) -> Result<BodyFraming> {
+        request_framing(&headers(fields), Version::HTTP_11)
+    }
+
+    #[test]
+    fn frames_by_content_length_or_chunked() {
+        assert_eq!(framing(&[]).unwrap(), BodyFraming::Length(0));
+        assert_eq!(framing(&[("content-length", "42")]).unwrap(), BodyFraming::Length(42));
+        assert_eq!(framing(&[("transfer-encoding", "chunked")]).unwrap(), BodyFraming::Chunked);
+        assert_eq!(framing(&[("transfer-encoding", "Chunked")]).unwrap(), BodyFraming::Chunked);
+    }
+
+    #[test]
+    fn refuses_transfer_encoding_with_content_length() {
+        let result = framing(&[("transfer-encoding", "chunked"), ("content-length", "5")]);
+        assert!(matches!(result, Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn refuses_transfer_encodings_other_than_a_final_chunked() {
+        for value in ["gzip", "chunked, gzip", "chunked, chunked", "identity"] {
+            assert!(matches!(framing(&[("transfer-encoding", value)]), Err(Error::Parse(_))), "accepted {value:?}");
+        }
+        let http10 = request_framing(&headers(&[("transfer-encoding", "chunked")]), Version::HTTP_10);
+        assert!(matches!(http10, Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn refuses_conflicting_content_lengths() {
+        assert!(matches!(framing(&[("content-length", "5"), ("content-length", "6")]), Err(Error::Parse(_))));
+        assert!(matches!(framing(&[("content-length", "5, 6")]), Err(Error::Parse(_))));
+        assert_eq!(framing(&[("content-length", "5"), ("content-length", "5")]).unwrap(), BodyFraming::Length(5));
+        assert_eq!(framing(&[("content-length", "5, 5")]).unwrap(), BodyFraming::Length(5));
+    }
+
+    #[test]
+    fn refuses_content_lengths_that_are_not_plain_digits() {
+        for value in ["+5", "-5", "0x5", "5a", "", "5 5"] {
+            assert!(matches!(framing(&[("content-length", value)]), Err(Error::Parse(_))), "accepted {value:?}");
+        }
+    }
+
+    #[test]
+    fn refuses_obs_fold_when_strict() {
+        let block = b"X-Folded: one\r\n two\r\n";
+        assert!(matches!(parse_header_block(block, 100, true), Err(Error::Parse(_))));
+        let headers = parse_header_block(block, 100, false).unwrap();
+        assert_eq!(headers.get("x-folded").unwrap(), "one two");
+    }
+
+    #[test]
+    fn refuses_malformed_header_lines() {
+        for block in [&b"X-Name : value\r\n"[..], b"X Name: value\r\n", b": value\r\n", b"no colon\r\n", b"X-Name: a\0b\r\n", b" leading: fold\r\n"] {
+            assert!(parse_header_block(block, 100, false).is_err(), "accepted {:?}", String::from_utf8_lossy(block));
+        }
+    }
+
+    #[test]
+    fn limits_the_number_of_headers() {
+        assert!(matches!(parse_header_block(b"A: 1\r\nB: 2\r\nC: 3\r\n", 2, true), Err(Error::Limit(LimitError::TooManyHeaders))));
+    }
+
+    fn host(uri: &str, fields: &[(&str, &str)], version: Version) -> Result<HeaderMap> {
+        let mut headers = headers(fields);
+        validate_host(&uri.parse().unwrap(), &mut headers, version)?;
+        Ok(headers)
+    }
+
+    #[test]
+    fn requires_exactly_one_host_from_http11() {
+        assert!(host("/", &[("host", "example.com")], Version::HTTP_11).is_ok());
+        assert!(host("/", &[("host", "example.com:8080")], Version::HTTP_11).is_ok());
+        assert!(host("/", &[("host", "[::1]:8080")], Version::HTTP_11).is_ok());
+        // an empty Host is allowed when the target has no authority
+        assert!(host("/", &[("host", "")], Version::HTTP_11).is_ok());
+        assert!(host("/", &[], Version::HTTP_10).is_ok());
+
+        assert!(matches!(host("/", &[], Version::HTTP_11), Err(Error::Parse(_))));
+        assert!(matches!(host("/", &[("host", "a"), ("host", "b")], Version::HTTP_11), Err(Error::Parse(_))));
+        assert!(matches!(host("/", &[("host", "a"), ("host", "b")], Version::HTTP_10), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn refuses_invalid_hosts_and_user_info() {
+        for value in ["user@example.com", "exa mple.com", "example.com/path", "example.com:port"] {
+            assert!(matches!(host("/", &[("host", value)], Version::HTTP_11), Err(Error::Parse(_))), "accepted {value:?}");
+        }
+    }
+
+    #[test]
+    fn lets_absolute_form_targets_override_host() {
+        let headers = host("http://example.com:8080/path", &[("host", "other.example")], Version::HTTP_11).unwrap();
+        assert_eq!(headers.get(HOST).unwrap(), "example.com:8080");
+        assert!(matches!(host("ftp://example.com/", &[("host", "example.com")], Version::HTTP_11), Err(Error::Parse(_))));
+        assert!(matches!(host("http://user@example.com/", &[("host", "example.com")], Version::HTTP_11), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn completes_origin_form_uris() {
+        let mut request = Request::builder().uri("/path?q=1").header(HOST, "example.com").body(()).unwrap();
+        complete_uri(&mut request, true);
+        assert_eq!(request.uri(), "https://example.com/path?q=1");
+
+        let mut request = Request::builder().method(Method::OPTIONS).uri("*").header(HOST, "example.com").body(()).unwrap();
+        complete_uri(&mut request, false);
+        assert_eq!(request.uri(), "*");
+    }
+}
@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+use http::Request;
+
+use crate::error::{Error, Result};
+
+/// Values captured by `:name` and `*name` route segments, percent-decoded and in the order
+/// they appear in the route
+#[derive(Clone, Debug, Default)]
+pub struct PathParams {
+    params: Vec<(String, String)>,
+}
+
+static EMPTY: PathParams = PathParams { params: Vec::new() };
+
+impl PathParams {
+    pub(crate) fn new(params: Vec<(String, String)>) -> Self {
+        Self { params }
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(param, _)| param == name).map(|(_, value)| value.as_str())
+    }
+
+    /// The param parsed as `T`, e.g. `params.get::<u64>("id")`. Fails with [`Error::Parse`],
+    /// which handlers answer with a 400, if it is missing or doesn't parse
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<T> {
+        let value = self.get_str(name).ok_or_else(|| Error::parse(format!("missing path param `{name}`")))?;
+        value.parse().map_err(|_| Error::parse(format!("invalid path param `{name}`: {value:?}")))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn as_slice(&self) -> &[(String, String)] {
+        &self.params
+    }
+}
+
+/// Access to the path params the router captured for a request
+pub trait RequestPathExt {
+    /// Empty if the route had no params or no route matched
+    fn path_params(&self) -> &PathParams;
+}
+
+impl<B> RequestPathExt for Request<B> {
+    fn path_params(&self) -> &PathParams {
+        self.extensions().get::<PathParams>().unwrap_or(&EMPTY)
+    }
+}
+
+/// Path params deserialized into `T`: a single value such as `u64` for one param, a tuple to
+/// take them in order, or a struct to take them by name
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Path<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> Path<T> {
+    /// Fails with [`Error::Parse`], which handlers answer with a 400, if the params don't fit `T`
+    pub fn extract<B>(request: &Request<B>) -> Result<Self> {
+        let deserializer = crate::de::ParamsDeserializer::new(request.path_params().as_slice());
+        T::deserialize(deserializer)
+            .map(Path)
+            .map_err(|err| Error::parse(format!("invalid path params: {err}")))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> std::ops::Deref for Path<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
@@ -0,0 +1,35 @@
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decodes `%XX` escapes, and `+` as a space when `plus_as_space` is set as in form encoding.
+/// Returns `None` on a malformed escape or if the result isn't UTF-8
+pub(crate) fn decode(input: &str, plus_as_space: bool) -> Option<String> {
+    let input = input.as_bytes();
+    let mut output = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'%' => {
+                let high = hex_value(*input.get(i + 1)?)?;
+                let low = hex_value(*input.get(i + 2)?)?;
+                output.push(high << 4 | low);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                output.push(b' ');
+                i += 1;
+            }
+            c => {
+                output.push(c);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(output).ok()
+}
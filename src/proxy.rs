@@ -0,0 +1,292 @@
+use std::net::{SocketAddr, ToSocketAddrs as _};
+use std::str::FromStr as _;
+use std::sync::Arc;
+
+use async_tls::TlsConnector;
+use futures_lite::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use http::header::{HeaderName, CONNECTION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING};
+use http::{HeaderMap, Method, Request, Response, StatusCode, Uri, Version};
+use simple_error::{box_err, SimpleResult};
+
+use crate::async_connection::AsyncConnection;
+use crate::body::Body;
+use crate::chunked;
+use crate::connection_info::ConnectionInfo;
+use crate::error::{Error, Result};
+use crate::forwarded::ClientAddr;
+use crate::hop_by_hop::is_hop_by_hop;
+use crate::router::RouteHandler;
+use crate::runtime;
+use crate::spawner::Spawner;
+use crate::types::BoxFuture;
+
+const MAX_STATUS_LINE: usize = 8 * 1024;
+const MAX_RESPONSE_HEAD: usize = 64 * 1024;
+
+/// Forwards requests to an upstream HTTP(S) server, e.g.
+/// `router.add_route(Method::GET, "/api/*rest", Proxy::new("http://127.0.0.1:3000")?.strip_prefix("/api").into_handler())`.
+/// Upstream responses are streamed back as they arrive; the request body has already been
+/// buffered by the server and is sent in one piece
+pub struct Proxy {
+    tls: bool,
+    host: String,
+    port: u16,
+    /// Value for the upstream `Host` header
+    authority: String,
+    base_path: String,
+    strip_prefix: Option<String>,
+    connector: TlsConnector,
+}
+
+impl Proxy {
+    /// `upstream` is a base URL like `"https://backend.internal:8443/v1"`; request paths are
+    /// appended to its path
+    pub fn new(upstream: &str) -> Result<Self> {
+        let uri = Uri::from_str(upstream).map_err(|_| Error::parse("Invalid upstream URL"))?;
+        let tls = match uri.scheme_str() {
+            Some("http") => false,
+            Some("https") => true,
+            _ => return Err(Error::parse("Upstream must be an http:// or https:// URL")),
+        };
+        let authority = uri.authority().ok_or(Error::parse("Upstream URL has no host"))?;
+        let authority_host = match authority.port() {
+            Some(port) => format!("{}:{}", authority.host(), port),
+            None => authority.host().to_string(),
+        };
+
+        Ok(Self {
+            tls,
+            host: authority.host().trim_start_matches('[').trim_end_matches(']').to_string(),
+            port: authority.port_u16().unwrap_or(if tls { 443 } else { 80 }),
+            authority: authority_host,
+            base_path: uri.path().trim_end_matches('/').to_string(),
+            strip_prefix: None,
+            connector: TlsConnector::default(),
+        })
+    }
+
+    /// Removes `prefix` from request paths before appending them to the upstream path, so
+    /// `/api/users` can be served by the upstream's `/users`
+    pub fn strip_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.strip_prefix = Some(prefix.into().trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Uses `connector` for https upstreams, e.g. one trusting a private CA
+    pub fn with_tls_connector(mut self, connector: TlsConnector) -> Self {
+        self.connector = connector;
+        self
+    }
+
+    /// Route handler forwarding every request it receives; failures to reach the upstream
+    /// become a 502
+    pub fn into_handler(self) -> Arc<RouteHandler> {
+        let proxy = Arc::new(self);
+        Arc::new(
+            move |_spawner: Arc<dyn Spawner>, request: Request<Vec<u8>>| -> BoxFuture<'static, SimpleResult<Response<Body>>> {
+                let proxy = proxy.clone();
+                Box::pin(async move {
+                    match proxy.forward(request).await {
+                        Ok(response) => Ok(response),
+                        Err(err) => {
+                            log::warn!("proxy upstream {} failed err = {err:?}", proxy.authority);
+                            Ok(Response::builder()
+                                .status(StatusCode::BAD_GATEWAY)
+                                .version(Version::HTTP_11)
+                                .header("Content-Type", "text/plain")
+                                .body(Body::from("Bad Gateway"))?)
+                        }
+                    }
+                })
+            },
+        )
+    }
+
+    /// Upstream request target for `uri`
+    fn target(&self, uri: &Uri) -> String {
+        let path = uri.path();
+        let path = match &self.strip_prefix {
+            Some(prefix) => match path.strip_prefix(prefix.as_str()) {
+                Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+                _ => path,
+            },
+            None => path,
+        };
+
+        let mut target = format!("{}/{}", self.base_path, path.trim_start_matches('/'));
+        if let Some(query) = uri.query() {
+            target.push('?');
+            target.push_str(query);
+        }
+        target
+    }
+
+    async fn connect(&self) -> SimpleResult<Box<dyn AsyncConnection>> {
+        // name resolution blocks, so keep it off the async threads
+        let host = self.host.clone();
+        let port = self.port;
+        let addrs: Vec<SocketAddr> = blocking::unblock(move || (host.as_str(), port).to_socket_addrs().map(|addrs| addrs.collect())).await?;
+
+        let mut last_err = None;
+        for addr in addrs {
+            match runtime::connect_tcp(addr).await {
+                Ok(stream) if self.tls => return Ok(Box::new(self.connector.connect(&self.host, stream).await?)),
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(match last_err {
+            Some(err) => err.into(),
+            None => box_err!("Upstream host did not resolve"),
+        })
+    }
+
+    /// Request head for the upstream: end-to-end headers plus `Host` and `X-Forwarded-*`
+    fn request_head(&self, request: &Request<Vec<u8>>) -> Vec<u8> {
+        let headers = request.headers();
+        let mut head = format!("{} {} HTTP/1.1\r\n", request.method(), self.target(request.uri())).into_bytes();
+
+        for (name, value) in headers {
+            let skip = is_hop_by_hop(name, headers)
+                || name == HOST
+                || name == CONTENT_LENGTH
+                || name.as_str().starts_with("x-forwarded-");
+            if !skip {
+                push_header(&mut head, name.as_str(), value.as_bytes());
+            }
+        }
+        push_header(&mut head, HOST.as_str(), self.authority.as_bytes());
+
+        // append the peer to the chain of addresses the request passed through
+        let mut forwarded_for = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let connection_info = request.extensions().get::<ConnectionInfo>();
+        if let Some(remote_addr) = connection_info.and_then(|connection_info| connection_info.remote_addr) {
+            forwarded_for.push(remote_addr.ip().to_string());
+        }
+        if !forwarded_for.is_empty() {
+            push_header(&mut head, "x-forwarded-for", forwarded_for.join(", ").as_bytes());
+        }
+
+        let scheme = match request.extensions().get::<ClientAddr>() {
+            Some(client_addr) => client_addr.scheme.clone(),
+            None if connection_info.is_some_and(|connection_info| connection_info.tls) => "https".to_string(),
+            None => "http".to_string(),
+        };
+        push_header(&mut head, "x-forwarded-proto", scheme.as_bytes());
+
+        let original_host = headers
+            .get(HOST)
+            .map(|host| host.as_bytes().to_vec())
+            .or_else(|| request.uri().authority().map(|authority| authority.as_str().as_bytes().to_vec()));
+        if let Some(original_host) = original_host {
+            push_header(&mut head, "x-forwarded-host", &original_host);
+        }
+
+        // without pooling, every upstream connection carries exactly one request
+        push_header(&mut head, CONNECTION.as_str(), b"close");
+        let sends_body = !request.body().is_empty() || !matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS | Method::DELETE);
+        if sends_body {
+            push_header(&mut head, CONTENT_LENGTH.as_str(), request.body().len().to_string().as_bytes());
+        }
+        head.extend_from_slice(b"\r\n");
+        head
+    }
+
+    async fn forward(&self, request: Request<Vec<u8>>) -> SimpleResult<Response<Body>> {
+        let mut connection = self.connect().await?;
+        connection.write_all(&self.request_head(&request)).await?;
+        connection.write_all(request.body()).await?;
+        connection.flush().await?;
+
+        let mut reader = BufReader::new(connection);
+        let (status, headers) = loop {
+            let (status, headers) = read_response_head(&mut reader).await?;
+            // interim responses like 100 Continue are consumed here
+            if !status.is_informational() || status == StatusCode::SWITCHING_PROTOCOLS {
+                break (status, headers);
+            }
+        };
+
+        let mut response = Response::builder().status(status).version(Version::HTTP_11);
+        for (name, value) in &headers {
+            if !is_hop_by_hop(name, &headers) {
+                response = response.header(name, value);
+            }
+        }
+
+        let content_length = match headers.get(CONTENT_LENGTH) {
+            Some(value) => Some(value.to_str()?.trim().parse::<u64>()?),
+            None => None,
+        };
+        let chunked = headers
+            .get_all(TRANSFER_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.to_ascii_lowercase().contains("chunked"));
+        let no_body = request.method() == Method::HEAD
+            || status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED;
+
+        let body = if no_body {
+            Body::empty()
+        } else if chunked {
+            Body::from_stream(chunked::decode(reader), None)
+        } else if let Some(content_length) = content_length {
+            Body::from_reader(reader.take(content_length), Some(content_length))
+        } else {
+            // delimited by the upstream closing the connection
+            Body::from_reader(reader, None)
+        };
+
+        Ok(response.body(body)?)
+    }
+}
+
+fn push_header(head: &mut Vec<u8>, name: &str, value: &[u8]) {
+    head.extend_from_slice(name.as_bytes());
+    head.extend_from_slice(b": ");
+    head.extend_from_slice(value);
+    head.extend_from_slice(b"\r\n");
+}
+
+/// Reads a line of the upstream's response head, refusing one that runs past `max` bytes
+async fn read_head_line<R: AsyncBufRead + Unpin>(reader: &mut R, line: &mut String, max: usize) -> SimpleResult<usize> {
+    let read = (&mut *reader).take(max as u64 + 1).read_line(line).await?;
+    if read > max && !line.ends_with('\n') {
+        return Err(box_err!("Upstream response head too large"));
+    }
+    Ok(read)
+}
+
+async fn read_response_head<R: AsyncBufRead + Unpin>(reader: &mut R) -> SimpleResult<(StatusCode, HeaderMap)> {
+    let mut status_line = String::new();
+    read_head_line(reader, &mut status_line, MAX_STATUS_LINE).await?;
+    let mut parts = status_line.split_whitespace();
+    let version = parts.next().ok_or(box_err!("Failed to parse upstream version"))?;
+    if !version.starts_with("HTTP/1.") {
+        return Err(box_err!("Unsupported upstream HTTP version"));
+    }
+    let status = StatusCode::from_str(parts.next().ok_or(box_err!("Failed to parse upstream status"))?)?;
+
+    let mut headers = HeaderMap::new();
+    let mut head_size = 0;
+    loop {
+        let mut header_line = String::new();
+        let remaining = MAX_RESPONSE_HEAD.saturating_sub(head_size);
+        head_size += read_head_line(reader, &mut header_line, remaining).await?;
+        if header_line.trim_end_matches(['\r', '\n']).is_empty() {
+            break;
+        }
+
+        let (name, value) = header_line.split_once(':').ok_or(box_err!("Failed to parse upstream header"))?;
+        headers.append(HeaderName::from_str(name.trim())?, value.trim().parse()?);
+    }
+
+    Ok((status, headers))
+}
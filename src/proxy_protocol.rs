@@ -0,0 +1,111 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use futures_lite::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::{Error, Result};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V1_MAX_LENGTH: usize = 107;
+
+/// Reads a PROXY protocol v1 or v2 header from the start of `stream`, returning the original
+/// source and destination addresses. `None` means the proxy sent a health check (`LOCAL` /
+/// `UNKNOWN`) and the connection's own addresses apply
+pub(crate) async fn read_proxy_header<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> Result<Option<(SocketAddr, SocketAddr)>> {
+    // both versions are at least 12 bytes long, so this never reads into the payload
+    let mut prefix = [0u8; 12];
+    stream.read_exact(&mut prefix).await?;
+
+    if prefix == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if prefix.starts_with(b"PROXY ") {
+        read_v1(stream, &prefix).await
+    } else {
+        Err(Error::parse("Missing PROXY protocol header"))
+    }
+}
+
+async fn read_v1<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    prefix: &[u8],
+) -> Result<Option<(SocketAddr, SocketAddr)>> {
+    // the header is a single CRLF terminated line; read byte by byte so no payload is consumed
+    // (the server reads through a buffer, so this isn't a syscall per byte)
+    let mut line = prefix.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(Error::parse("PROXY v1 header too long"));
+        }
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await?;
+        line.push(byte[0]);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| Error::parse("PROXY v1 header is not ASCII"))?;
+    let mut parts = line.split(' ').skip(1);
+    match parts.next() {
+        Some("TCP4") | Some("TCP6") => {}
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(Error::parse("Unsupported PROXY v1 protocol")),
+    }
+
+    let source_ip = parts.next().ok_or(Error::parse("Missing PROXY v1 source address"))?.parse::<IpAddr>().map_err(|_| Error::parse("Invalid PROXY v1 address"))?;
+    let destination_ip = parts.next().ok_or(Error::parse("Missing PROXY v1 destination address"))?.parse::<IpAddr>().map_err(|_| Error::parse("Invalid PROXY v1 address"))?;
+    let source_port = parts.next().ok_or(Error::parse("Missing PROXY v1 source port"))?.parse::<u16>().map_err(|_| Error::parse("Invalid PROXY v1 port"))?;
+    let destination_port = parts.next().ok_or(Error::parse("Missing PROXY v1 destination port"))?.parse::<u16>().map_err(|_| Error::parse("Invalid PROXY v1 port"))?;
+
+    Ok(Some((
+        SocketAddr::new(source_ip, source_port),
+        SocketAddr::new(destination_ip, destination_port),
+    )))
+}
+
+async fn read_v2<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<(SocketAddr, SocketAddr)>> {
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    let [version_command, family, length_high, length_low] = header;
+
+    if version_command >> 4 != 2 {
+        return Err(Error::parse("Unsupported PROXY protocol version"));
+    }
+
+    // the address block (plus any TLVs) always has to be consumed
+    let length = u16::from_be_bytes([length_high, length_low]) as usize;
+    let mut block = vec![0u8; length];
+    stream.read_exact(&mut block).await?;
+
+    // LOCAL: health checks from the proxy itself
+    if version_command & 0x0F == 0 {
+        return Ok(None);
+    }
+
+    let port = |offset: usize| u16::from_be_bytes([block[offset], block[offset + 1]]);
+    match family >> 4 {
+        // AF_INET
+        0x1 if block.len() >= 12 => {
+            let source = Ipv4Addr::new(block[0], block[1], block[2], block[3]);
+            let destination = Ipv4Addr::new(block[4], block[5], block[6], block[7]);
+            Ok(Some((
+                SocketAddr::new(source.into(), port(8)),
+                SocketAddr::new(destination.into(), port(10)),
+            )))
+        }
+        // AF_INET6
+        0x2 if block.len() >= 36 => {
+            let octets = |offset: usize| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&block[offset..offset + 16]);
+                octets
+            };
+            let source = Ipv6Addr::from(octets(0));
+            let destination = Ipv6Addr::from(octets(16));
+            Ok(Some((
+                SocketAddr::new(source.into(), port(32)),
+                SocketAddr::new(destination.into(), port(34)),
+            )))
+        }
+        // AF_UNSPEC, AF_UNIX: nothing we can express as a socket address
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,146 @@
+use http::header::{CONTENT_TYPE, LOCATION};
+use http::{HeaderValue, Response, StatusCode, Version};
+
+use crate::body::Body;
+use crate::error::Error;
+
+/// Anything a handler can turn into a response, e.g. `Ok(Html(page).into_response())`
+pub trait IntoResponse {
+    fn into_response(self) -> Response<Body>;
+}
+
+impl IntoResponse for Response<Body> {
+    fn into_response(self) -> Response<Body> {
+        self
+    }
+}
+
+/// A bare status with an empty body, e.g. `StatusCode::NO_CONTENT.into_response()`
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = self;
+        *response.version_mut() = Version::HTTP_11;
+        response
+    }
+}
+
+/// Overrides the status of another response, e.g. `(StatusCode::CREATED, Text("created"))`
+impl<R: IntoResponse> IntoResponse for (StatusCode, R) {
+    fn into_response(self) -> Response<Body> {
+        let mut response = self.1.into_response();
+        *response.status_mut() = self.0;
+        response
+    }
+}
+
+/// Client errors carry their message; server errors only their status, after being logged
+impl IntoResponse for Error {
+    fn into_response(self) -> Response<Body> {
+        let status = self.status();
+        if status.is_server_error() {
+            log::error!("Handler error: {self:?}");
+            return (status, Text(status.canonical_reason().unwrap_or_default())).into_response();
+        }
+        (status, Text(self.to_string())).into_response()
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> Response<Body> {
+        match self {
+            Ok(response) => response.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+fn with_content_type(body: Body, content_type: &'static str) -> Response<Body> {
+    let mut response = StatusCode::OK.into_response();
+    *response.body_mut() = body;
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response
+}
+
+/// A `text/html` response
+pub struct Html<T>(pub T);
+
+impl<T: Into<Body>> IntoResponse for Html<T> {
+    fn into_response(self) -> Response<Body> {
+        with_content_type(self.0.into(), "text/html; charset=utf-8")
+    }
+}
+
+/// A `text/plain` response
+pub struct Text<T>(pub T);
+
+impl<T: Into<Body>> IntoResponse for Text<T> {
+    fn into_response(self) -> Response<Body> {
+        with_content_type(self.0.into(), "text/plain; charset=utf-8")
+    }
+}
+
+/// A redirect to `Location`
+pub struct Redirect {
+    status: StatusCode,
+    location: HeaderValue,
+}
+
+impl Redirect {
+    fn new(status: StatusCode, uri: &str) -> Self {
+        // control characters are the only bytes a header value can't carry
+        let uri = uri.chars().filter(|c| !c.is_control()).collect::<String>();
+        Self {
+            status,
+            location: HeaderValue::from_str(&uri).expect("control characters were removed"),
+        }
+    }
+
+    /// 307, repeating the same method and body at `uri`
+    pub fn temporary(uri: &str) -> Self {
+        Self::new(StatusCode::TEMPORARY_REDIRECT, uri)
+    }
+
+    /// 308, repeating the same method and body at `uri` from now on
+    pub fn permanent(uri: &str) -> Self {
+        Self::new(StatusCode::PERMANENT_REDIRECT, uri)
+    }
+
+    /// 303, following up with a GET to `uri`, e.g. after a form POST
+    pub fn see_other(uri: &str) -> Self {
+        Self::new(StatusCode::SEE_OTHER, uri)
+    }
+}
+
+impl IntoResponse for Redirect {
+    fn into_response(self) -> Response<Body> {
+        let mut response = self.status.into_response();
+        response.headers_mut().insert(LOCATION, self.location);
+        response
+    }
+}
+
+/// A `application/x-ndjson` response writing each item of a stream as one line of JSON, e.g.
+/// for long exports. Every line goes out as soon as it's serialized (as its own chunk over
+/// HTTP/1.1) rather than once the whole stream is done; an item that fails to serialize ends
+/// the response early
+#[cfg(feature = "json")]
+pub struct Ndjson<S>(pub S);
+
+#[cfg(feature = "json")]
+impl<S, T> IntoResponse for Ndjson<S>
+where
+    S: futures_lite::Stream<Item = T> + Send + 'static,
+    T: serde::Serialize,
+{
+    fn into_response(self) -> Response<Body> {
+        use futures_lite::StreamExt as _;
+
+        let lines = self.0.map(|item| {
+            let mut line = serde_json::to_vec(&item).map_err(std::io::Error::other)?;
+            line.push(b'\n');
+            Ok(line)
+        });
+        with_content_type(Body::from_stream(lines, None), "application/x-ndjson")
+    }
+}
@@ -0,0 +1,67 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_lite::io::{AsyncRead, AsyncWrite, BufReader};
+
+/// A stream that replays bytes already read from it before reading on, so a connection can
+/// change hands (PROXY header parsing, protocol upgrades) without losing what was buffered
+pub(crate) struct Rewind<S> {
+    prefix: Vec<u8>,
+    position: usize,
+    inner: S,
+}
+
+impl<S> Rewind<S> {
+    pub(crate) fn new(prefix: Vec<u8>, inner: S) -> Self {
+        Self {
+            prefix,
+            position: 0,
+            inner,
+        }
+    }
+
+    /// Takes the stream back out of `reader`, keeping whatever it had buffered
+    pub(crate) fn from_reader(reader: BufReader<S>) -> Self {
+        let prefix = reader.buffer().to_vec();
+        Self::new(prefix, reader.into_inner())
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Rewind<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let remaining = &this.prefix[this.position..];
+        if remaining.is_empty() {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+
+        let read = remaining.len().min(buf.len());
+        buf[..read].copy_from_slice(&remaining[..read]);
+        this.position += read;
+        if this.position == this.prefix.len() {
+            this.prefix = Vec::new();
+            this.position = 0;
+        }
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Rewind<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
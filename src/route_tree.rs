@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use http::Method;
+
+/// Routes keyed by path segment, so a lookup costs one step per segment of the request path
+/// however many routes are registered. Static segments win over `:param` segments, which win
+/// over a trailing `*wildcard`
+pub(crate) struct RouteTree<T> {
+    root: Node<T>,
+}
+
+struct Node<T> {
+    statics: HashMap<String, Node<T>>,
+    param: Option<Box<Node<T>>>,
+    /// Routes whose `*name` segment starts here and swallows the rest of the path
+    wildcard: HashMap<Method, T>,
+    /// Routes ending at this node
+    routes: HashMap<Method, T>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Self {
+            statics: HashMap::new(),
+            param: None,
+            wildcard: HashMap::new(),
+            routes: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Default for RouteTree<T> {
+    fn default() -> Self {
+        Self { root: Node::default() }
+    }
+}
+
+impl<T> RouteTree<T> {
+    /// Adds or replaces the route for `method` at `path`; anything after a `*name` segment is ignored
+    pub(crate) fn insert(&mut self, method: Method, path: &str, value: T) -> Option<T> {
+        let mut node = &mut self.root;
+        for segment in path.split('/') {
+            if segment.starts_with('*') {
+                return node.wildcard.insert(method, value);
+            }
+            node = if segment.starts_with(':') {
+                node.param.get_or_insert_with(Box::default).as_mut()
+            } else {
+                node.statics.entry(segment.to_string()).or_default()
+            };
+        }
+        node.routes.insert(method, value)
+    }
+
+    /// The route registered for `method` under exactly this `path` pattern
+    pub(crate) fn get_mut(&mut self, method: &Method, path: &str) -> Option<&mut T> {
+        let mut node = &mut self.root;
+        for segment in path.split('/') {
+            if segment.starts_with('*') {
+                return node.wildcard.get_mut(method);
+            }
+            node = if segment.starts_with(':') {
+                node.param.as_deref_mut()?
+            } else {
+                node.statics.get_mut(segment)?
+            };
+        }
+        node.routes.get_mut(method)
+    }
+
+    /// Removes the route registered for `method` under exactly this `path` pattern. Emptied
+    /// nodes are left in place; they cost nothing on lookup beyond a map probe
+    pub(crate) fn remove(&mut self, method: &Method, path: &str) -> Option<T> {
+        let mut node = &mut self.root;
+        for segment in path.split('/') {
+            if segment.starts_with('*') {
+                return node.wildcard.remove(method);
+            }
+            node = if segment.starts_with(':') {
+                node.param.as_deref_mut()?
+            } else {
+                node.statics.get_mut(segment)?
+            };
+        }
+        node.routes.remove(method)
+    }
+
+    /// Every route, in no particular order
+    pub(crate) fn iter(&self) -> Vec<(&Method, &T)> {
+        fn collect<'t, T>(node: &'t Node<T>, routes: &mut Vec<(&'t Method, &'t T)>) {
+            routes.extend(node.routes.iter());
+            routes.extend(node.wildcard.iter());
+            for child in node.statics.values() {
+                collect(child, routes);
+            }
+            if let Some(child) = &node.param {
+                collect(child, routes);
+            }
+        }
+
+        let mut routes = Vec::new();
+        collect(&self.root, &mut routes);
+        routes
+    }
+
+    /// The route for `method` matching `path`, with the raw values of its param and wildcard
+    /// segments in the order they appear
+    pub(crate) fn find<'t, 'p>(&'t self, method: &Method, path: &'p str) -> Option<(&'t T, Vec<&'p str>)> {
+        let mut captures = Vec::new();
+        let value = Self::find_in(&self.root, Some(path), method, &mut captures)?;
+        Some((value, captures))
+    }
+
+    /// `remaining` starts at the segment `node`'s children are keyed by; `None` once the whole
+    /// path has been consumed
+    fn find_in<'t, 'p>(node: &'t Node<T>, remaining: Option<&'p str>, method: &Method, captures: &mut Vec<&'p str>) -> Option<&'t T> {
+        let Some(remaining) = remaining else {
+            return node.routes.get(method);
+        };
+        let (segment, rest) = match remaining.split_once('/') {
+            Some((segment, rest)) => (segment, Some(rest)),
+            None => (remaining, None),
+        };
+
+        if let Some(child) = node.statics.get(segment) {
+            if let Some(value) = Self::find_in(child, rest, method, captures) {
+                return Some(value);
+            }
+        }
+
+        // params never match an empty segment
+        if let (Some(child), false) = (&node.param, segment.is_empty()) {
+            captures.push(segment);
+            if let Some(value) = Self::find_in(child, rest, method, captures) {
+                return Some(value);
+            }
+            captures.pop();
+        }
+
+        let value = node.wildcard.get(method)?;
+        captures.push(remaining);
+        Some(value)
+    }
+}
@@ -1,117 +1,615 @@
-use std::{collections::HashMap, sync::Arc};
-use regex::Regex;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use async_executor::Executor;
-use http::{Method, Request, Response, StatusCode, Version};
+use http::header::LOCATION;
+use http::{Extensions, Method, Request, Response, StatusCode, Version};
 use simple_error::SimpleResult;
 
+use crate::body::Body;
+use crate::error::Result;
+use crate::connection_info::ConnectionInfo;
+use crate::extract::State;
+use crate::handler::{self, Handler};
+use crate::health::HealthRegistry;
+use crate::middleware::{Middleware, Next};
+use crate::path_params::PathParams;
+use crate::percent;
+use crate::response::IntoResponse as _;
+use crate::route_tree::RouteTree;
+use crate::server::{ConnectionConfig, HttpServer};
+use crate::spawner::{Spawner, ThreadPoolSpawner};
+use crate::static_files::{self, StaticFiles};
+use crate::timeout::{with_timeout, TimeoutError};
 use crate::types::BoxFuture;
 
-pub type RouteHandler = dyn Fn(Arc<Executor<'static>>, Request<Vec<u8>>) -> BoxFuture<'static, SimpleResult<Response<String>>> + Send + Sync;
+pub type RouteHandler = dyn Fn(Arc<dyn Spawner>, Request<Vec<u8>>) -> BoxFuture<'static, SimpleResult<Response<Body>>> + Send + Sync;
 
 struct RouteInfo {
     handler: Arc<RouteHandler>,
-    pattern: Regex,
+    path: Arc<str>,
     path_params: Vec<String>,
+    meta: RouteMeta,
+    options: RouteOptions,
+}
+
+/// Documentation for a route, attached with [`Router::describe_route`] and used for the OpenAPI
+/// document, e.g. `RouteMeta::new().summary("Fetch a user").tag("users")`
+#[derive(Clone, Debug, Default)]
+pub struct RouteMeta {
+    pub(crate) summary: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) tags: Vec<String>,
+    #[cfg(feature = "openapi")]
+    pub(crate) request_schema: Option<crate::openapi::Schema>,
+    #[cfg(feature = "openapi")]
+    pub(crate) response_schema: Option<crate::openapi::Schema>,
+}
+
+/// Limits for one route that override the server-wide [`Limits`](crate::Limits) and
+/// [`Timeouts`](crate::Timeouts), attached with [`Router::configure_route`], e.g.
+/// `RouteOptions::new().max_body_size(100 * 1024 * 1024)` for an upload endpoint
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RouteOptions {
+    pub(crate) max_body_size: Option<usize>,
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) handler_timeout: Option<Duration>,
+}
+
+impl RouteOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Largest accepted request body, in bytes
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = Some(max_body_size);
+        self
+    }
+
+    /// Time allowed to receive the request body, in place of both the body read timeout and
+    /// the whole-request bound
+    pub fn read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = Some(read_timeout);
+        self
+    }
+
+    /// Time allowed for middleware and the handler to produce a response, after which the
+    /// client gets a 503
+    pub fn handler_timeout(mut self, handler_timeout: Duration) -> Self {
+        self.handler_timeout = Some(handler_timeout);
+        self
+    }
+}
+
+impl RouteMeta {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn summary(mut self, summary: impl Into<String>) -> Self {
+        self.summary = Some(summary.into());
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// The JSON request body is a `T`
+    #[cfg(feature = "openapi")]
+    pub fn request<T: schemars::JsonSchema>(mut self) -> Self {
+        self.request_schema = Some(crate::openapi::Schema::of::<T>());
+        self
+    }
+
+    /// The JSON response body is a `T`
+    #[cfg(feature = "openapi")]
+    pub fn response<T: schemars::JsonSchema>(mut self) -> Self {
+        self.response_schema = Some(crate::openapi::Schema::of::<T>());
+        self
+    }
+}
+
+/// One entry of the route table, as returned by [`Router::routes`]
+#[derive(Clone, Debug)]
+pub struct RouteDescription {
+    pub method: Method,
+    /// The pattern the route was registered under, e.g. `/users/:id`
+    pub path: String,
+    pub path_params: Vec<String>,
+    /// Names of the middleware wrapping the route, outermost first
+    pub middleware: Vec<String>,
+    pub meta: RouteMeta,
+}
+
+/// What to do with a request whose path only misses a route by a trailing slash, e.g. `/foo/`
+/// when `/foo` is registered
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Paths must match exactly
+    #[default]
+    Strict,
+    /// Redirect permanently to the registered form
+    Redirect,
+    /// Serve both forms from the same route
+    Merge,
 }
 
-#[derive(Default)]
 pub struct Router {
-    executor: Arc<Executor<'static>>,
-    routes: HashMap<(Method, String), RouteInfo>,
+    spawner: Arc<dyn Spawner>,
+    /// Behind a lock so routes can be added and removed while the server is running
+    routes: RwLock<RouteTree<RouteInfo>>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    trailing_slash: TrailingSlash,
+    state: Extensions,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new(Arc::new(ThreadPoolSpawner))
+    }
 }
 
 impl Router {
-    pub fn new(executor: Arc<Executor<'static>>) -> Self {
+    /// Connections and handler work run on `spawner`, e.g. an `Arc<async_executor::Executor>`
+    pub fn new(spawner: Arc<dyn Spawner>) -> Self {
         Self {
-            executor,
-            routes: HashMap::new(),
+            spawner,
+            routes: RwLock::new(RouteTree::default()),
+            middleware: Vec::new(),
+            trailing_slash: TrailingSlash::default(),
+            state: Extensions::new(),
         }
     }
 
-    pub fn add_routes(&mut self, routes: Vec<(Method, &str, Arc<RouteHandler>)>) {
+    pub fn spawner(&self) -> &Arc<dyn Spawner> {
+        &self.spawner
+    }
+
+    pub fn add_routes(&self, routes: Vec<(Method, &str, Arc<RouteHandler>)>) {
         for (method, path, handler) in routes {
             self.add_route(method, path, handler);
         }
     }
 
-    pub fn add_route(&mut self, method: Method, path: &str, handler: Arc<RouteHandler>) {
-        let key = (method, path.to_string());
-        
-        log::debug!("Adding route: {:?}", key);
-        let mut path_params = Vec::new();
-        let pattern_str = path
+    /// Registers `handler` for `path`, where `:name` segments match one path segment and a final
+    /// `*name` segment matches the rest of the path, slashes included. Handlers read the
+    /// captured values through [`RequestPathExt`](crate::RequestPathExt). Routes can also be
+    /// added through the `Arc<Router>` while it is being served; requests already matched keep
+    /// the handler they were matched to
+    pub fn add_route(&self, method: Method, path: &str, handler: Arc<RouteHandler>) {
+        log::debug!("Adding route: {:?}", (&method, path));
+        let path_params = path
             .split('/')
-            .map(|segment| {
-                if segment.starts_with(':') {
-                    path_params.push(segment[1..].to_string());
-                    "([^/]+)".to_string()
-                } else {
-                    regex::escape(segment)
-                }
-            })
-            .collect::<Vec<_>>()
-            .join("/");
+            .filter_map(|segment| segment.strip_prefix(':').or_else(|| segment.strip_prefix('*')))
+            .map(str::to_string)
+            .collect();
 
-        let pattern = Regex::new(&format!("^{}$", pattern_str)).unwrap();
-        
-        self.routes.insert(key, RouteInfo {
+        self.routes.write().unwrap().insert(method, path, RouteInfo {
             handler,
-            pattern,
+            path: path.into(),
             path_params,
+            meta: RouteMeta::default(),
+            options: RouteOptions::default(),
         });
     }
 
-    pub async fn route(&self, request: Request<Vec<u8>>) -> SimpleResult<Response<String>> {
+    /// Unregisters the route added for `method` under exactly this `path` pattern, returning
+    /// whether there was one. Like [`Router::add_route`] this works while the server is running
+    pub fn remove_route(&self, method: &Method, path: &str) -> bool {
+        log::debug!("Removing route: {:?}", (method, path));
+        self.routes.write().unwrap().remove(method, path).is_some()
+    }
+
+    /// Attaches documentation to the route registered for `method` at `path`
+    pub fn describe_route(&self, method: &Method, path: &str, meta: RouteMeta) {
+        match self.routes.write().unwrap().get_mut(method, path) {
+            Some(route_info) => route_info.meta = meta,
+            None => log::warn!("describe_route: no route for ({:?}, {})", method, path),
+        }
+    }
+
+    /// Overrides body size and timeouts for the route registered for `method` at `path`
+    pub fn configure_route(&self, method: &Method, path: &str, options: RouteOptions) {
+        match self.routes.write().unwrap().get_mut(method, path) {
+            Some(route_info) => route_info.options = options,
+            None => log::warn!("configure_route: no route for ({:?}, {})", method, path),
+        }
+    }
+
+    /// The options of the route a request for `path` will be routed to, so the server can
+    /// apply them while reading its body
+    pub(crate) fn route_options(&self, method: &Method, path: &str) -> RouteOptions {
+        let routes = self.routes.read().unwrap();
+        if let Some((route_info, _)) = routes.find(method, path) {
+            return route_info.options;
+        }
+        if self.trailing_slash == TrailingSlash::Strict || path == "/" {
+            return RouteOptions::default();
+        }
+        let alternate = match path.strip_suffix('/') {
+            Some(stripped) => stripped.to_string(),
+            None => format!("{path}/"),
+        };
+        routes.find(method, &alternate).map(|(route_info, _)| route_info.options).unwrap_or_default()
+    }
+
+    /// An OpenAPI 3 document covering every route registered so far
+    #[cfg(feature = "openapi")]
+    pub fn openapi_json(&self, title: &str, version: &str) -> String {
+        let routes = self.routes();
+        let operations = routes
+            .iter()
+            .map(|route| crate::openapi::Operation {
+                method: route.method.as_str(),
+                path: &route.path,
+                path_params: &route.path_params,
+                meta: &route.meta,
+            })
+            .collect::<Vec<_>>();
+        crate::openapi::document(title, version, &operations).to_string()
+    }
+
+    /// Serves [`Router::openapi_json`] at `path`. The document is generated now, so register
+    /// this after the routes it should describe
+    #[cfg(feature = "openapi")]
+    pub fn add_openapi_route(&self, path: &str, title: &str, version: &str) {
+        let document = self.openapi_json(title, version);
+        self.add_route(Method::GET, path, Arc::new(move |_spawner: Arc<dyn Spawner>, _request: Request<Vec<u8>>| -> BoxFuture<'static, SimpleResult<Response<Body>>> {
+            let document = document.clone();
+            Box::pin(async move {
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .version(Version::HTTP_11)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(document))?)
+            })
+        }));
+    }
+
+    /// Serves `health`'s liveness and readiness endpoints
+    pub fn add_health_routes(&self, health: &HealthRegistry) {
+        let liveness = health.clone();
+        self.add_route(Method::GET, &health.health_path, Arc::new(move |_spawner: Arc<dyn Spawner>, _request: Request<Vec<u8>>| -> BoxFuture<'static, SimpleResult<Response<Body>>> {
+            let liveness = liveness.clone();
+            Box::pin(async move { Ok(liveness.liveness().await) })
+        }));
+        let readiness = health.clone();
+        self.add_route(Method::GET, &health.ready_path, Arc::new(move |_spawner: Arc<dyn Spawner>, _request: Request<Vec<u8>>| -> BoxFuture<'static, SimpleResult<Response<Body>>> {
+            let readiness = readiness.clone();
+            Box::pin(async move { Ok(readiness.readiness().await) })
+        }));
+    }
+
+    /// Serves `files` to GET and HEAD requests for paths under `prefix`, e.g.
+    /// `router.add_static("/assets", StaticFiles::new("./public"))`
+    pub fn add_static(&self, prefix: &str, files: StaticFiles) {
+        let pattern = format!("{}/*{}", prefix.trim_end_matches('/'), static_files::WILDCARD);
+        let files = Arc::new(files);
+        for method in [Method::GET, Method::HEAD] {
+            let files = files.clone();
+            self.add_route(method, &pattern, Arc::new(move |_spawner: Arc<dyn Spawner>, request: Request<Vec<u8>>| -> BoxFuture<'static, SimpleResult<Response<Body>>> {
+                let files = files.clone();
+                Box::pin(async move { Ok(files.serve(&request).await) })
+            }));
+        }
+    }
+
+    /// Registers an async function taking [`FromRequest`](crate::FromRequest) arguments, e.g.
+    /// `router.add_handler(Method::GET, "/users/:id", get_user)`
+    pub fn add_handler<H: Handler<Args>, Args>(&self, method: Method, path: &str, handler: H) {
+        self.add_route(method, path, handler::handler(handler));
+    }
+
+    /// Makes `state` available to every request as [`State<T>`](crate::State)
+    pub fn add_state<T: Clone + Send + Sync + 'static>(&mut self, state: T) {
+        self.state.insert(State(state));
+    }
+
+    /// How paths differing from a route only by a trailing slash are treated; strict by default
+    pub fn set_trailing_slash(&mut self, trailing_slash: TrailingSlash) {
+        self.trailing_slash = trailing_slash;
+    }
+
+    /// The route table sorted by path then method, e.g. for logging at startup or an admin endpoint
+    pub fn routes(&self) -> Vec<RouteDescription> {
+        let middleware = self.middleware.iter().map(|middleware| middleware.name().to_string()).collect::<Vec<_>>();
+        let mut routes = self
+            .routes
+            .read()
+            .unwrap()
+            .iter()
+            .into_iter()
+            .map(|(method, route_info)| RouteDescription {
+                method: method.clone(),
+                path: route_info.path.to_string(),
+                path_params: route_info.path_params.clone(),
+                middleware: middleware.clone(),
+                meta: route_info.meta.clone(),
+            })
+            .collect::<Vec<_>>();
+        routes.sort_by(|a, b| (&a.path, a.method.as_str()).cmp(&(&b.path, b.method.as_str())));
+        routes
+    }
+
+    /// [`Router::routes`] as aligned text, one route per line followed by the middleware stack
+    pub fn route_table(&self) -> String {
+        let routes = self.routes();
+        let method_width = routes.iter().map(|route| route.method.as_str().len()).max().unwrap_or(0);
+        let path_width = routes.iter().map(|route| route.path.len()).max().unwrap_or(0);
+
+        let mut table = String::new();
+        for route in &routes {
+            let line = format!("{:method_width$}  {:path_width$}  {}", route.method.as_str(), route.path, route.meta.summary.as_deref().unwrap_or(""));
+            table.push_str(line.trim_end());
+            table.push('\n');
+        }
+        if !self.middleware.is_empty() {
+            // type names are fully qualified, which is more than a listing needs
+            let names = self
+                .middleware
+                .iter()
+                .map(|middleware| middleware.name().rsplit("::").next().unwrap_or_default().to_string())
+                .collect::<Vec<_>>();
+            table.push_str(&format!("middleware: {}\n", names.join(" -> ")));
+        }
+        table
+    }
+
+    /// Wraps every request in `middleware`; the first added is the outermost layer
+    pub fn add_middleware(&mut self, middleware: Arc<dyn Middleware>) {
+        self.middleware.push(middleware);
+    }
+
+    /// Finds the handler for `path`, along with any path params it captured
+    fn find_route(&self, method: &Method, path: &str) -> Option<(Arc<str>, Arc<RouteHandler>, PathParams, RouteOptions)> {
+        let routes = self.routes.read().unwrap();
+        let (route_info, values) = routes.find(method, path)?;
+        let params = route_info
+            .path_params
+            .iter()
+            .zip(values)
+            .map(|(name, value)| {
+                let value = percent::decode(value, false).unwrap_or_else(|| value.to_string());
+                (name.clone(), value)
+            })
+            .collect();
+        Some((route_info.path.clone(), route_info.handler.clone(), PathParams::new(params), route_info.options))
+    }
+
+    /// Finds the handler for the request and the route it was registered under, recording any
+    /// path params in its extensions. Under [`TrailingSlash::Redirect`] a miss that only differs
+    /// by a trailing slash yields a handler redirecting to the registered form
+    fn match_route(&self, request: &mut Request<Vec<u8>>) -> Option<(Arc<RouteHandler>, Arc<str>, RouteOptions)> {
         let method = request.method().clone();
         let path = request.uri().path().to_string();
-        
-        // Get all routes for this method
-        for ((route_method, _), route_info) in self.routes.iter() {
-            if route_method != &method {
-                continue;
-            }
 
-            if let Some(captures) = route_info.pattern.captures(&path) {
-                let mut params = HashMap::new();
-                for (i, param_name) in route_info.path_params.iter().enumerate() {
-                    if let Some(value) = captures.get(i + 1) {
-                        params.insert(param_name.clone(), value.as_str().to_string());
-                    }
-                }
+        if let Some((route_path, handler, params, options)) = self.find_route(&method, &path) {
+            request.extensions_mut().insert(params);
+            return Some((handler, route_path, options));
+        }
 
-                let mut request = request;
-                request.extensions_mut().insert(params);
+        if self.trailing_slash == TrailingSlash::Strict || path == "/" {
+            return None;
+        }
+        let alternate = match path.strip_suffix('/') {
+            Some(stripped) => stripped.to_string(),
+            None => format!("{path}/"),
+        };
+        let (route_path, handler, params, options) = self.find_route(&method, &alternate)?;
 
-                return match (route_info.handler)(self.executor.clone(), request).await {
-                    Ok(response) => {
-                        log::debug!("Response: {:?}", response);
-                        Ok(response)
-                    },
-                    Err(err) => {
-                        log::error!("Controller error: {:?}", err);
-                        let response_body = format!("{:?}", err);
+        match self.trailing_slash {
+            TrailingSlash::Merge => {
+                request.extensions_mut().insert(params);
+                Some((handler, route_path, options))
+            }
+            _ => {
+                let location = match request.uri().query() {
+                    Some(query) => format!("{alternate}?{query}"),
+                    None => alternate,
+                };
+                // 308 keeps the method and body of anything other than a plain fetch
+                let status = match method {
+                    Method::GET | Method::HEAD => StatusCode::MOVED_PERMANENTLY,
+                    _ => StatusCode::PERMANENT_REDIRECT,
+                };
+                let redirect: Arc<RouteHandler> = Arc::new(move |_spawner: Arc<dyn Spawner>, _request: Request<Vec<u8>>| -> BoxFuture<'static, SimpleResult<Response<Body>>> {
+                    let location = location.clone();
+                    Box::pin(async move {
                         Ok(Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .status(status)
                             .version(Version::HTTP_11)
-                            .header("Content-Type", "text/plain")
-                            .header("Content-Length", response_body.len().to_string())
-                            .body(response_body)
-                            .unwrap())
-                    },
-                };
+                            .header(LOCATION, location)
+                            .body(Body::empty())?)
+                    })
+                });
+                Some((redirect, route_path, RouteOptions::default()))
             }
         }
+    }
+
+    /// Runs `request` as if it had arrived on a plain connection from `127.0.0.1`, without a
+    /// socket, for exercising handlers and middleware from tests
+    pub async fn call(&self, request: Request<Vec<u8>>) -> Response<Body> {
+        let connection_info = ConnectionInfo {
+            remote_addr: Some(([127, 0, 0, 1], 0).into()),
+            local_addr: Some(([127, 0, 0, 1], 0).into()),
+            ..ConnectionInfo::default()
+        };
+        self.call_with(request, connection_info).await
+    }
+
+    /// Like [`Router::call`], with the connection details handlers should see. The request is
+    /// prepared as the server would, with no trusted proxies: its URI is completed from `Host`
+    /// and its extensions include an [`Informational`](crate::Informational) sender, whose
+    /// responses are discarded, and a [`Cancellation`](crate::Cancellation) token
+    pub async fn call_with(&self, mut request: Request<Vec<u8>>, connection_info: ConnectionInfo) -> Response<Body> {
+        let (_cancellation, _interim) = HttpServer::prepare_request(&mut request, &connection_info, &ConnectionConfig::default());
+        match self.route(request).await {
+            Ok(response) => response,
+            Err(err) => err.into_response(),
+        }
+    }
+
+    /// [`Router::call`] driven to completion on the current thread, for synchronous tests
+    pub fn call_blocking(&self, request: Request<Vec<u8>>) -> Response<Body> {
+        futures_lite::future::block_on(self.call(request))
+    }
+
+    /// Runs the request through the middleware chain and its handler; failures in either
+    /// become a 500 response rather than an error
+    pub async fn route(&self, mut request: Request<Vec<u8>>) -> Result<Response<Body>> {
+        if !self.state.is_empty() {
+            request.extensions_mut().extend(self.state.clone());
+        }
+        let matched = self.match_route(&mut request);
+        let handler_timeout = matched.as_ref().and_then(|(_, _, options)| options.handler_timeout);
+        let next = Next {
+            spawner: &self.spawner,
+            middleware: &self.middleware,
+            handler: matched.as_ref().map(|(handler, route, _)| (handler.as_ref(), route.as_ref())),
+        };
+
+        match with_timeout(handler_timeout, "handler", next.run(request)).await {
+            Ok(response) => Ok(response),
+            Err(err) if err.is::<TimeoutError>() => {
+                log::warn!("{err}");
+                Ok(StatusCode::SERVICE_UNAVAILABLE.into_response())
+            }
+            Err(err) => {
+                log::error!("Middleware error: {:?}", err);
+                let response_body = format!("{:?}", err);
+                Ok(Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .version(Version::HTTP_11)
+                    .header("Content-Type", "text/plain")
+                    .header("Content-Length", response_body.len().to_string())
+                    .body(Body::from(response_body))
+                    .unwrap())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use http::header::HOST;
+
+    use super::*;
+    use crate::cancellation::Cancellation;
+    use crate::forwarded::ClientAddr;
+    use crate::informational::Informational;
+    use crate::path_params::RequestPathExt as _;
+    use crate::response::{IntoResponse, Text};
+
+    fn request(method: Method, uri: &str) -> Request<Vec<u8>> {
+        Request::builder().method(method).uri(uri).body(Vec::new()).unwrap()
+    }
+
+    fn respond(f: impl Fn(Request<Vec<u8>>) -> Response<Body> + Send + Sync + 'static) -> Arc<RouteHandler> {
+        Arc::new(move |_spawner: Arc<dyn Spawner>, request: Request<Vec<u8>>| -> BoxFuture<'static, SimpleResult<Response<Body>>> {
+            let response = f(request);
+            Box::pin(async move { Ok(response) })
+        })
+    }
+
+    fn text(text: &'static str) -> Arc<RouteHandler> {
+        respond(move |_| Text(text).into_response())
+    }
+
+    fn body(response: &Response<Body>) -> &str {
+        std::str::from_utf8(response.body().as_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn routes_by_method_and_path() {
+        let router = Router::default();
+        router.add_route(Method::GET, "/users", text("list"));
+        router.add_route(Method::POST, "/users", text("create"));
+        router.add_route(Method::GET, "/users/:id", respond(|request| Text(request.path_params().get_str("id").unwrap().to_string()).into_response()));
+        router.add_route(Method::GET, "/users/me", text("me"));
+
+        assert_eq!(body(&router.call_blocking(request(Method::GET, "/users"))), "list");
+        assert_eq!(body(&router.call_blocking(request(Method::POST, "/users"))), "create");
+        assert_eq!(body(&router.call_blocking(request(Method::GET, "/users/42"))), "42");
+        // static segments win over params
+        assert_eq!(body(&router.call_blocking(request(Method::GET, "/users/me"))), "me");
+    }
+
+    #[test]
+    fn answers_404_for_unknown_paths() {
+        let router = Router::default();
+        router.add_route(Method::GET, "/users/:id", text("user"));
+
+        assert_eq!(router.call_blocking(request(Method::GET, "/posts/1")).status(), StatusCode::NOT_FOUND);
+        assert_eq!(router.call_blocking(request(Method::PUT, "/users/1")).status(), StatusCode::NOT_FOUND);
+    }
+
+    struct Record(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for Record {
+        fn handle<'a>(&'a self, request: Request<Vec<u8>>, next: Next<'a>) -> BoxFuture<'a, SimpleResult<Response<Body>>> {
+            Box::pin(async move {
+                self.1.lock().unwrap().push(format!("{} before", self.0));
+                let response = next.run(request).await;
+                self.1.lock().unwrap().push(format!("{} after", self.0));
+                response
+            })
+        }
+    }
+
+    #[test]
+    fn runs_middleware_in_the_order_added_around_the_handler() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::default();
+        router.add_middleware(Arc::new(Record("outer", calls.clone())));
+        router.add_middleware(Arc::new(Record("inner", calls.clone())));
+        router.add_route(Method::GET, "/", {
+            let calls = calls.clone();
+            respond(move |_| {
+                calls.lock().unwrap().push("handler".to_string());
+                StatusCode::OK.into_response()
+            })
+        });
+
+        router.call_blocking(request(Method::GET, "/"));
+        assert_eq!(*calls.lock().unwrap(), ["outer before", "inner before", "handler", "inner after", "outer after"]);
+
+        // middleware wraps unmatched requests too
+        calls.lock().unwrap().clear();
+        router.call_blocking(request(Method::GET, "/missing"));
+        assert_eq!(*calls.lock().unwrap(), ["outer before", "inner before", "inner after", "outer after"]);
+    }
+
+    #[test]
+    fn fills_in_request_extensions() {
+        let mut router = Router::default();
+        router.add_state(42u32);
+        router.add_route(Method::GET, "/items/:id", respond(|request| {
+            let extensions = request.extensions();
+            assert_eq!(extensions.get::<State<u32>>().unwrap().0, 42);
+            assert_eq!(request.path_params().get_str("id"), Some("7"));
+            let client = extensions.get::<ClientAddr>().unwrap();
+            assert_eq!(client.ip, Some([127, 0, 0, 1].into()));
+            assert_eq!(client.scheme, "http");
+            assert!(extensions.get::<ConnectionInfo>().is_some());
+            // as the server prepares requests
+            assert_eq!(request.uri(), "http://example.com/items/7");
+            assert!(extensions.get::<Informational>().unwrap().early_hints(["</style.css>; rel=preload"]));
+            assert!(!extensions.get::<Cancellation>().unwrap().is_cancelled());
+            StatusCode::NO_CONTENT.into_response()
+        }));
 
-        // No matching route found
-        log::warn!("Route not found: ({:?}, {})", method, path);
-        let response_body = "Not Found".to_string();
-        Ok(Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .version(Version::HTTP_11)
-            .header("Content-Type", "text/plain")
-            .header("Content-Length", response_body.len().to_string())
-            .body(response_body)
-            .unwrap())
+        // a failed assertion in the handler would show up as a 500
+        let request = Request::builder().uri("/items/7").header(HOST, "example.com").body(Vec::new()).unwrap();
+        assert_eq!(router.call_blocking(request).status(), StatusCode::NO_CONTENT);
     }
 }
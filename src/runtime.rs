@@ -0,0 +1,56 @@
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use async_io::{Async, Timer};
+
+use crate::async_connection::AsyncConnection;
+use crate::types::BoxFuture;
+
+/// Whether the caller is running inside a tokio runtime, in which case its reactor and timers are
+/// used instead of async-io's
+#[cfg(feature = "tokio")]
+pub(crate) fn in_tokio() -> bool {
+    tokio::runtime::Handle::try_current().is_ok()
+}
+
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(feature = "tokio")]
+    if in_tokio() {
+        tokio::time::sleep(duration).await;
+        return;
+    }
+    Timer::after(duration).await;
+}
+
+pub(crate) async fn connect_tcp(addr: SocketAddr) -> io::Result<Box<dyn AsyncConnection>> {
+    #[cfg(feature = "tokio")]
+    if in_tokio() {
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        return Ok(Box::new(async_compat::Compat::new(stream)));
+    }
+    Ok(Box::new(Async::<TcpStream>::connect(addr).await?))
+}
+
+/// Runs blocking or CPU-heavy work, like hashing a file or calling a synchronous database
+/// driver, on a thread pool instead of the threads serving connections, e.g.
+/// `let digest = spawn_blocking(move || sha256(&body)).await;`. Uses tokio's blocking pool
+/// inside a tokio runtime and the `blocking` crate's otherwise. The work starts straight away,
+/// and a panic in `f` resumes on the task awaiting it
+pub fn spawn_blocking<T, F>(f: F) -> BoxFuture<'static, T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    #[cfg(feature = "tokio")]
+    if in_tokio() {
+        let task = tokio::task::spawn_blocking(f);
+        return Box::pin(async move {
+            match task.await {
+                Ok(value) => value,
+                Err(err) => std::panic::resume_unwind(err.into_panic()),
+            }
+        });
+    }
+    Box::pin(blocking::unblock(f))
+}
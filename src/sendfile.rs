@@ -0,0 +1,124 @@
+use std::fs::File;
+use std::io;
+
+/// The TCP socket under a plaintext connection, which file bodies are copied to by the kernel
+/// with `sendfile(2)` instead of being read into userspace and written back out. TLS
+/// connections have to encrypt what they send, so they stream files instead
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy)]
+pub(crate) struct SendFileSocket(std::os::fd::RawFd);
+
+/// Without `sendfile(2)` there is no such socket, and file bodies are always streamed
+#[cfg(not(target_os = "linux"))]
+#[derive(Clone, Copy)]
+pub(crate) enum SendFileSocket {}
+
+#[cfg(target_os = "linux")]
+impl SendFileSocket {
+    /// Largest count passed to one `sendfile` call, as Linux sends at most about 2 GiB per call
+    const MAX_COUNT: u64 = 1 << 30;
+
+    pub(crate) fn new(stream: &async_io::Async<std::net::TcpStream>) -> Self {
+        use std::os::fd::AsRawFd as _;
+        Self(stream.as_raw_fd())
+    }
+
+    /// Sends the first `length` bytes of `file`, waiting for room in the socket's send buffer
+    /// whenever it fills up
+    pub(crate) async fn send(self, file: &File, length: u64) -> io::Result<()> {
+        use std::os::fd::{AsRawFd as _, BorrowedFd};
+
+        // SAFETY: the socket belongs to the connection this was created for, which is being
+        // served for as long as its responses are written
+        let socket = unsafe { BorrowedFd::borrow_raw(self.0) }.try_clone_to_owned()?;
+        // the duplicate gets its own reactor registration to wait for writability on
+        let socket = async_io::Async::new(std::net::TcpStream::from(socket))?;
+
+        let mut offset: libc::off_t = 0;
+        let mut remaining = length;
+        while remaining > 0 {
+            let count = remaining.min(Self::MAX_COUNT) as usize;
+            let sent = socket
+                .write_with(|socket| {
+                    // SAFETY: both descriptors are open for the duration of the call
+                    let sent = unsafe { libc::sendfile(socket.as_raw_fd(), file.as_raw_fd(), &mut offset, count) };
+                    if sent < 0 {
+                        Err(io::Error::last_os_error())
+                    } else {
+                        Ok(sent as u64)
+                    }
+                })
+                .await?;
+            // the file shrank since its length was taken, and Content-Length can't be honored
+            if sent == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            remaining -= sent;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl SendFileSocket {
+    pub(crate) async fn send(self, _file: &File, _length: u64) -> io::Result<()> {
+        match self {}
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::io::{Read as _, Write as _};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use async_io::{block_on, Async};
+
+    use super::*;
+
+    /// Writes `contents` to a new file under the temp directory and opens it for reading
+    fn file(name: &str, contents: &[u8]) -> File {
+        let path = std::env::temp_dir().join(format!("http_server-sendfile-{}-{name}", std::process::id()));
+        File::create(&path).unwrap().write_all(contents).unwrap();
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+
+    /// Sends `length` bytes of `file` over a loopback connection and returns what the peer read
+    fn send(file: &File, length: u64) -> (io::Result<()>, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut received = Vec::new();
+            TcpStream::connect(addr).unwrap().read_to_end(&mut received).unwrap();
+            received
+        });
+        let stream = Async::new(listener.accept().unwrap().0).unwrap();
+        let result = block_on(SendFileSocket::new(&stream).send(file, length));
+        drop(stream);
+        (result, client.join().unwrap())
+    }
+
+    #[test]
+    fn sends_files_larger_than_the_socket_buffer() {
+        let contents: Vec<u8> = (0..8 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let (result, received) = send(&file("large", &contents), contents.len() as u64);
+        result.unwrap();
+        assert!(received == contents);
+    }
+
+    #[test]
+    fn sends_only_the_requested_length() {
+        let (result, received) = send(&file("prefix", b"hello world"), 5);
+        result.unwrap();
+        assert_eq!(received, b"hello");
+    }
+
+    #[test]
+    fn fails_when_the_file_is_shorter_than_its_length() {
+        let (result, received) = send(&file("short", b"hello"), 10);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(received, b"hello");
+    }
+}
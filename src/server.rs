@@ -1,221 +1,1436 @@
-use async_io::Async;
 use async_executor::Executor;
-use futures_lite::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, AsyncReadExt};
-use http::{Method, Request, Uri, Version};
-use simple_error::{box_err, SimpleResult};
-use async_tls::TlsAcceptor;
-use rustls::{Certificate, PrivateKey, ServerConfig};
-use std::net::{TcpListener, TcpStream, ToSocketAddrs as _};
+use async_io::{Async, Timer};
+use futures_lite::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, AsyncReadExt};
+use futures_lite::future;
+use futures_lite::StreamExt as _;
+use http::header::{HeaderName, CONNECTION, CONTENT_LENGTH, TRAILER, TRANSFER_ENCODING};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri, Version};
+use rustls::ServerConfig;
+use std::net::{TcpListener, ToSocketAddrs as _};
 use std::str::FromStr as _;
+use std::io::{self, Write as _};
+use std::pin::pin;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::async_connection::AsyncConnection;
-use crate::router::Router;
+use crate::body::{declared_trailers, Body, BodyInner, Trailers};
+use crate::chunked;
+use crate::builder::HttpServerBuilder;
+use crate::connection_info::ConnectionInfo;
+use crate::error::{Error, Result};
+use crate::forwarded::{insert_connection_extensions, TrustedProxies};
+use crate::hop_by_hop;
+use crate::connection_limiter::ConnectionLimiter;
+use crate::default_headers::DefaultHeaders;
+use crate::listener::{accept_retry_delay, Listener};
+use crate::keep_alive::{request_wants_keep_alive, response_wants_close, KeepAlive};
+use crate::proxy_protocol::read_proxy_header;
+use crate::limits::{read_until_limited, LimitError, Limits};
+use crate::parse::{complete_uri, parse_header_block, request_framing, validate_host, BodyFraming};
+use crate::rewind::Rewind;
+use crate::sendfile::SendFileSocket;
+use crate::router::{RouteOptions, Router};
+use crate::upgrade::OnUpgrade;
+use crate::informational::Informational;
+use crate::cancellation::Cancellation;
+use crate::middleware::ip_filter::IpFilter;
+use crate::hooks::{ConnectionHooks, ServerHooks};
+use crate::metrics::{append_server_timing, CountingStream, RequestMetrics};
+use crate::shutdown::ShutdownHandle;
+use crate::socket::SocketOptions;
+use crate::trace::{in_connection_span, in_request_span};
+use crate::tls::{client_verifier, Acceptor, load_certs, load_private_key, server_config, CertSource, PeerCertificates, TlsHandle};
+use crate::timeout::{with_timeout, Timeouts};
+use crate::vectored::write_all_vectored;
 
+/// How long, and for how many bytes, [`HttpServer::lingering_close`] keeps reading
+const LINGER_TIMEOUT: Duration = Duration::from_secs(2);
+const LINGER_MAX_BYTES: usize = 1024 * 1024;
+
+/// Per-connection settings shared with every connection task
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionConfig {
+    pub timeouts: Timeouts,
+    pub limits: Limits,
+    pub keep_alive: KeepAlive,
+    pub trusted_proxies: Option<TrustedProxies>,
+    pub proxy_protocol: bool,
+    pub socket_options: SocketOptions,
+    /// Peers to drop as soon as they connect
+    pub ip_filter: Option<IpFilter>,
+    pub hooks: Option<Arc<dyn ServerHooks>>,
+    /// Unfold obsolete line folding and let Transfer-Encoding override Content-Length rather
+    /// than refusing such requests
+    pub lenient_parsing: bool,
+    /// Report the handler's duration in a `Server-Timing` response header
+    pub server_timing: bool,
+    /// Treat a client closing its side of the connection mid-request as hanging up
+    pub cancel_on_eof: bool,
+    /// Serve HTTP/2 to plaintext clients that open with its connection preface
+    #[cfg(feature = "http2")]
+    pub h2c: bool,
+    pub default_headers: DefaultHeaders,
+    pub shutdown: ShutdownHandle,
+}
+
+/// What interrupted waiting on a handler's response
+enum RouteEvent<R> {
+    Routed(R),
+    Informational((StatusCode, HeaderMap)),
+    HungUp,
+}
+
+/// Scratch space reused by every request on a connection, so steady-state requests parse and
+/// respond without allocating buffers of their own
+#[derive(Default)]
+struct ConnectionBuffers {
+    request_line: Vec<u8>,
+    header_block: Vec<u8>,
+    response_head: Vec<u8>,
+}
+
+#[derive(Default)]
 pub struct HttpServer {
-    tls_acceptor: Option<TlsAcceptor>,
+    tls: TlsHandle,
+    config: Arc<ConnectionConfig>,
 }
 
 impl HttpServer {
     pub fn new() -> Self {
-        Self { tls_acceptor: None }
-    }
-
-    pub fn with_tls(cert_pem: &str, key_pem: &str) -> SimpleResult<Self> {
-        // Load certificate from string
-        let mut cert_reader = std::io::BufReader::new(std::io::Cursor::new(cert_pem));
-        let cert = rustls_pemfile::certs(&mut cert_reader)?
-            .into_iter()
-            .map(Certificate)
-            .collect();
-
-        // Load private key from string
-        let mut key_reader = std::io::BufReader::new(std::io::Cursor::new(key_pem));
-        let key = rustls_pemfile::pkcs8_private_keys(&mut key_reader)?
-            .into_iter()
-            .map(PrivateKey)
-            .next()
-            .ok_or("No private key found")?;
+        Self {
+            tls: TlsHandle::default(),
+            config: Arc::default(),
+        }
+    }
+
+    pub fn builder() -> HttpServerBuilder {
+        HttpServerBuilder::new()
+    }
+
+    /// Serves HTTPS with a PEM encoded certificate chain and PKCS#8 key. With the `native-tls`
+    /// feature the handshake goes through the platform's TLS stack, e.g. for FIPS builds, and
+    /// only HTTP/1.1 is offered; otherwise through rustls
+    #[cfg(not(feature = "native-tls"))]
+    pub fn with_tls(cert_pem: &str, key_pem: &str) -> Result<Self> {
+        // Load certificate and private key from strings
+        let cert = load_certs(cert_pem)?;
+        let key = load_private_key(key_pem)?;
 
         // Create TLS config
-        let config = ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(cert, key)?;
+        let config = server_config(None, CertSource::Single(cert, key))?;
 
-        Ok(Self {
-            tls_acceptor: Some(TlsAcceptor::from(Arc::new(config))),
-        })
+        Ok(Self::with_tls_config(config))
     }
 
-    async fn accept_connection(&self, stream: Async<TcpStream>) -> SimpleResult<Box<dyn AsyncConnection>> {
-        if let Some(tls_acceptor) = &self.tls_acceptor {
-            // Handle HTTPS connection
-            let tls_stream = tls_acceptor.accept(stream).await?;
-            Ok(Box::new(tls_stream))
+    /// Serves HTTPS with a PEM encoded certificate chain and PKCS#8 key through the platform's
+    /// TLS stack, which offers only HTTP/1.1
+    #[cfg(feature = "native-tls")]
+    pub fn with_tls(cert_pem: &str, key_pem: &str) -> Result<Self> {
+        Ok(Self::with_tls_handle(TlsHandle::native(cert_pem, key_pem)?))
+    }
+
+    /// Like [`HttpServer::with_tls`], additionally asking clients for a certificate signed by
+    /// one of the PEM encoded CAs in `client_ca_pem`. With `required` set, clients without one
+    /// are refused during the handshake
+    pub fn with_mutual_tls(cert_pem: &str, key_pem: &str, client_ca_pem: &str, required: bool) -> Result<Self> {
+        let client_verifier = client_verifier(client_ca_pem, required)?;
+        let certs = CertSource::Single(load_certs(cert_pem)?, load_private_key(key_pem)?);
+        let config = server_config(Some(client_verifier.clone()), certs)?;
+
+        Ok(Self::with_tls_handle(TlsHandle::new(config, Some(client_verifier))))
+    }
+
+    /// Serves HTTPS using a prepared rustls config, e.g. one with a custom certificate resolver
+    pub fn with_tls_config(config: ServerConfig) -> Self {
+        Self::with_tls_handle(TlsHandle::new(config, None))
+    }
+
+    pub(crate) fn with_tls_handle(tls: TlsHandle) -> Self {
+        Self {
+            tls,
+            config: Arc::default(),
+        }
+    }
+
+    /// Handle for swapping the TLS certificate while the server is running
+    pub fn tls_handle(&self) -> TlsHandle {
+        self.tls.clone()
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        Arc::make_mut(&mut self.config).timeouts = timeouts;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        Arc::make_mut(&mut self.config).limits = limits;
+        self
+    }
+
+    pub fn with_keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        Arc::make_mut(&mut self.config).keep_alive = keep_alive;
+        self
+    }
+
+    /// Believe the forwarding header these proxies set when resolving the [`ClientAddr`] of a
+    /// request
+    pub fn with_trusted_proxies(mut self, trusted_proxies: TrustedProxies) -> Self {
+        Arc::make_mut(&mut self.config).trusted_proxies = Some(trusted_proxies);
+        self
+    }
+
+    /// Expect every connection to start with a PROXY protocol v1/v2 header, as sent by HAProxy
+    /// or an AWS NLB, and report the addresses it carries in [`ConnectionInfo`]
+    pub fn with_proxy_protocol(mut self, proxy_protocol: bool) -> Self {
+        Arc::make_mut(&mut self.config).proxy_protocol = proxy_protocol;
+        self
+    }
+
+    /// Serve HTTP/2 without TLS, to clients with prior knowledge (`curl --http2-prior-knowledge`,
+    /// gRPC) recognised by the connection preface, and to HTTP/1.1 requests without a body that
+    /// ask for it with `Upgrade: h2c` (`curl --http2`), which are answered on the new connection
+    #[cfg(feature = "http2")]
+    pub fn with_h2c(mut self, h2c: bool) -> Self {
+        Arc::make_mut(&mut self.config).h2c = h2c;
+        self
+    }
+
+    /// Closes connections from peers `ip_filter` refuses straight after accepting them. The
+    /// peer is the socket's address, not one from a PROXY header or forwarding headers
+    pub fn with_ip_filter(mut self, ip_filter: IpFilter) -> Self {
+        Arc::make_mut(&mut self.config).ip_filter = Some(ip_filter);
+        self
+    }
+
+    /// Whether to refuse requests with obsolete line folding or with both Transfer-Encoding and
+    /// Content-Length with a 400, the default. Turning this off accepts them the way RFC 9112
+    /// allows, closing the connection after a request carrying both framing headers; only do
+    /// so behind a front proxy that already normalizes requests
+    pub fn with_strict_parsing(mut self, strict: bool) -> Self {
+        Arc::make_mut(&mut self.config).lenient_parsing = !strict;
+        self
+    }
+
+    /// Adds a `Server-Timing: handler;dur=<ms>` header to every response, so browser devtools
+    /// show how much of a request's latency was spent in the handler. Off by default, since it
+    /// tells clients about backend timings
+    pub fn with_server_timing(mut self, server_timing: bool) -> Self {
+        Arc::make_mut(&mut self.config).server_timing = server_timing;
+        self
+    }
+
+    /// Cancels a request, dropping its handler and firing its [`Cancellation`], when the client
+    /// closes its side of the connection before the response is written. Off by default: a
+    /// client may half-close once it has sent its request and still wait for the response, so
+    /// only a reset connection cancels a request otherwise
+    pub fn with_cancel_on_eof(mut self, cancel_on_eof: bool) -> Self {
+        Arc::make_mut(&mut self.config).cancel_on_eof = cancel_on_eof;
+        self
+    }
+
+    /// Reports connections, requests and responses to `hooks`
+    pub fn with_hooks(mut self, hooks: Arc<dyn ServerHooks>) -> Self {
+        Arc::make_mut(&mut self.config).hooks = Some(hooks);
+        self
+    }
+
+    /// TCP options for listeners this server binds and the connections it accepts
+    pub fn with_socket_options(mut self, socket_options: SocketOptions) -> Self {
+        Arc::make_mut(&mut self.config).socket_options = socket_options;
+        self
+    }
+
+    /// Headers added to every response that doesn't set them, `Date` and `Server` by default
+    pub fn with_default_headers(mut self, default_headers: DefaultHeaders) -> Self {
+        Arc::make_mut(&mut self.config).default_headers = default_headers;
+        self
+    }
+
+    /// Lets `shutdown` drain this server; several servers may share one handle
+    pub fn with_shutdown_handle(mut self, shutdown: ShutdownHandle) -> Self {
+        Arc::make_mut(&mut self.config).shutdown = shutdown;
+        self
+    }
+
+    /// Handle for draining the server while it is running
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.config.shutdown.clone()
+    }
+
+    /// Performs the TLS handshake if configured, recording what it negotiated in `connection_info`.
+    /// Runs on the connection's own task, so a stalled client only holds up itself
+    async fn accept_connection<S: AsyncConnection + 'static>(
+        stream: S,
+        tls_acceptor: Option<Acceptor>,
+        config: &ConnectionConfig,
+        connection_info: &mut ConnectionInfo,
+    ) -> Result<Box<dyn AsyncConnection>> {
+        // The PROXY header precedes everything else on the wire, including the TLS handshake.
+        // It is read through a buffer, and anything read past it is replayed to the next reader
+        let stream = if config.proxy_protocol {
+            let mut reader = BufReader::new(stream);
+            let header_read_timeout = config.timeouts.header_read;
+            let addresses = with_timeout(header_read_timeout, "PROXY header read", read_proxy_header(&mut reader)).await?;
+            if let Some((source, destination)) = addresses {
+                connection_info.proxied_by = connection_info.remote_addr;
+                connection_info.remote_addr = Some(source);
+                connection_info.local_addr = Some(destination);
+            }
+            Rewind::from_reader(reader)
         } else {
+            Rewind::new(Vec::new(), stream)
+        };
+
+        match tls_acceptor {
+            // Handle HTTPS connection
+            Some(Acceptor::Rustls(tls_acceptor)) => {
+                let tls_stream = with_timeout(config.timeouts.handshake, "TLS handshake", async { Ok::<_, Error>(tls_acceptor.accept(stream).await?) }).await?;
+                let session = tls_stream.get_ref().1;
+                connection_info.tls = true;
+                connection_info.server_name = session.server_name().map(|server_name| server_name.to_string());
+                connection_info.alpn_protocol = session.alpn_protocol().map(|protocol| protocol.to_vec());
+                connection_info.peer_certificates = session.peer_certificates().map(|certs| PeerCertificates(certs.to_vec()));
+                Ok(Box::new(tls_stream))
+            }
+            #[cfg(feature = "native-tls")]
+            Some(Acceptor::Native(tls_acceptor)) => {
+                let handshake = async { tls_acceptor.accept(stream).await.map_err(|err| Error::Io(io::Error::other(err))) };
+                let tls_stream = with_timeout(config.timeouts.handshake, "TLS handshake", handshake).await?;
+                connection_info.tls = true;
+                // native-tls only exposes the client's own certificate, not the chain behind it
+                if let Ok(Some(certificate)) = tls_stream.peer_certificate() {
+                    if let Ok(der) = certificate.to_der() {
+                        connection_info.peer_certificates = Some(PeerCertificates(vec![rustls::Certificate(der)]));
+                    }
+                }
+                Ok(Box::new(tls_stream))
+            }
             // Handle HTTP connection
-            Ok(Box::new(stream))
+            None => Ok(Box::new(stream)),
         }
     }
 
-    async fn read_http_request<S: AsyncRead + AsyncWrite + Unpin>(
-        stream: &mut S,
-    ) -> SimpleResult<Request<Vec<u8>>> {
-        // Wrap the stream with a BufReader for efficient reading
-        let mut reader = BufReader::new(stream);
-
+    async fn read_request_head<R: AsyncBufRead + Unpin>(
+        reader: &mut R,
+        limits: &Limits,
+        strict: bool,
+        buffers: &mut ConnectionBuffers,
+    ) -> Result<http::request::Builder> {
         // Read the request line (e.g., "GET /path HTTP/1.1")
-        let mut request_line = String::new();
-        reader.read_line(&mut request_line).await?;
+        let request_line = &mut buffers.request_line;
+        request_line.clear();
+        // the method and version are short, so a request line over the limit is a long target
+        let read = read_until_limited(reader, request_line, limits.max_request_line, LimitError::UriTooLong).await?;
+        if read == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let request_line = std::str::from_utf8(request_line).map_err(|_| Error::parse("Invalid request line"))?;
 
         // Parse the request line into components
-        let mut parts = request_line.trim().split_whitespace();
-        let method = parts.next().ok_or(box_err!("Failed to parse method"))?;
-        let uri = parts.next().ok_or(box_err!("Failed to parse URI"))?;
-        let version = parts.next().ok_or(box_err!("Failed to parse version"))?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().ok_or(Error::parse("Failed to parse method"))?;
+        let uri = parts.next().ok_or(Error::parse("Failed to parse URI"))?;
+        let version = parts.next().ok_or(Error::parse("Failed to parse version"))?;
+        if uri.len() > limits.max_uri_length {
+            return Err(LimitError::UriTooLong.into());
+        }
 
         // Convert components into appropriate types for Request
-        let method = Method::from_str(method)?;
-        let uri = Uri::from_str(uri)?;
+        let method = Method::from_str(method).map_err(|_| Error::parse("Invalid method"))?;
+        let uri = Uri::from_str(uri).map_err(|_| Error::parse("Invalid request target"))?;
         let version = match version {
             "HTTP/1.0" => Version::HTTP_10,
             "HTTP/1.1" => Version::HTTP_11,
-            "HTTP/2.0" => Version::HTTP_2,
-            _ => return Err(box_err!("Unsupported HTTP version")),
+            version if version.starts_with("HTTP/") => return Err(Error::UnsupportedVersion),
+            _ => return Err(Error::parse("Invalid HTTP version")),
         };
 
         // Create a new request builder
         let mut request_builder = Request::builder().method(method).uri(uri).version(version);
 
-        // Read the HTTP headers
+        // Accumulate the whole header block, up to the empty line that ends it
+        let header_block = &mut buffers.header_block;
+        header_block.clear();
         loop {
-            let mut header_line = String::new();
-            reader.read_line(&mut header_line).await?;
+            let line_start = header_block.len();
+            let remaining = limits.max_header_size.saturating_sub(line_start);
+            let read = read_until_limited(reader, header_block, remaining, LimitError::HeaderTooLarge).await?;
+            if read == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
 
-            // An empty line indicates the end of the headers
-            if header_line == "\r\n" {
+            let line = &header_block[line_start..];
+            if line == b"\r\n" || line == b"\n" {
+                header_block.truncate(line_start);
                 break;
             }
+        }
 
-            // Split the header into key and value
-            let mut header_parts = header_line.trim().splitn(2, ':');
-            let key = header_parts.next().ok_or(box_err!("Failed to parse header key"))?;
-            let value = header_parts.next().ok_or(box_err!("Failed to parse header value"))?;
+        let mut headers = parse_header_block(header_block, limits.max_header_count, strict)?;
+        if let Some(uri) = request_builder.uri_ref() {
+            validate_host(uri, &mut headers, version)?;
+        }
+        if !strict && headers.contains_key(TRANSFER_ENCODING) && headers.remove(CONTENT_LENGTH).is_some() {
+            // the body is read as chunked, and the connection is closed after the response in
+            // case whatever sent the request framed it by its length instead
+            log::warn!("request with both Transfer-Encoding and Content-Length");
+            headers.insert(CONNECTION, HeaderValue::from_static("close"));
+        }
+        if let Some(builder_headers) = request_builder.headers_mut() {
+            *builder_headers = headers;
+        }
+
+        Ok(request_builder)
+    }
+
+    async fn read_request_body<R: AsyncBufRead + Unpin>(
+        reader: &mut R,
+        request_builder: &http::request::Builder,
+        limits: &Limits,
+    ) -> Result<(Vec<u8>, HeaderMap)> {
+        let (Some(headers), Some(version)) = (request_builder.headers_ref(), request_builder.version_ref()) else {
+            return Err(Error::parse("Invalid request head"));
+        };
 
-            // Add the header to the request builder
-            request_builder = request_builder.header(key.trim(), value.trim());
+        match request_framing(headers, *version)? {
+            BodyFraming::Length(length) => {
+                if length > limits.max_body_size {
+                    return Err(Error::BodyTooLarge);
+                }
+
+                // Read the specified number of bytes from the request body
+                let mut request_body = vec![0; length];
+                reader.read_exact(&mut request_body).await?;
+                Ok((request_body, HeaderMap::new()))
+            }
+            BodyFraming::Chunked => chunked::read_body(reader, limits.max_body_size).await,
         }
+    }
 
-        // Extract the Content-Length header if it exists
-        let mut request_body = Vec::new();
-        if let Some(length) = request_builder
-            .headers_ref()
-            .and_then(|headers| headers.get("content-length")) // TODO: case-sensitive?
-        {
-            let length = length
-                .to_str()
-                .map_err(|_| box_err!("Invalid Content-Length header"))?
-                .parse::<usize>()
-                .map_err(|_| box_err!("Content-Length is not a valid number"))?;
+    async fn read_http_request<R: AsyncBufRead + Unpin>(
+        reader: &mut R,
+        config: &ConnectionConfig,
+        router: &Router,
+        buffers: &mut ConnectionBuffers,
+    ) -> Result<Request<Vec<u8>>> {
+        let started = Instant::now();
 
-            // Read the specified number of bytes from the request body
-            request_body.resize(length, 0);
-            reader.read_exact(&mut request_body).await?;
+        // Read the request line and headers, bounded so a slow client can't hold the task forever
+        let read_head = with_timeout(config.timeouts.header_read, "header read", Self::read_request_head(reader, &config.limits, !config.lenient_parsing, buffers));
+        let request_builder = with_timeout(config.timeouts.request, "request", read_head).await?;
+
+        // The route the request is headed for may allow a larger body or more time to send it
+        let route_options = match (request_builder.method_ref(), request_builder.uri_ref()) {
+            (Some(method), Some(uri)) => router.route_options(method, uri.path()),
+            _ => RouteOptions::default(),
+        };
+        let mut limits = config.limits;
+        if let Some(max_body_size) = route_options.max_body_size {
+            limits.max_body_size = max_body_size;
         }
 
-        // TODO: support more request body types like chunked, multipart, etc.
+        // Read the body under its own deadline, and what is left of the whole-request one
+        let read_body = Self::read_request_body(reader, &request_builder, &limits);
+        let (request_body, trailers) = match route_options.read_timeout {
+            Some(read_timeout) => with_timeout(Some(read_timeout), "body read", read_body).await?,
+            None => {
+                let remaining = config.timeouts.request.map(|request| request.saturating_sub(started.elapsed()));
+                let read_body = with_timeout(config.timeouts.body_read, "body read", read_body);
+                with_timeout(remaining, "request", read_body).await?
+            }
+        };
 
         // Build the request with the body
-        let request = request_builder.body(request_body)?;
+        let mut request = request_builder.body(request_body)?;
+        if !trailers.is_empty() {
+            request.extensions_mut().insert(Trailers(trailers));
+        }
 
         Ok(request)
     }
 
-    async fn handle_request<S: AsyncRead + AsyncWrite + Unpin>(
-        router: Arc<Router>,
-        mut stream: S,
-    ) -> SimpleResult<()> {
-        // read request
-        let request = Self::read_http_request(&mut stream).await?;
-    
-        // Route requests by method + path
-        let response = router.route(request).await?;
-    
-        // Write the status line
-        let status_line = format!(
-            "{:?} {} {}\r\n",
-            response.version(),
-            response.status().as_str(),
-            response.status().canonical_reason().unwrap_or("")
-        );
-        stream.write_all(status_line.as_bytes()).await?;
-    
-        // Write headers
-        for (name, value) in response.headers() {
-            let header_line = format!("{}: {}\r\n", name, value.to_str()?);
-            stream.write_all(header_line.as_bytes()).await?;
-        }
-    
-        // Add Content-Length header if not present
-        if !response.headers().contains_key("content-length") {
-            let content_length = format!("Content-Length: {}\r\n", response.body().len());
-            stream.write_all(content_length.as_bytes()).await?;
-        }
-    
-        // Write the empty line that separates headers from body
-        stream.write_all(b"\r\n").await?;
-    
-        // Write the body
-        stream.write_all(response.body().as_bytes()).await?;
+    /// Writes `response` to the connection, as the answer to a `method` request. Clients that
+    /// can't take `chunked` (HTTP/1.0 ones) get a body of unknown length delimited by the
+    /// connection closing instead, without trailers; the caller closes the connection after such
+    /// a response
+    async fn write_response<S: AsyncWrite + Unpin>(
+        stream: &mut CountingStream<S>,
+        response: Response<Body>,
+        head: &mut Vec<u8>,
+        method: &Method,
+        accepts_chunked: bool,
+        sendfile: Option<SendFileSocket>,
+    ) -> Result<()> {
+        let (parts, mut body) = response.into_parts();
+
+        // Serialize the status line and headers into one buffer
+        head.clear();
+        head.extend_from_slice(match parts.version {
+            Version::HTTP_10 => b"HTTP/1.0 ",
+            _ => b"HTTP/1.1 ",
+        });
+        head.extend_from_slice(parts.status.as_str().as_bytes());
+        head.push(b' ');
+        head.extend_from_slice(parts.status.canonical_reason().unwrap_or("").as_bytes());
+        head.extend_from_slice(b"\r\n");
+
+        // trailers need chunked framing, so they replace any Content-Length
+        let trailers = body.trailers.take();
+        let sends_trailers = accepts_chunked && trailers.is_some() && parts.headers.contains_key(TRAILER);
+
+        // framing is decided here, so any Transfer-Encoding from the handler is dropped
+        let framing_header = |name: &HeaderName| *name == TRANSFER_ENCODING || (sends_trailers && *name == CONTENT_LENGTH);
+        for (name, value) in parts.headers.iter().filter(|(name, _)| !framing_header(name)) {
+            head.extend_from_slice(name.as_str().as_bytes());
+            head.extend_from_slice(b": ");
+            head.extend_from_slice(value.as_bytes());
+            head.extend_from_slice(b"\r\n");
+        }
+
+        // 1xx, 204 and 304 responses never carry a body, or framing for one, and neither do
+        // responses handing the connection over to another protocol
+        let status = parts.status;
+        let hands_over = parts.extensions.get::<OnUpgrade>().is_some();
+        if status.is_informational() || status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED || hands_over {
+            head.extend_from_slice(b"\r\n");
+            stream.write_all(head).await?;
+            stream.flush().await?;
+            return Ok(());
+        }
+
+        // Add Content-Length header if not present, falling back to chunked for streams of unknown length
+        let has_content_length = parts.headers.contains_key(CONTENT_LENGTH);
+        let chunked = match body.content_length() {
+            _ if sends_trailers => {
+                head.extend_from_slice(b"Transfer-Encoding: chunked\r\n");
+                true
+            }
+            Some(length) => {
+                if !has_content_length {
+                    write!(head, "Content-Length: {length}\r\n")?;
+                }
+                false
+            }
+            None if has_content_length || !accepts_chunked => false,
+            None => {
+                head.extend_from_slice(b"Transfer-Encoding: chunked\r\n");
+                true
+            }
+        };
+
+        // The empty line that separates headers from body
+        head.extend_from_slice(b"\r\n");
+
+        // a HEAD response has the headers, framing included, that a GET would get, but no body
+        if method == Method::HEAD {
+            stream.write_all(head).await?;
+            stream.flush().await?;
+            return Ok(());
+        }
+
+        // Write the body, together with the head when it is already in memory
+        match (body.inner, sendfile) {
+            (BodyInner::Full(bytes), _) if !chunked => write_all_vectored(stream, [&head[..], &bytes[..]]).await?,
+            (BodyInner::Shared(bytes), _) if !chunked => write_all_vectored(stream, [&head[..], &bytes[..]]).await?,
+            #[cfg(feature = "bytes")]
+            (BodyInner::Bytes(bytes), _) if !chunked => write_all_vectored(stream, [&head[..], &bytes[..]]).await?,
+            // the head is on the socket before the kernel appends the file to it
+            (BodyInner::File { file, length }, Some(socket)) if !chunked => {
+                stream.write_all(head).await?;
+                stream.flush().await?;
+                socket.send(&file, length).await?;
+                stream.count_written(length);
+            }
+            (inner, _) => {
+                let mut chunks = Body { inner, trailers: None }.into_stream();
+                stream.write_all(head).await?;
+                while let Some(chunk) = chunks.next().await {
+                    let chunk = chunk?;
+                    if chunked {
+                        chunked::write_chunk(stream, &chunk).await?;
+                    } else {
+                        stream.write_all(&chunk).await?;
+                    }
+                }
+                if chunked {
+                    let trailers = match trailers {
+                        Some(trailers) if sends_trailers => Some(declared_trailers(&parts.headers, trailers.await)),
+                        _ => None,
+                    };
+                    chunked::write_last_chunk(stream, trailers.as_ref()).await?;
+                }
+            }
+        }
         stream.flush().await?;
-        
+
         Ok(())
     }
 
+    fn error_response(status: StatusCode) -> Result<Response<Body>> {
+        let response_body = status.canonical_reason().unwrap_or("");
+        Ok(Response::builder()
+            .status(status)
+            .version(Version::HTTP_11)
+            .header("Content-Type", "text/plain")
+            .header("Connection", "close")
+            .body(Body::from(response_body))?)
+    }
+
+    /// The first header whose value would break out of its line when written. `HeaderValue`'s
+    /// checked constructors already refuse these bytes, but the unchecked ones don't
+    fn unsafe_header(headers: &HeaderMap) -> Option<&HeaderName> {
+        headers.iter().find(|(_, value)| Self::breaks_header_line(value.as_bytes())).map(|(name, _)| name)
+    }
+
+    fn breaks_header_line(value: &[u8]) -> bool {
+        value.iter().any(|byte| matches!(byte, b'\r' | b'\n' | b'\0'))
+    }
+
+    /// Closes our side of the connection after an error response, then discards whatever the
+    /// client is still sending for a moment. Closing with unread request bytes would make the
+    /// kernel reset the connection, possibly before the client has read the response
+    async fn lingering_close<S: AsyncRead + AsyncWrite + Unpin>(reader: &mut BufReader<S>) {
+        if reader.get_mut().close().await.is_err() {
+            return;
+        }
+        let drain = async {
+            let mut discard = [0; 4096];
+            let mut drained = 0;
+            while drained < LINGER_MAX_BYTES {
+                match reader.read(&mut discard).await? {
+                    0 => break,
+                    read => drained += read,
+                }
+            }
+            Ok::<_, Error>(())
+        };
+        let _ = with_timeout(Some(LINGER_TIMEOUT), "lingering close", drain).await;
+    }
+
+    /// Resolves once the connection fails while a request is being handled, or, with
+    /// `cancel_on_eof`, once the client closes its side. Otherwise an EOF is a half-close from a
+    /// client still waiting for its response. Buffered bytes are a pipelined request, which
+    /// can't be looked past without reading it, so while there are any this never resolves
+    async fn wait_for_hangup<R: AsyncBufRead + Unpin>(reader: &mut R, cancel_on_eof: bool) {
+        match reader.fill_buf().await {
+            Err(_) => {}
+            Ok(buffered) if buffered.is_empty() && cancel_on_eof => {}
+            Ok(_) => future::pending::<()>().await,
+        }
+    }
+
+    /// Waits for the first byte of the next request on an idle keep-alive connection
+    async fn wait_for_next_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<bool> {
+        match reader.fill_buf().await {
+            Ok(buffered) => Ok(!buffered.is_empty()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Whether a plaintext connection opens with the HTTP/2 preface, `PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n`.
+    /// `PRI` isn't a method HTTP/1 clients send, so its first line is enough to tell
+    #[cfg(feature = "http2")]
+    async fn starts_with_h2_preface<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<bool> {
+        Ok(reader.fill_buf().await?.starts_with(b"PRI * HTTP/2.0"))
+    }
+
+    /// Readies a parsed request for the router as every HTTP/1 connection does: completes its
+    /// URI, inserts the connection extensions, runs the `on_request` hook and hands it an
+    /// [`Informational`] sender (on HTTP/1.1) and a [`Cancellation`] token. Returns the token
+    /// and the receiving end of the informational responses
+    pub(crate) fn prepare_request(
+        request: &mut Request<Vec<u8>>,
+        connection_info: &ConnectionInfo,
+        config: &ConnectionConfig,
+    ) -> (Cancellation, Option<async_channel::Receiver<(StatusCode, HeaderMap)>>) {
+        complete_uri(request, connection_info.tls);
+        insert_connection_extensions(request, connection_info, config.trusted_proxies.as_ref());
+        if let Some(hooks) = &config.hooks {
+            hooks.on_request(request, connection_info);
+        }
+
+        // HTTP/1.0 clients don't expect anything before the final response
+        let interim = (request.version() == Version::HTTP_11).then(|| {
+            let (informational, interim) = Informational::channel();
+            request.extensions_mut().insert(informational);
+            interim
+        });
+
+        let cancellation = Cancellation::default();
+        request.extensions_mut().insert(cancellation.clone());
+        (cancellation, interim)
+    }
+
+    async fn handle_connection<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+        router: Arc<Router>,
+        stream: S,
+        config: Arc<ConnectionConfig>,
+        connection_info: ConnectionInfo,
+        sendfile: Option<SendFileSocket>,
+    ) -> Result<()> {
+        // The reader, and so its buffer, is owned here for the life of the connection: bytes read
+        // past one request (pipelined requests, or a body that arrived with its headers) are
+        // still buffered when the next read starts
+        let mut reader = BufReader::new(CountingStream::new(stream));
+        let mut buffers = ConnectionBuffers::default();
+        let mut requests_served = 0;
+
+        #[cfg(feature = "http2")]
+        if config.h2c && !connection_info.tls && with_timeout(config.timeouts.header_read, "h2c preface", Self::starts_with_h2_preface(&mut reader)).await? {
+            // h2 reads the preface itself, so hand it the connection with nothing consumed
+            return crate::http2::serve_connection(router, Rewind::from_reader(reader), config, connection_info).await.map_err(Error::from);
+        }
+
+        loop {
+            // idle keep-alive connections are closed quietly on EOF, idle timeout or shutdown
+            if requests_served > 0 {
+                let idle_timeout = config.keep_alive.idle_timeout;
+                let next_request = async {
+                    matches!(with_timeout(idle_timeout, "keep-alive idle", Self::wait_for_next_request(&mut reader)).await, Ok(true))
+                };
+                let draining = async {
+                    config.shutdown.draining().await;
+                    false
+                };
+                if !future::or(next_request, draining).await {
+                    return Ok(());
+                }
+            }
+
+            // read request, counting the bytes it takes up: whatever was read off the socket
+            // less what is still buffered for the next one
+            let started = Instant::now();
+            let consumed = |reader: &BufReader<CountingStream<S>>| reader.get_ref().bytes_read() - reader.buffer().len() as u64;
+            let read_before = consumed(&reader);
+            let written_before = reader.get_ref().bytes_written();
+            let mut request = match Self::read_http_request(&mut reader, &config, &router, &mut buffers).await {
+                Ok(request) => request,
+                // the client closed the connection without sending a request
+                Err(Error::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => {
+                    // Malformed requests, timeouts and limit violations get a response before the
+                    // connection is closed, so clients see an error rather than a reset
+                    let status = match err {
+                        Error::Io(_) => return Err(err),
+                        _ => err.status(),
+                    };
+                    log::warn!("closing connection: {err}");
+                    let mut response = Self::error_response(status)?;
+                    config.default_headers.apply(response.headers_mut());
+                    // the request couldn't be read, so the error is answered as if to a GET
+                    Self::write_response(reader.get_mut(), response, &mut buffers.response_head, &Method::GET, true, None).await?;
+                    Self::lingering_close(&mut reader).await;
+                    return Ok(());
+                }
+            };
+            requests_served += 1;
+            let bytes_read = consumed(&reader) - read_before;
+
+            #[cfg(feature = "http2")]
+            if config.h2c && !connection_info.tls && crate::http2::wants_h2c_upgrade(&request) {
+                return crate::http2::serve_upgraded_connection(router, reader, request, config, connection_info).await;
+            }
+            let (cancellation, interim) = Self::prepare_request(&mut request, &connection_info, &config);
+
+            let mut keep_alive = config.keep_alive.enabled
+                && request_wants_keep_alive(&request)
+                && config.keep_alive.max_requests.is_none_or(|max| requests_served < max);
+            let request_version = request.version();
+
+            // Route requests by method + path, writing out any 1xx responses while the handler
+            // runs, and dropping the handler if the client hangs up before it's done
+            let method = request.method().clone();
+            let path = request.uri().path().to_string();
+            let handler_started = Instant::now();
+            let mut route = pin!(in_request_span(&method, &path, router.route(request)));
+            let mut response = loop {
+                let routed = async { RouteEvent::Routed(route.as_mut().await) };
+                let next_interim = async {
+                    match &interim {
+                        Some(interim) => match interim.recv().await {
+                            Ok(informational) => RouteEvent::Informational(informational),
+                            // unreachable while the request holds the sender
+                            Err(_) => future::pending().await,
+                        },
+                        None => future::pending().await,
+                    }
+                };
+                let hangup = async {
+                    Self::wait_for_hangup(&mut reader, config.cancel_on_eof).await;
+                    RouteEvent::HungUp
+                };
+                let event = future::or(routed, future::or(next_interim, hangup)).await;
+                match event {
+                    RouteEvent::Routed(response) => break response?,
+                    RouteEvent::Informational((status, headers)) => {
+                        if let Some(name) = Self::unsafe_header(&headers) {
+                            log::error!("not sending {status} response: invalid {name} header value");
+                            continue;
+                        }
+                        let mut informational = Response::new(Body::empty());
+                        *informational.status_mut() = status;
+                        *informational.headers_mut() = headers;
+                        if let Err(err) = Self::write_response(reader.get_mut(), informational, &mut buffers.response_head, &method, true, None).await {
+                            cancellation.cancel();
+                            return Err(err);
+                        }
+                    }
+                    RouteEvent::HungUp => {
+                        log::debug!("client hung up before the response to {method} {path}");
+                        cancellation.cancel();
+                        return Ok(());
+                    }
+                }
+            };
+            let handler_duration = handler_started.elapsed();
+            if config.server_timing {
+                append_server_timing(response.headers_mut(), handler_duration);
+            }
+
+            // a line break in a header value would let it inject headers, or a whole response
+            if let Some(name) = Self::unsafe_header(response.headers()) {
+                log::error!("not sending {} response: invalid {name} header value", response.status());
+                response = Self::error_response(StatusCode::INTERNAL_SERVER_ERROR)?;
+            }
+
+            let on_upgrade = response.extensions().get::<OnUpgrade>().and_then(OnUpgrade::take);
+            if response.status() == StatusCode::SWITCHING_PROTOCOLS || on_upgrade.is_some() {
+                config.default_headers.apply(response.headers_mut());
+                let status = response.status();
+                Self::write_response(reader.get_mut(), response, &mut buffers.response_head, &method, true, None).await?;
+                if let Some(hooks) = &config.hooks {
+                    let metrics = RequestMetrics {
+                        bytes_read,
+                        bytes_written: reader.get_ref().bytes_written() - written_before,
+                        handler_duration,
+                        elapsed: started.elapsed(),
+                    };
+                    hooks.on_response(&connection_info, &method, &path, status, &metrics);
+                }
+                // the connection now speaks the new protocol, starting with anything already buffered
+                if let Some(on_upgrade) = on_upgrade {
+                    on_upgrade(Box::new(Rewind::from_reader(reader))).await;
+                }
+                return Ok(());
+            }
+
+            config.default_headers.apply(response.headers_mut());
+            // a handler may ask for the connection to close, but its other connection headers are
+            // dropped. HTTP/1.0 clients can't take chunked, so a body of unknown length ends with
+            // the connection
+            let close_delimited = request_version == Version::HTTP_10
+                && method != Method::HEAD
+                && response.body().content_length().is_none()
+                && !response.headers().contains_key(CONTENT_LENGTH);
+            keep_alive = keep_alive && !close_delimited && !response_wants_close(&response) && !config.shutdown.is_draining();
+            hop_by_hop::strip_from_response(response.headers_mut());
+            if !keep_alive {
+                response.headers_mut().insert(CONNECTION, HeaderValue::from_static("close"));
+            } else if request_version == Version::HTTP_10 {
+                response.headers_mut().insert(CONNECTION, HeaderValue::from_static("keep-alive"));
+            }
+
+            let status = response.status();
+            Self::write_response(reader.get_mut(), response, &mut buffers.response_head, &method, request_version != Version::HTTP_10, sendfile).await?;
+            if let Some(hooks) = &config.hooks {
+                let metrics = RequestMetrics {
+                    bytes_read,
+                    bytes_written: reader.get_ref().bytes_written() - written_before,
+                    handler_duration,
+                    elapsed: started.elapsed(),
+                };
+                hooks.on_response(&connection_info, &method, &path, status, &metrics);
+            }
+
+            if !keep_alive {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Serves HTTP/3 over QUIC on the UDP port at `addr`, with the same settings as this server
+    #[cfg(feature = "http3")]
+    pub(crate) async fn serve_quic(&self, addr: &str, tls_config: ServerConfig, router: Arc<Router>) -> Result<()> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Failed to build host"))?;
+        crate::http3::serve(addr, tls_config, router, self.config.clone()).await
+    }
+
+    pub(crate) fn bind(addr: &str, socket_options: &SocketOptions) -> Result<TcpListener> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Failed to build host"))?;
+        Ok(socket_options.bind(addr)?)
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn bind_unix(path: &std::path::Path) -> Result<Async<std::os::unix::net::UnixListener>> {
+        use std::os::unix::fs::FileTypeExt as _;
+
+        // a socket file left behind by a previous run would make bind fail
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(Async::<std::os::unix::net::UnixListener>::bind(path)?)
+    }
+
     pub async fn run_server(
-        executor: Arc<Executor<'static>>,
         host: &str,
         port: u16,
         router: Arc<Router>,
         tls_config: Option<(String, String)>,
-    ) -> SimpleResult<()> {
+    ) -> Result<()> {
         let server = if let Some((cert_path, key_path)) = tls_config {
             Self::with_tls(&cert_path, &key_path)?
         } else {
             Self::new()
         };
 
+        server.listen(host, port, router).await
+    }
+
+    /// Serves `builder` on a fresh executor driven by `threads` threads, the calling one
+    /// included, and blocks until the server shuts down, e.g.
+    /// `HttpServer::run_blocking(4, HttpServer::builder().bind("0.0.0.0:8080"), |router| router.add_handler(Method::GET, "/", index))`.
+    /// With the `signals` feature, SIGINT and SIGTERM start a graceful shutdown
+    pub fn run_blocking(threads: usize, builder: HttpServerBuilder, routes: impl FnOnce(&mut Router)) -> Result<()> {
+        let executor = Arc::new(Executor::new());
+        let mut router = Router::new(executor.clone());
+        routes(&mut router);
+        #[cfg(feature = "signals")]
+        let builder = builder.shutdown_on_signals();
+
+        // workers run the executor until the sender is dropped, once the server has finished
+        let (stop, stopped) = async_channel::bounded::<()>(1);
+        let workers = (1..threads.max(1))
+            .map(|worker| {
+                let executor = executor.clone();
+                let stopped = stopped.clone();
+                std::thread::Builder::new()
+                    .name(format!("http-server-worker-{worker}"))
+                    .spawn(move || async_io::block_on(executor.run(async { while stopped.recv().await.is_ok() {} })))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let result = async_io::block_on(executor.run(builder.serve(Arc::new(router))));
+        drop(stop);
+        for worker in workers {
+            if worker.join().is_err() {
+                log::error!("executor worker thread panicked");
+            }
+        }
+        result
+    }
+
+    pub async fn listen(
+        &self,
+        host: &str,
+        port: u16,
+        router: Arc<Router>,
+    ) -> Result<()> {
         // bind listener
-        let addr = format!("{host}:{port}")
-            .to_socket_addrs()?
-            .next()
-            .ok_or("Failed to build host")?;
-        let listener = Async::<TcpListener>::bind(addr)?;
+        let listener = Async::new(Self::bind(&format!("{host}:{port}"), &self.config.socket_options)?)?;
+
+        self.accept_loop(listener, router).await
+    }
+
+    /// Serves connections from a listener that was bound (or inherited) by the caller
+    pub async fn serve_listener(
+        &self,
+        listener: TcpListener,
+        router: Arc<Router>,
+    ) -> Result<()> {
+        let listener = Async::new(listener)?;
+
+        self.accept_loop(listener, router).await
+    }
+
+    /// Serves HTTP on a connection accepted by the caller, e.g. an in-memory pipe in tests or a
+    /// stream from another TLS stack, until it closes. The stream is read as plaintext: it
+    /// carries HTTP/1.x, or HTTP/2 when it opens with the HTTP/2 preface and h2c is enabled.
+    /// `connection_info` is what handlers see as the [`ConnectionInfo`]. The IP filter and
+    /// connection limits, which apply at accept time, are not consulted
+    pub async fn serve_connection<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+        &self,
+        stream: S,
+        connection_info: ConnectionInfo,
+        router: Arc<Router>,
+    ) -> Result<()> {
+        let _connection_hooks = self.config.hooks.as_ref().map(|hooks| ConnectionHooks::connect(hooks, &connection_info));
+        Self::handle_connection(router, stream, self.config.clone(), connection_info, None).await
+    }
+
+    /// Serves connections from a Unix domain socket at `path`, e.g. behind a reverse proxy
+    #[cfg(unix)]
+    pub async fn run_server_uds(
+        path: impl AsRef<std::path::Path>,
+        router: Arc<Router>,
+    ) -> Result<()> {
+        let listener = Self::bind_unix(path.as_ref())?;
+
+        Self::new().accept_loop(listener, router).await
+    }
+
+    /// Accepts connections until the listener itself fails or the server starts draining, serving
+    /// each on the router's spawner. Failed accepts that concern only one connection, or running
+    /// out of descriptors, are logged and retried
+    pub(crate) async fn accept_loop<L: Listener>(
+        &self,
+        listener: L,
+        router: Arc<Router>,
+    ) -> Result<()> {
+        let limiter = ConnectionLimiter::new(&self.config.limits);
 
         // handle request
         loop {
-            let (stream, _) = listener.accept().await?;
+            // wait for a free connection slot before accepting more work
+            let global_permit = limiter.reserve().await;
+            let accepted = future::or(async { Some(listener.accept_stream().await) }, async {
+                self.config.shutdown.draining().await;
+                None
+            });
+            let Some(accepted) = accepted.await else {
+                log::info!("draining, no longer accepting connections");
+                return Ok(());
+            };
+            let (stream, mut connection_info) = match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => match accept_retry_delay(&err) {
+                    Some(delay) => {
+                        log::warn!("failed to accept connection err = {err:?}");
+                        if !delay.is_zero() {
+                            Timer::after(delay).await;
+                        }
+                        continue;
+                    }
+                    // the listener itself is gone
+                    None => return Err(err.into()),
+                },
+            };
             log::info!("accepted new connection");
+            if let Err(err) = L::configure_stream(&stream, &self.config.socket_options) {
+                log::warn!("failed to set socket options err = {err:?}");
+            }
+            let sendfile = L::sendfile_socket(&stream);
+
+            let Some(connection_guard) = self.config.shutdown.track_connection() else {
+                continue;
+            };
+
+            let peer_ip = connection_info.remote_addr.map(|remote_addr| remote_addr.ip());
+            if let (Some(ip_filter), Some(ip)) = (&self.config.ip_filter, peer_ip) {
+                if !ip_filter.is_allowed(ip) {
+                    log::warn!("refused connection from {ip}");
+                    continue;
+                }
+            }
+            let Some(permit) = limiter.admit(global_permit, peer_ip) else {
+                log::warn!("too many connections from {peer_ip:?}, closing");
+                continue;
+            };
         
-            match server.accept_connection(stream).await {
-                Ok(connection) => {
-                    let task = executor.spawn({
-                        let router = router.clone();
-                        async move {
-                            if let Err(err) = Self::handle_request(router, connection).await {
-                                log::error!("error handling request err = {err:?}");
+            // the handshake happens on the connection's task, with the certificate current at accept time
+            let tls_acceptor = self.tls.acceptor();
+            router.spawner().spawn(Box::pin({
+                let router = router.clone();
+                let config = self.config.clone();
+                let span_info = connection_info.clone();
+                in_connection_span(&span_info, async move {
+                    let _permit = permit;
+                    let _connection_guard = connection_guard;
+                    let shutdown = config.shutdown.clone();
+                    let serve = async move {
+                        let connection = match Self::accept_connection(stream, tls_acceptor, &config, &mut connection_info).await {
+                            Ok(connection) => connection,
+                            Err(err) => {
+                                log::warn!("Failed to establish connection: {:?}", err);
+                                return Ok(());
                             }
+                        };
+                        let _connection_hooks = config.hooks.as_ref().map(|hooks| ConnectionHooks::connect(hooks, &connection_info));
+                        // the kernel can only copy files to connections it doesn't have to encrypt
+                        let sendfile = sendfile.filter(|_| !connection_info.tls);
+                        match connection_info.alpn_protocol.clone().as_deref() {
+                            #[cfg(feature = "http2")]
+                            Some(crate::http2::ALPN_H2) => crate::http2::serve_connection(router, connection, config, connection_info).await.map_err(Error::from),
+                            _ => Self::handle_connection(router, connection, config, connection_info, sendfile).await,
                         }
-                    });
-                    task.detach();
+                    };
+                    // connections still open when the shutdown grace period ends are dropped
+                    let closed = async {
+                        shutdown.closing().await;
+                        Ok(())
+                    };
+                    if let Err(err) = future::or(serve, closed).await {
+                        log::error!("error handling request err = {err:?}");
+                    }
+                })
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use futures_lite::future::block_on;
+    use futures_lite::io::Cursor;
+    use simple_error::SimpleResult;
+
+    use super::*;
+    use crate::response::{IntoResponse as _, Text};
+    use crate::spawner::{Spawner, ThreadPoolSpawner};
+    use crate::types::BoxFuture;
+
+    /// A connection whose client sends `input` then closes its side, keeping what the server
+    /// writes back
+    struct MockConnection {
+        input: Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl AsyncRead for MockConnection {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.input).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for MockConnection {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.output.lock().unwrap().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Serves `input` as one connection and returns everything written back
+    fn exchange(router: Router, input: &str) -> String {
+        exchange_with(router, ConnectionConfig::default(), input)
+    }
+
+    fn exchange_with(router: Router, config: ConnectionConfig, input: &str) -> String {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let connection = MockConnection {
+            input: Cursor::new(input.as_bytes().to_vec()),
+            output: output.clone(),
+        };
+        let config = Arc::new(config);
+        let _ = block_on(HttpServer::handle_connection(Arc::new(router), connection, config, ConnectionInfo::default(), None));
+        let output = output.lock().unwrap();
+        String::from_utf8_lossy(&output).into_owned()
+    }
+
+    // http only skips validating "unchecked" header values in release builds, and panics on
+    // invalid ones otherwise, so the tests needing one only run under `cargo test --release`
+    #[cfg(not(debug_assertions))]
+    fn unchecked_value(value: &'static str) -> HeaderValue {
+        // SAFETY: the value is a `&str`, so valid UTF-8
+        unsafe { HeaderValue::from_maybe_shared_unchecked(value.as_bytes()) }
+    }
+
+    #[test]
+    fn finds_line_breaks_and_nul_in_header_values() {
+        for value in ["a\rb", "a\nb", "a\0b", "a\r\nSet-Cookie: b", "\n"] {
+            assert!(HttpServer::breaks_header_line(value.as_bytes()), "missed {value:?}");
+        }
+        for value in ["", "plain", "tabs\tand obs-text \u{e9} are fine"] {
+            assert!(!HttpServer::breaks_header_line(value.as_bytes()), "refused {value:?}");
+        }
+        let mut headers = HeaderMap::new();
+        headers.insert("x-safe", HeaderValue::from_static("fine"));
+        assert_eq!(HttpServer::unsafe_header(&headers), None);
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[test]
+    fn names_the_unsafe_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-safe", HeaderValue::from_static("fine"));
+        headers.insert("x-unsafe", unchecked_value("a\r\nSet-Cookie: b"));
+        assert_eq!(HttpServer::unsafe_header(&headers).map(HeaderName::as_str), Some("x-unsafe"));
+    }
+
+    #[test]
+    fn header_names_cannot_carry_line_breaks_or_nul() {
+        for name in [&b"x-a\rb"[..], b"x-a\nb", b"x-a\0b"] {
+            assert!(HeaderName::from_bytes(name).is_err());
+            let mut block = name.to_vec();
+            block.extend_from_slice(b": value\r\n");
+            assert!(parse_header_block(&block, 100, true).is_err());
+        }
+    }
+
+    #[test]
+    fn sends_responses_with_safe_headers_as_they_are() {
+        async fn tagged() -> Response<Body> {
+            let mut response = Response::new(Body::from("hello"));
+            response.headers_mut().insert("x-tag", HeaderValue::from_static("a\tb"));
+            response
+        }
+        let router = Router::new(Arc::new(ThreadPoolSpawner));
+        router.add_handler(Method::GET, "/", tagged);
+
+        let output = exchange(router, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(output.starts_with("HTTP/1.1 200 "), "{output}");
+        assert!(output.contains("x-tag: a\tb\r\n"), "{output}");
+        assert!(output.ends_with("\r\n\r\nhello"), "{output}");
+    }
+
+    #[test]
+    fn answers_head_requests_with_headers_only() {
+        let router = Router::new(Arc::new(ThreadPoolSpawner));
+        for method in [Method::GET, Method::HEAD] {
+            router.add_route(method, "/", Arc::new(|_spawner: Arc<dyn Spawner>, _request: Request<Vec<u8>>| -> BoxFuture<'static, SimpleResult<Response<Body>>> {
+                Box::pin(async move { Ok(Response::new(Body::from("hello"))) })
+            }));
+        }
+        router.add_route(Method::HEAD, "/stream", Arc::new(|_spawner: Arc<dyn Spawner>, _request: Request<Vec<u8>>| -> BoxFuture<'static, SimpleResult<Response<Body>>> {
+            Box::pin(async move { Ok(Response::new(Body::from_reader(Cursor::new(b"hello".to_vec()), None))) })
+        }));
+
+        // the connection stays usable, so nothing follows the head but the next response
+        let output = exchange(router, "HEAD / HTTP/1.1\r\nHost: a\r\n\r\nHEAD /stream HTTP/1.1\r\nHost: a\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        let responses = output.split("HTTP/1.1 ").skip(1).collect::<Vec<_>>();
+        assert_eq!(responses.len(), 3, "{output}");
+        assert!(responses[0].contains("Content-Length: 5\r\n"), "{output}");
+        assert!(responses[0].ends_with("\r\n\r\n"), "{output}");
+        assert!(responses[1].contains("Transfer-Encoding: chunked\r\n"), "{output}");
+        assert!(responses[1].ends_with("\r\n\r\n"), "{output}");
+        assert!(responses[2].ends_with("\r\n\r\nhello"), "{output}");
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[test]
+    fn replaces_responses_with_unsafe_headers_with_a_500() {
+        async fn injected() -> Response<Body> {
+            let mut response = Response::new(Body::from("hello"));
+            response.headers_mut().insert("x-injected", unchecked_value("a\r\nSet-Cookie: session=stolen"));
+            response
+        }
+        let router = Router::new(Arc::new(ThreadPoolSpawner));
+        router.add_handler(Method::GET, "/", injected);
+
+        let output = exchange(router, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(output.starts_with("HTTP/1.1 500 "), "{output}");
+        assert!(!output.contains("Set-Cookie"), "{output}");
+        assert!(!output.contains("hello"), "{output}");
+    }
+
+    fn hello_router() -> Router {
+        let router = Router::new(Arc::new(ThreadPoolSpawner));
+        for method in [Method::GET, Method::POST] {
+            router.add_route(method, "/", Arc::new(|_spawner: Arc<dyn Spawner>, _request: Request<Vec<u8>>| -> BoxFuture<'static, SimpleResult<Response<Body>>> {
+                Box::pin(async move { Ok(Response::new(Body::from("hello"))) })
+            }));
+        }
+        router
+    }
+
+    /// Answers `GET /` after a moment, sending `103 Early Hints` first
+    fn slow_router() -> Router {
+        let router = Router::new(Arc::new(ThreadPoolSpawner));
+        router.add_route(Method::GET, "/", Arc::new(|_spawner: Arc<dyn Spawner>, request: Request<Vec<u8>>| -> BoxFuture<'static, SimpleResult<Response<Body>>> {
+            Box::pin(async move {
+                if let Some(informational) = request.extensions().get::<Informational>() {
+                    informational.early_hints(["</style.css>; rel=preload; as=style"]);
                 }
-                Err(err) => {
-                    log::warn!("Failed to establish connection: {:?}", err);
-                    // Continue accepting new connections
-                    continue;
+                Timer::after(Duration::from_millis(20)).await;
+                Ok(Text("page").into_response())
+            })
+        }));
+        router
+    }
+
+    #[test]
+    fn keeps_connections_alive_across_pipelined_requests() {
+        let output = exchange(hello_router(), "GET / HTTP/1.1\r\nHost: a\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(output.matches("HTTP/1.1 200 ").count(), 2, "{output}");
+        assert_eq!(output.matches("hello").count(), 2, "{output}");
+    }
+
+    #[test]
+    fn closes_connections_the_client_asks_to_close() {
+        let output = exchange(hello_router(), "GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(output.matches(" 200 ").count(), 1, "{output}");
+        assert!(output.to_ascii_lowercase().contains("connection: close\r\n"), "{output}");
+    }
+
+    #[test]
+    fn closes_http10_connections_without_keep_alive() {
+        let output = exchange(hello_router(), "GET / HTTP/1.0\r\n\r\nGET / HTTP/1.0\r\n\r\n");
+        assert_eq!(output.matches(" 200 ").count(), 1, "{output}");
+
+        let output = exchange(hello_router(), "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\n");
+        assert_eq!(output.matches(" 200 ").count(), 2, "{output}");
+    }
+
+    #[test]
+    fn refuses_http11_requests_without_a_host() {
+        let output = exchange(hello_router(), "GET / HTTP/1.1\r\n\r\n");
+        assert!(output.starts_with("HTTP/1.1 400 "), "{output}");
+
+        let output = exchange(hello_router(), "GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n");
+        assert!(output.starts_with("HTTP/1.1 400 "), "{output}");
+    }
+
+    #[test]
+    fn refuses_ambiguous_framing() {
+        let output = exchange(hello_router(), "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n");
+        assert!(output.starts_with("HTTP/1.1 400 "), "{output}");
+        assert_eq!(output.matches("HTTP/1.1 ").count(), 1, "{output}");
+    }
+
+    #[test]
+    fn sends_informational_responses_before_the_final_one() {
+        let output = exchange(slow_router(), "GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(output.starts_with("HTTP/1.1 103 Early Hints\r\n"), "{output}");
+        assert!(output.contains("link: </style.css>; rel=preload; as=style\r\n"), "{output}");
+        let final_response = output.find("HTTP/1.1 200 ").expect("no final response");
+        assert!(output[final_response..].ends_with("page"), "{output}");
+    }
+
+    #[test]
+    fn never_sends_informational_responses_to_http10_clients() {
+        let output = exchange(slow_router(), "GET / HTTP/1.0\r\n\r\n");
+        assert!(!output.contains(" 103 "), "{output}");
+        assert!(output.contains(" 200 "), "{output}");
+    }
+
+    #[test]
+    fn answers_clients_that_half_close_after_their_request() {
+        // the mock client's input ends right after the request, like a shutdown(SHUT_WR)
+        let output = exchange(slow_router(), "GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(output.ends_with("page"), "{output}");
+
+        let config = ConnectionConfig {
+            cancel_on_eof: true,
+            ..ConnectionConfig::default()
+        };
+        let output = exchange_with(slow_router(), config, "GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(output, "");
+    }
+
+    #[cfg(feature = "http2")]
+    const H2C_UPGRADE: &str = "Host: a\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAoAAAAAIAAAAA\r\n";
+
+    /// A connection whose client sends `input` and then waits, never closing its side
+    #[cfg(feature = "http2")]
+    struct OpenConnection(MockConnection);
+
+    #[cfg(feature = "http2")]
+    impl AsyncRead for OpenConnection {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            match Pin::new(&mut self.0).poll_read(cx, buf) {
+                Poll::Ready(Ok(0)) => Poll::Pending,
+                read => read,
+            }
+        }
+    }
+
+    #[cfg(feature = "http2")]
+    impl AsyncWrite for OpenConnection {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.0).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_flush(cx)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_close(cx)
+        }
+    }
+
+    #[cfg(feature = "http2")]
+    #[test]
+    fn upgrades_to_h2c_and_answers_the_upgrade_request_on_stream_1() {
+        let mut input = format!("GET / HTTP/1.1\r\n{H2C_UPGRADE}\r\n").into_bytes();
+        // the client's connection preface, with empty SETTINGS
+        input.extend_from_slice(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+        input.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 0, 0]);
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let connection = OpenConnection(MockConnection {
+            input: Cursor::new(input),
+            output: output.clone(),
+        });
+        let config = Arc::new(ConnectionConfig {
+            h2c: true,
+            ..ConnectionConfig::default()
+        });
+
+        // the body comes back as a DATA frame on stream 1
+        let answered = |output: &[u8]| output.windows(9).any(|window| window == b"\0\0\0\x01hello");
+        let serve = HttpServer::handle_connection(Arc::new(hello_router()), connection, config, ConnectionInfo::default(), None);
+        let wait = async {
+            for _ in 0..500 {
+                if answered(&output.lock().unwrap()) {
+                    break;
                 }
+                Timer::after(Duration::from_millis(10)).await;
             }
+        };
+        block_on(future::or(async { drop(serve.await) }, wait));
+
+        let output = output.lock().unwrap();
+        assert!(output.starts_with(b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n"), "{output:?}");
+        assert!(answered(&output), "{output:?}");
+    }
+
+    #[cfg(feature = "http2")]
+    #[test]
+    fn answers_h2c_upgrades_with_a_body_over_http1() {
+        let config = ConnectionConfig {
+            h2c: true,
+            ..ConnectionConfig::default()
+        };
+        let output = exchange_with(hello_router(), config, &format!("POST / HTTP/1.1\r\n{H2C_UPGRADE}Content-Length: 2\r\n\r\nhi"));
+        assert!(output.starts_with("HTTP/1.1 200 "), "{output}");
+        assert!(output.ends_with("\r\n\r\nhello"), "{output}");
+    }
+
+    /// A listener whose accepts fail with `errors`, in order
+    struct FailingListener(Mutex<Vec<io::Error>>);
+
+    impl Listener for FailingListener {
+        type Stream = MockConnection;
+
+        fn accept_stream(&self) -> BoxFuture<'_, io::Result<(Self::Stream, ConnectionInfo)>> {
+            let err = self.0.lock().unwrap().remove(0);
+            Box::pin(async move { Err(err) })
         }
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_accepting_after_transient_accept_errors() {
+        let errors = [libc::EMFILE, libc::ECONNABORTED, libc::ENFILE, libc::ENOBUFS, libc::EBADF];
+        let listener = FailingListener(Mutex::new(errors.into_iter().map(io::Error::from_raw_os_error).collect()));
+        let router = Arc::new(Router::new(Arc::new(ThreadPoolSpawner)));
+
+        // only the listener's own descriptor going bad ends the loop, after backing off on the
+        // exhausted resources
+        let started = std::time::Instant::now();
+        let result = block_on(HttpServer::new().accept_loop(listener, router));
+        assert!(matches!(result, Err(Error::Io(err)) if err.raw_os_error() == Some(libc::EBADF)));
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
 }
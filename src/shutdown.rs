@@ -0,0 +1,95 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_lock::{OnceCell, RwLock, RwLockReadGuardArc};
+use futures_lite::future;
+
+use crate::runtime;
+
+#[derive(Default)]
+struct ShutdownState {
+    draining: OnceCell<()>,
+    closing: OnceCell<()>,
+    /// Every open connection holds a read guard, so taking the write lock waits for all of them
+    connections: Arc<RwLock<()>>,
+}
+
+/// Drains a running server: accept loops stop, keep-alive connections close after their
+/// in-flight response and whatever is still open once the grace period ends is dropped.
+/// Clones control the same servers
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.state.draining.is_initialized()
+    }
+
+    /// Starts draining and resolves once every connection has finished, or once `grace_period`
+    /// has elapsed and the remaining connections have been told to close
+    pub async fn shutdown(&self, grace_period: Option<Duration>) {
+        let _ = self.state.draining.set(()).await;
+
+        let drained = async {
+            let _all_closed = self.state.connections.write().await;
+            true
+        };
+        let drained = match grace_period {
+            Some(grace_period) => future::or(drained, async {
+                runtime::sleep(grace_period).await;
+                false
+            }).await,
+            None => drained.await,
+        };
+
+        if !drained {
+            log::warn!("shutdown grace period elapsed, closing remaining connections");
+            let _ = self.state.closing.set(()).await;
+        }
+    }
+
+    /// Resolves once draining starts
+    pub(crate) async fn draining(&self) {
+        self.state.draining.wait().await;
+    }
+
+    /// Resolves once connections still open after the grace period must be dropped
+    pub(crate) async fn closing(&self) {
+        self.state.closing.wait().await;
+    }
+
+    /// Registers an open connection until the guard is dropped; `None` once draining has begun
+    pub(crate) fn track_connection(&self) -> Option<RwLockReadGuardArc<()>> {
+        if self.is_draining() {
+            return None;
+        }
+        self.state.connections.try_read_arc()
+    }
+}
+
+/// Resolves on the first SIGINT or SIGTERM, e.g. for
+/// [`HttpServerBuilder::shutdown_signal`](crate::HttpServerBuilder::shutdown_signal). Never
+/// resolves if the handlers can't be installed
+#[cfg(feature = "signals")]
+pub async fn termination_signal() {
+    use async_signal::{Signal, Signals};
+    use futures_lite::StreamExt as _;
+
+    match Signals::new([Signal::Int, Signal::Term]) {
+        Ok(mut signals) => {
+            if let Some(Ok(signal)) = signals.next().await {
+                log::info!("received {signal:?}, shutting down");
+            }
+        }
+        Err(err) => {
+            log::error!("failed to install signal handlers: {err}");
+            future::pending::<()>().await;
+        }
+    }
+}
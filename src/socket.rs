@@ -0,0 +1,57 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+
+/// TCP tuning for listeners and the connections they accept
+#[derive(Clone, Copy, Debug)]
+pub struct SocketOptions {
+    /// Disable Nagle's algorithm on accepted connections, so small responses aren't held back
+    /// waiting for an ACK; on by default
+    pub nodelay: bool,
+    /// Allow rebinding a port with connections still in `TIME_WAIT`; on by default
+    pub reuse_address: bool,
+    /// Let several listeners bind the same port, with the kernel spreading connections across
+    /// them. Only takes effect on Unix
+    pub reuse_port: bool,
+    /// Idle time before TCP keepalive probes are sent on accepted connections; `None` leaves
+    /// keepalive off
+    pub keepalive: Option<Duration>,
+    /// Length of the queue of connections waiting to be accepted
+    pub backlog: i32,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            reuse_address: true,
+            reuse_port: false,
+            keepalive: None,
+            backlog: 1024,
+        }
+    }
+}
+
+impl SocketOptions {
+    pub(crate) fn bind(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_reuse_address(self.reuse_address)?;
+        #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+        socket.set_reuse_port(self.reuse_port)?;
+        socket.bind(&addr.into())?;
+        socket.listen(self.backlog)?;
+        Ok(socket.into())
+    }
+
+    /// Applies the per-connection options to an accepted socket
+    pub(crate) fn configure(&self, socket: SockRef<'_>) -> io::Result<()> {
+        socket.set_nodelay(self.nodelay)?;
+        if let Some(keepalive) = self.keepalive {
+            socket.set_keepalive(true)?;
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(keepalive))?;
+        }
+        Ok(())
+    }
+}
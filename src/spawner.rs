@@ -0,0 +1,45 @@
+use async_executor::Executor;
+
+use crate::types::BoxFuture;
+
+/// Runs background work for the server: one task per connection, plus anything handlers spawn
+pub trait Spawner: Send + Sync {
+    /// Runs `future` to completion in the background
+    fn spawn(&self, future: BoxFuture<'static, ()>);
+
+    /// Runs blocking work in the background without tying up the threads that drive `spawn`ed
+    /// futures. Handlers that need the result await [`spawn_blocking`](crate::spawn_blocking)
+    /// instead
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        blocking::unblock(f).detach();
+    }
+}
+
+/// async-executor, and smol's executor which re-exports it
+impl Spawner for Executor<'static> {
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        Executor::spawn(self, future).detach();
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Spawner for tokio::runtime::Handle {
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        drop(tokio::runtime::Handle::spawn(self, future));
+    }
+
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        drop(tokio::runtime::Handle::spawn_blocking(self, f));
+    }
+}
+
+/// Fallback needing no executor at all: every future is driven by its own thread from the
+/// `blocking` pool, so this suits low connection counts
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadPoolSpawner;
+
+impl Spawner for ThreadPoolSpawner {
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        blocking::unblock(move || async_io::block_on(future)).detach();
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use http::header::{CONTENT_TYPE, VARY};
+use http::{HeaderValue, Request, Response, StatusCode};
+
+use crate::accept::negotiate;
+use crate::body::Body;
+use crate::date::DateTime;
+use crate::file::{FileCache, FileResponse};
+use crate::middleware::access_log::json_escape;
+use crate::path_params::RequestPathExt as _;
+use crate::response::{Html, IntoResponse, Redirect};
+
+/// The wildcard segment [`Router::add_static`](crate::Router::add_static) registers
+pub(crate) const WILDCARD: &str = "static_path";
+
+/// Serves the files under a directory, mounted with [`Router::add_static`](crate::Router::add_static).
+/// A request for a directory is answered with its index file or, with autoindex enabled, a
+/// listing of its entries; without either it is a 404
+#[derive(Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index_file: String,
+    autoindex: bool,
+    precompressed: bool,
+    cache: Option<FileCache>,
+}
+
+/// One row of a directory listing
+struct DirEntry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+fn html_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent-encodes a file name for use as one path segment of a link
+fn encode_segment(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+fn format_time(modified: Option<SystemTime>) -> String {
+    modified.map(|modified| DateTime::from_system_time(modified).to_rfc3339()).unwrap_or_default()
+}
+
+async fn read_dir(path: PathBuf) -> io::Result<Vec<DirEntry>> {
+    let mut entries = blocking::unblock(move || {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            // names that aren't UTF-8 couldn't be linked to anyway
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            entries.push(DirEntry {
+                name,
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+        Ok::<_, io::Error>(entries)
+    })
+    .await?;
+    // directories first, then by name
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    Ok(entries)
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            index_file: "index.html".to_string(),
+            autoindex: false,
+            precompressed: false,
+            cache: None,
+        }
+    }
+
+    /// The file served for a directory, `index.html` by default
+    pub fn index_file(mut self, index_file: impl Into<String>) -> Self {
+        self.index_file = index_file.into();
+        self
+    }
+
+    /// List the entries of directories without an index file, as HTML or, when the request's
+    /// `Accept` prefers it, JSON. Off by default, since it reveals every file under the root
+    pub fn autoindex(mut self, autoindex: bool) -> Self {
+        self.autoindex = autoindex;
+        self
+    }
+
+    /// Serve `.br` and `.gz` siblings when the client accepts them; see
+    /// [`FileResponse::open_precompressed`]
+    pub fn precompressed(mut self, precompressed: bool) -> Self {
+        self.precompressed = precompressed;
+        self
+    }
+
+    /// Serve small files through `cache` instead of reading them every time
+    pub fn cache(mut self, cache: FileCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// The file under the root a request path points to. `..` segments are refused rather than
+    /// resolved, so nothing outside the root can be reached
+    fn resolve(&self, relative: &str) -> Option<PathBuf> {
+        let mut path = self.root.clone();
+        for segment in relative.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return None,
+                segment if segment.contains(['\\', '\0']) => return None,
+                segment => path.push(segment),
+            }
+        }
+        Some(path)
+    }
+
+    pub(crate) async fn serve(&self, request: &Request<Vec<u8>>) -> Response<Body> {
+        let relative = request.path_params().get_str(WILDCARD).unwrap_or("");
+        let Some(path) = self.resolve(relative) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let metadata = blocking::unblock({
+            let path = path.clone();
+            move || std::fs::metadata(path)
+        })
+        .await;
+        let Ok(metadata) = metadata else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        if !metadata.is_dir() {
+            return self.serve_file(&path, request).await;
+        }
+
+        // links in the index resolve against the directory only when its URL ends in a slash
+        let request_path = request.uri().path();
+        if !request_path.ends_with('/') {
+            let mut location = format!("{request_path}/");
+            if let Some(query) = request.uri().query() {
+                location.push('?');
+                location.push_str(query);
+            }
+            return Redirect::permanent(&location).into_response();
+        }
+        let index = path.join(&self.index_file);
+        let has_index = blocking::unblock({
+            let index = index.clone();
+            move || index.is_file()
+        })
+        .await;
+        if has_index {
+            return self.serve_file(&index, request).await;
+        }
+        if !self.autoindex {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        match read_dir(path).await {
+            Ok(entries) => Self::listing(request, &entries),
+            Err(err) => {
+                log::error!("failed to list {request_path}: {err}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+
+    async fn serve_file(&self, path: &Path, request: &Request<Vec<u8>>) -> Response<Body> {
+        let opened = match (&self.cache, self.precompressed) {
+            (Some(cache), true) => cache.open_precompressed(path, request).await,
+            (Some(cache), false) => cache.open(path).await,
+            (None, true) => FileResponse::open_precompressed(path, request).await,
+            (None, false) => FileResponse::open(path).await,
+        };
+        match opened {
+            Ok(file) => match file.validators().not_modified(request) {
+                Some(not_modified) => not_modified,
+                None => file.into_response(),
+            },
+            Err(err) if matches!(err.kind(), io::ErrorKind::NotFound | io::ErrorKind::InvalidInput) => StatusCode::NOT_FOUND.into_response(),
+            Err(err) => {
+                log::error!("failed to open {}: {err}", path.display());
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+
+    fn listing(request: &Request<Vec<u8>>, entries: &[DirEntry]) -> Response<Body> {
+        let media_type = match negotiate(request, &["text/html", "application/json"]) {
+            Ok(media_type) => media_type,
+            Err(err) => return err.into_response(),
+        };
+        let mut response = if media_type == "application/json" {
+            let mut json = String::from("[");
+            for (i, entry) in entries.iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                let _ = write!(
+                    json,
+                    "{{\"name\":\"{}\",\"type\":\"{}\",\"size\":{},\"modified\":\"{}\"}}",
+                    json_escape(&entry.name),
+                    if entry.is_dir { "directory" } else { "file" },
+                    entry.size,
+                    format_time(entry.modified)
+                );
+            }
+            json.push(']');
+            let mut response = StatusCode::OK.into_response();
+            *response.body_mut() = Body::from(json);
+            response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            response
+        } else {
+            let title = html_escape(request.uri().path());
+            let mut html = format!("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n");
+            if request.uri().path() != "/" {
+                html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+            }
+            for entry in entries {
+                let slash = if entry.is_dir { "/" } else { "" };
+                let size = if entry.is_dir { "-".to_string() } else { entry.size.to_string() };
+                let _ = writeln!(
+                    html,
+                    "<tr><td><a href=\"{}{slash}\">{}{slash}</a></td><td>{size}</td><td>{}</td></tr>",
+                    encode_segment(&entry.name),
+                    html_escape(&entry.name),
+                    format_time(entry.modified)
+                );
+            }
+            html.push_str("</table>\n</body>\n</html>\n");
+            Html(html).into_response()
+        };
+        response.headers_mut().append(VARY, HeaderValue::from_static("Accept"));
+        response
+    }
+}
@@ -0,0 +1,60 @@
+use std::error::Error as StdError;
+
+use http::Response;
+
+use crate::body::Body;
+use crate::error::Error;
+use crate::response::{Html, IntoResponse};
+
+/// Anything that renders to an HTML document, e.g. an askama template struct with the `askama`
+/// feature or a [`MinijinjaTemplate`] with the `minijinja` feature
+pub trait Template {
+    fn render_html(&self) -> Result<String, Box<dyn StdError + Send + Sync>>;
+}
+
+/// Renders a [`Template`] into a `text/html` response, or a logged 500 if rendering fails,
+/// e.g. `Ok(Rendered(IndexPage { user }).into_response())`
+pub struct Rendered<T>(pub T);
+
+impl<T: Template> IntoResponse for Rendered<T> {
+    fn into_response(self) -> Response<Body> {
+        match self.0.render_html() {
+            Ok(html) => Html(html).into_response(),
+            Err(err) => Error::Handler(err).into_response(),
+        }
+    }
+}
+
+#[cfg(feature = "askama")]
+impl<T: askama::Template> Template for T {
+    fn render_html(&self) -> Result<String, Box<dyn StdError + Send + Sync>> {
+        Ok(self.render()?)
+    }
+}
+
+/// A named template from a shared minijinja environment along with its context
+#[cfg(feature = "minijinja")]
+pub struct MinijinjaTemplate {
+    env: std::sync::Arc<minijinja::Environment<'static>>,
+    name: String,
+    context: minijinja::Value,
+}
+
+#[cfg(feature = "minijinja")]
+impl MinijinjaTemplate {
+    /// `context` is anything serializable, e.g. `minijinja::context! { user }`
+    pub fn new(env: std::sync::Arc<minijinja::Environment<'static>>, name: impl Into<String>, context: impl serde::Serialize) -> Self {
+        Self {
+            env,
+            name: name.into(),
+            context: minijinja::Value::from_serialize(context),
+        }
+    }
+}
+
+#[cfg(feature = "minijinja")]
+impl Template for MinijinjaTemplate {
+    fn render_html(&self) -> Result<String, Box<dyn StdError + Send + Sync>> {
+        Ok(self.env.get_template(&self.name)?.render(&self.context)?)
+    }
+}
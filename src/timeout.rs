@@ -0,0 +1,69 @@
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use futures_lite::future;
+
+use crate::runtime;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    /// Time allowed for the TLS handshake, and the PROXY header before it, once a connection is accepted
+    pub handshake: Option<Duration>,
+    /// Time allowed to receive the request line and all headers
+    pub header_read: Option<Duration>,
+    /// Time allowed to receive the request body once the headers are in
+    pub body_read: Option<Duration>,
+    /// Upper bound on reading the whole request, regardless of the stages above
+    pub request: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            handshake: Some(Duration::from_secs(10)),
+            header_read: Some(Duration::from_secs(10)),
+            body_read: Some(Duration::from_secs(30)),
+            request: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn none() -> Self {
+        Self {
+            handshake: None,
+            header_read: None,
+            body_read: None,
+            request: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TimeoutError(pub &'static str);
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} timed out", self.0)
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+pub(crate) async fn with_timeout<T, E: From<TimeoutError>>(
+    duration: Option<Duration>,
+    stage: &'static str,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    match duration {
+        Some(duration) => {
+            future::or(future, async move {
+                runtime::sleep(duration).await;
+                Err(E::from(TimeoutError(stage)))
+            })
+            .await
+        }
+        None => future.await,
+    }
+}
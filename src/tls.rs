@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientCertVerifier, ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+
+use crate::error::Result;
+use crate::runtime;
+use crate::tls_stream::TlsAcceptor;
+
+/// Verifies the certificates clients present on mutual TLS connections
+pub(crate) type ClientVerifier = Arc<dyn ClientCertVerifier>;
+
+pub(crate) fn load_certs(cert_pem: &str) -> Result<Vec<Certificate>> {
+    let mut cert_reader = std::io::BufReader::new(std::io::Cursor::new(cert_pem));
+    Ok(rustls_pemfile::certs(&mut cert_reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect())
+}
+
+pub(crate) fn load_private_key(key_pem: &str) -> Result<PrivateKey> {
+    let mut key_reader = std::io::BufReader::new(std::io::Cursor::new(key_pem));
+    Ok(rustls_pemfile::pkcs8_private_keys(&mut key_reader)?
+        .into_iter()
+        .map(PrivateKey)
+        .next()
+        .ok_or_else(|| rustls::Error::General("No private key found".to_string()))?)
+}
+
+pub(crate) fn certified_key(cert_pem: &str, key_pem: &str) -> Result<CertifiedKey> {
+    let key = rustls::sign::any_supported_type(&load_private_key(key_pem)?)
+        .map_err(|_| rustls::Error::General("Unsupported private key type".to_string()))?;
+    Ok(CertifiedKey::new(load_certs(cert_pem)?, key))
+}
+
+/// Advertises the application protocols this build can speak
+pub(crate) fn set_alpn_protocols(config: &mut ServerConfig) {
+    config.alpn_protocols.clear();
+    // Offer h2 during the handshake when HTTP/2 support is compiled in
+    #[cfg(feature = "http2")]
+    config.alpn_protocols.push(crate::http2::ALPN_H2.to_vec());
+    config.alpn_protocols.push(b"http/1.1".to_vec());
+}
+
+/// Verifies client certificates against the PEM encoded CA bundle; with `required` unset,
+/// clients may still connect without presenting one
+pub(crate) fn client_verifier(ca_pem: &str, required: bool) -> Result<ClientVerifier> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca_pem)? {
+        roots.add(&cert).map_err(|err| rustls::Error::General(format!("Invalid client CA certificate: {err:?}")))?;
+    }
+
+    Ok(if required {
+        AllowAnyAuthenticatedClient::new(roots).boxed()
+    } else {
+        AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+    })
+}
+
+pub(crate) enum CertSource {
+    Single(Vec<Certificate>, PrivateKey),
+    Resolver(Arc<dyn ResolvesServerCert>),
+}
+
+pub(crate) fn server_config(
+    client_verifier: Option<ClientVerifier>,
+    certs: CertSource,
+) -> Result<ServerConfig> {
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match client_verifier {
+        Some(client_verifier) => builder.with_client_cert_verifier(client_verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = match certs {
+        CertSource::Single(cert, key) => builder.with_single_cert(cert, key)?,
+        CertSource::Resolver(resolver) => builder.with_cert_resolver(resolver),
+    };
+    set_alpn_protocols(&mut config);
+    Ok(config)
+}
+
+/// Certificate chain a client presented and the client CA verified, inserted into request
+/// extensions on mutual TLS connections
+#[derive(Clone, Debug)]
+pub struct PeerCertificates(pub Vec<Certificate>);
+
+/// Picks a certificate by the SNI hostname the client asked for, falling back to a default
+#[derive(Default)]
+pub(crate) struct SniResolver {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+impl SniResolver {
+    pub fn add(&mut self, hostname: &str, cert_pem: &str, key_pem: &str) -> Result<()> {
+        let key = certified_key(cert_pem, key_pem)?;
+        self.by_name.insert(hostname.to_ascii_lowercase(), Arc::new(key));
+        Ok(())
+    }
+
+    pub fn set_default(&mut self, cert_pem: &str, key_pem: &str) -> Result<()> {
+        self.default = Some(Arc::new(certified_key(cert_pem, key_pem)?));
+        Ok(())
+    }
+
+    fn lookup(&self, server_name: &str) -> Option<Arc<CertifiedKey>> {
+        let server_name = server_name.to_ascii_lowercase();
+        if let Some(key) = self.by_name.get(&server_name) {
+            return Some(key.clone());
+        }
+
+        // "*.example.com" covers exactly one extra label
+        let (_, parent) = server_name.split_once('.')?;
+        self.by_name.get(&format!("*.{parent}")).cloned()
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|server_name| self.lookup(server_name))
+            .or_else(|| self.default.clone())
+    }
+}
+
+/// The TLS implementation handshakes go through
+#[derive(Clone)]
+pub(crate) enum Acceptor {
+    Rustls(TlsAcceptor),
+    /// The platform's TLS stack (SChannel, Secure Transport or OpenSSL), which has no
+    /// server-side ALPN, so connections through it always speak HTTP/1.1
+    #[cfg(feature = "native-tls")]
+    Native(Arc<async_native_tls::TlsAcceptor>),
+}
+
+/// A native-tls acceptor for a PEM encoded certificate chain and PKCS#8 key
+#[cfg(feature = "native-tls")]
+pub(crate) fn native_acceptor(cert_pem: &str, key_pem: &str) -> Result<Acceptor> {
+    let identity = native_tls::Identity::from_pkcs8(cert_pem.as_bytes(), key_pem.as_bytes()).map_err(std::io::Error::other)?;
+    let acceptor = native_tls::TlsAcceptor::new(identity).map_err(std::io::Error::other)?;
+    Ok(Acceptor::Native(Arc::new(async_native_tls::TlsAcceptor::from(acceptor))))
+}
+
+/// Shared, swappable TLS configuration; new handshakes pick up a reloaded certificate
+/// while connections already established keep the one they negotiated
+#[derive(Clone, Default)]
+pub struct TlsHandle {
+    acceptor: Arc<RwLock<Option<Acceptor>>>,
+    client_verifier: Option<ClientVerifier>,
+}
+
+impl TlsHandle {
+    pub(crate) fn new(config: ServerConfig, client_verifier: Option<ClientVerifier>) -> Self {
+        let handle = Self {
+            acceptor: Arc::default(),
+            client_verifier,
+        };
+        handle.reload_config(config);
+        handle
+    }
+
+    /// Serves TLS through the platform's TLS stack instead of rustls
+    #[cfg(feature = "native-tls")]
+    pub(crate) fn native(cert_pem: &str, key_pem: &str) -> Result<Self> {
+        let handle = Self::default();
+        *handle.acceptor.write().unwrap() = Some(native_acceptor(cert_pem, key_pem)?);
+        Ok(handle)
+    }
+
+    pub(crate) fn acceptor(&self) -> Option<Acceptor> {
+        self.acceptor.read().unwrap().clone()
+    }
+
+    /// Replaces the certificate with a PEM encoded chain and PKCS#8 key, keeping any client
+    /// certificate verification the server was configured with, and the TLS implementation
+    pub fn reload(&self, cert_pem: &str, key_pem: &str) -> Result<()> {
+        #[cfg(feature = "native-tls")]
+        if matches!(self.acceptor(), Some(Acceptor::Native(_))) {
+            *self.acceptor.write().unwrap() = Some(native_acceptor(cert_pem, key_pem)?);
+            return Ok(());
+        }
+        let certs = CertSource::Single(load_certs(cert_pem)?, load_private_key(key_pem)?);
+        self.reload_config(server_config(self.client_verifier.clone(), certs)?);
+        Ok(())
+    }
+
+    /// Replaces the whole rustls config, e.g. to swap in a new SNI certificate set
+    pub fn reload_config(&self, config: ServerConfig) {
+        *self.acceptor.write().unwrap() = Some(Acceptor::Rustls(TlsAcceptor::from(Arc::new(config))));
+    }
+
+    pub fn reload_files(&self, cert_path: &Path, key_path: &Path) -> Result<()> {
+        let cert_pem = std::fs::read_to_string(cert_path)?;
+        let key_pem = std::fs::read_to_string(key_path)?;
+        self.reload(&cert_pem, &key_pem)
+    }
+
+    /// Polls the PEM files every `interval` and reloads when either one changes, e.g. after a
+    /// certificate renewal. The returned future never completes; spawn it, and drop it to
+    /// stop watching
+    pub fn watch_files(
+        &self,
+        cert_path: impl Into<PathBuf>,
+        key_path: impl Into<PathBuf>,
+        interval: Duration,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let handle = self.clone();
+        let cert_path = cert_path.into();
+        let key_path = key_path.into();
+        async move {
+            let mut last_modified = modified_times(&cert_path, &key_path);
+            loop {
+                runtime::sleep(interval).await;
+
+                let modified = modified_times(&cert_path, &key_path);
+                if modified == last_modified {
+                    continue;
+                }
+                match handle.reload_files(&cert_path, &key_path) {
+                    Ok(()) => {
+                        log::info!("reloaded TLS certificate from {}", cert_path.display());
+                        last_modified = modified;
+                    }
+                    // a renewal may still be writing the files; try again next tick
+                    Err(err) => log::warn!("failed to reload TLS certificate err = {err:?}"),
+                }
+            }
+        }
+    }
+}
+
+fn modified_times(cert_path: &Path, key_path: &Path) -> Option<(SystemTime, SystemTime)> {
+    let cert_modified = std::fs::metadata(cert_path).and_then(|metadata| metadata.modified()).ok()?;
+    let key_modified = std::fs::metadata(key_path).and_then(|metadata| metadata.modified()).ok()?;
+    Some((cert_modified, key_modified))
+}
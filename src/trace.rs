@@ -0,0 +1,60 @@
+use std::future::Future;
+
+use http::{Method, Response};
+
+use crate::connection_info::ConnectionInfo;
+
+/// Runs `future` inside a `connection` span when the `tracing` feature is enabled
+pub(crate) fn in_connection_span<F: Future>(connection_info: &ConnectionInfo, future: F) -> impl Future<Output = F::Output> {
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::Instrument as _;
+
+        let span = tracing::info_span!(
+            "connection",
+            remote_addr = ?connection_info.remote_addr,
+            local_addr = ?connection_info.local_addr,
+            tls = connection_info.tls,
+        );
+        future.instrument(span)
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = connection_info;
+        future
+    }
+}
+
+/// Runs `future` (the middleware chain and handler) inside a `request` span when the
+/// `tracing` feature is enabled, recording the status and latency once it completes
+pub(crate) async fn in_request_span<B, E, F>(method: &Method, path: &str, future: F) -> Result<Response<B>, E>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::Instrument as _;
+
+        let span = tracing::info_span!(
+            "request",
+            method = %method,
+            path = %path,
+            status = tracing::field::Empty,
+            latency_ms = tracing::field::Empty,
+        );
+        let started = std::time::Instant::now();
+        let result = future.instrument(span.clone()).await;
+        if let Ok(response) = &result {
+            span.record("status", response.status().as_u16());
+        }
+        span.record("latency_ms", started.elapsed().as_secs_f64() * 1000.0);
+        result
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (method, path);
+        future.await
+    }
+}
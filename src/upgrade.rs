@@ -0,0 +1,90 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use http::header::{CONNECTION, UPGRADE};
+use http::{HeaderValue, Request, Response, StatusCode};
+
+use crate::async_connection::AsyncConnection;
+use crate::body::Body;
+use crate::response::IntoResponse;
+use crate::types::BoxFuture;
+
+type UpgradeCallback = Box<dyn FnOnce(Box<dyn AsyncConnection>) -> BoxFuture<'static, ()> + Send>;
+
+/// Carried in the extensions of a 101 response, or a 2xx answer to `CONNECT`; the server
+/// writes only the response head and then hands the connection to it
+#[derive(Clone)]
+pub(crate) struct OnUpgrade(Arc<Mutex<Option<UpgradeCallback>>>);
+
+impl OnUpgrade {
+    pub(crate) fn new<F, Fut>(on_upgrade: F) -> Self
+    where
+        F: FnOnce(Box<dyn AsyncConnection>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let on_upgrade: UpgradeCallback = Box::new(move |connection| Box::pin(on_upgrade(connection)));
+        Self(Arc::new(Mutex::new(Some(on_upgrade))))
+    }
+
+    pub(crate) fn take(&self) -> Option<UpgradeCallback> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// A `101 Switching Protocols` response that hands the raw connection to a callback once it
+/// has been written, for protocols layered on HTTP/1.1 such as tunnels or remote shells, e.g.
+/// `Upgrade::new("my-protocol", |connection| async move { /* speak my-protocol */ })`.
+/// Bytes the client sent after the upgrade request are readable from the connection, and the
+/// connection closes when the callback's future completes. Only HTTP/1.1 connections can be
+/// upgraded; over HTTP/2 the callback never runs
+pub struct Upgrade {
+    protocol: HeaderValue,
+    on_upgrade: OnUpgrade,
+}
+
+impl Upgrade {
+    pub fn new<F, Fut>(protocol: &'static str, on_upgrade: F) -> Self
+    where
+        F: FnOnce(Box<dyn AsyncConnection>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Self {
+            protocol: HeaderValue::from_static(protocol),
+            on_upgrade: OnUpgrade::new(on_upgrade),
+        }
+    }
+
+    /// The protocols named in the request's `Upgrade` header, if its `Connection` header asks
+    /// for an upgrade at all
+    pub fn requested<B>(request: &Request<B>) -> Vec<&str> {
+        let asks_to_upgrade = request
+            .headers()
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|option| option.trim().eq_ignore_ascii_case("upgrade"));
+        if !asks_to_upgrade {
+            return Vec::new();
+        }
+        request
+            .headers()
+            .get_all(UPGRADE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|protocol| !protocol.is_empty())
+            .collect()
+    }
+}
+
+impl IntoResponse for Upgrade {
+    fn into_response(self) -> Response<Body> {
+        let mut response = StatusCode::SWITCHING_PROTOCOLS.into_response();
+        response.headers_mut().insert(CONNECTION, HeaderValue::from_static("upgrade"));
+        response.headers_mut().insert(UPGRADE, self.protocol);
+        response.extensions_mut().insert(self.on_upgrade);
+        response
+    }
+}
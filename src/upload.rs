@@ -0,0 +1,219 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use http::header::CONTENT_TYPE;
+use http::Request;
+use ring::rand::{SecureRandom as _, SystemRandom};
+
+use crate::error::{Error, Result};
+use crate::middleware::session::hex;
+
+/// Where [`Uploads::spool`] keeps uploaded files
+#[derive(Clone, Debug)]
+pub struct UploadOptions {
+    /// Files larger than this are written to disk rather than kept in memory
+    pub memory_threshold: usize,
+    /// Directory spooled files are created in
+    pub directory: PathBuf,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        Self {
+            memory_threshold: 64 * 1024,
+            directory: std::env::temp_dir(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Storage {
+    Memory(Vec<u8>),
+    /// Removed again when the [`UploadedFile`] is dropped
+    Disk(PathBuf),
+}
+
+/// One file from a `multipart/form-data` body
+#[derive(Debug)]
+pub struct UploadedFile {
+    pub field: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub size: u64,
+    storage: Storage,
+}
+
+impl UploadedFile {
+    /// The spooled file, unless it was small enough to stay in memory
+    pub fn path(&self) -> Option<&Path> {
+        match &self.storage {
+            Storage::Disk(path) => Some(path),
+            Storage::Memory(_) => None,
+        }
+    }
+
+    /// The contents, read back from disk if the file was spooled
+    pub async fn bytes(&self) -> io::Result<Vec<u8>> {
+        match &self.storage {
+            Storage::Memory(bytes) => Ok(bytes.clone()),
+            Storage::Disk(path) => {
+                let path = path.clone();
+                blocking::unblock(move || std::fs::read(path)).await
+            }
+        }
+    }
+
+    /// Moves the file to `destination`, e.g. permanent storage, so it outlives the request
+    pub async fn persist(mut self, destination: impl Into<PathBuf>) -> io::Result<()> {
+        let destination = destination.into();
+        match std::mem::replace(&mut self.storage, Storage::Memory(Vec::new())) {
+            Storage::Memory(bytes) => blocking::unblock(move || std::fs::write(destination, bytes)).await,
+            Storage::Disk(path) => {
+                blocking::unblock(move || {
+                    // rename can't cross filesystems, so fall back to copying
+                    std::fs::rename(&path, &destination).or_else(|_| {
+                        std::fs::copy(&path, &destination)?;
+                        std::fs::remove_file(&path)
+                    })
+                })
+                .await
+            }
+        }
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if let Storage::Disk(path) = &self.storage {
+            if let Err(err) = std::fs::remove_file(path) {
+                log::warn!("failed to remove spooled upload {}: {err}", path.display());
+            }
+        }
+    }
+}
+
+/// The files and plain fields of a `multipart/form-data` request. Request bodies are buffered
+/// before handlers run, so spooling bounds how long large files stay in memory rather than the
+/// peak; [`Limits::max_body_size`](crate::Limits) still caps the upload as a whole
+#[derive(Debug, Default)]
+pub struct Uploads {
+    pub files: Vec<UploadedFile>,
+    pub fields: Vec<(String, String)>,
+}
+
+struct Part<'b> {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    data: &'b [u8],
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
+
+/// A parameter such as `boundary` or `name` from a `;` separated header value
+fn header_param<'v>(value: &'v str, param: &str) -> Option<&'v str> {
+    value.split(';').skip(1).find_map(|pair| {
+        let (name, value) = pair.trim().split_once('=')?;
+        name.trim().eq_ignore_ascii_case(param).then(|| value.trim().trim_matches('"'))
+    })
+}
+
+fn parse_parts<'b>(body: &'b [u8], boundary: &str) -> Result<Vec<Part<'b>>> {
+    let delimiter = format!("--{boundary}");
+    let delimiter = delimiter.as_bytes();
+    let malformed = || Error::parse("malformed multipart body");
+
+    let mut parts = Vec::new();
+    let mut position = find(body, delimiter, 0).ok_or_else(malformed)? + delimiter.len();
+    loop {
+        // the final delimiter carries a trailing `--`
+        if body[position..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        if !body[position..].starts_with(b"\r\n") {
+            return Err(malformed());
+        }
+        let headers_start = position + 2;
+        let headers_end = find(body, b"\r\n\r\n", headers_start).ok_or_else(malformed)?;
+        let data_start = headers_end + 4;
+        let data_end = find(body, &[b"\r\n", delimiter].concat(), data_start).ok_or_else(malformed)?;
+
+        let mut part = Part {
+            name: String::new(),
+            filename: None,
+            content_type: None,
+            data: &body[data_start..data_end],
+        };
+        let headers = std::str::from_utf8(&body[headers_start..headers_end]).map_err(|_| malformed())?;
+        for line in headers.split("\r\n") {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            if name.trim().eq_ignore_ascii_case("content-disposition") {
+                part.name = header_param(value, "name").unwrap_or_default().to_string();
+                part.filename = header_param(value, "filename").map(str::to_string);
+            } else if name.trim().eq_ignore_ascii_case("content-type") {
+                part.content_type = Some(value.to_string());
+            }
+        }
+        parts.push(part);
+
+        position = data_end + 2 + delimiter.len();
+    }
+}
+
+impl Uploads {
+    /// Parses a `multipart/form-data` request, writing files above the memory threshold to
+    /// temporary files that are removed when their [`UploadedFile`] is dropped
+    pub async fn spool<B: AsRef<[u8]>>(request: &Request<B>, options: &UploadOptions) -> Result<Self> {
+        let content_type = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        if !content_type.trim_start().to_ascii_lowercase().starts_with("multipart/form-data") {
+            return Err(Error::UnsupportedMediaType);
+        }
+        let boundary = header_param(content_type, "boundary").ok_or_else(|| Error::parse("multipart body without a boundary"))?;
+
+        let rng = SystemRandom::new();
+        let mut uploads = Uploads::default();
+        for part in parse_parts(request.body().as_ref(), boundary)? {
+            let Some(filename) = part.filename else {
+                uploads.fields.push((part.name, String::from_utf8_lossy(part.data).into_owned()));
+                continue;
+            };
+
+            let data = part.data.to_vec();
+            let storage = if data.len() > options.memory_threshold {
+                let mut id = [0u8; 16];
+                rng.fill(&mut id).map_err(|_| Error::Io(io::Error::other("Failed to generate upload file name")))?;
+                let path = options.directory.join(format!("http_server-upload-{}", hex(&id)));
+                blocking::unblock({
+                    let path = path.clone();
+                    move || std::fs::write(path, data)
+                })
+                .await?;
+                Storage::Disk(path)
+            } else {
+                Storage::Memory(data)
+            };
+
+            uploads.files.push(UploadedFile {
+                field: part.name,
+                filename: Some(filename),
+                content_type: part.content_type,
+                size: part.data.len() as u64,
+                storage,
+            });
+        }
+        Ok(uploads)
+    }
+}
@@ -0,0 +1,23 @@
+use std::io::{self, IoSlice};
+
+use futures_lite::io::{AsyncWrite, AsyncWriteExt as _};
+
+/// Writes every buffer in order, handing them to the socket together so e.g. a response head
+/// and a small body leave in a single syscall
+pub(crate) async fn write_all_vectored<W: AsyncWrite + Unpin, const N: usize>(writer: &mut W, mut bufs: [&[u8]; N]) -> io::Result<()> {
+    while bufs.iter().any(|buf| !buf.is_empty()) {
+        let slices: [IoSlice<'_>; N] = std::array::from_fn(|i| IoSlice::new(bufs[i]));
+        let mut written = writer.write_vectored(&slices).await?;
+        if written == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+
+        // drop whatever was written from the front of the buffers
+        for buf in bufs.iter_mut() {
+            let consumed = written.min(buf.len());
+            *buf = &buf[consumed..];
+            written -= consumed;
+        }
+    }
+    Ok(())
+}
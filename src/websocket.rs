@@ -0,0 +1,542 @@
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+use futures_lite::future;
+use futures_lite::io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader};
+use http::header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE};
+use http::{HeaderValue, Method, Request, Response};
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+
+use crate::async_connection::AsyncConnection;
+use crate::base64;
+use crate::body::Body;
+use crate::error::{Error, Result};
+use crate::extract::FromRequest;
+use crate::response::IntoResponse as _;
+use crate::runtime;
+use crate::timeout::with_timeout;
+use crate::upgrade::Upgrade;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_TOO_BIG: u16 = 1009;
+
+/// A complete data message; fragments are reassembled and control frames handled internally
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Clone, Debug)]
+struct WebSocketConfig {
+    ping_interval: Option<Duration>,
+    max_message_size: usize,
+    close_timeout: Duration,
+    #[cfg(feature = "deflate")]
+    deflate: bool,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Some(Duration::from_secs(30)),
+            max_message_size: 16 * 1024 * 1024,
+            close_timeout: Duration::from_secs(5),
+            #[cfg(feature = "deflate")]
+            deflate: true,
+        }
+    }
+}
+
+/// The handshake half of a WebSocket, taken as a handler argument; finish it with
+/// [`WebSocketUpgrade::on_upgrade`], e.g.
+/// `async fn chat(ws: WebSocketUpgrade) -> impl IntoResponse { ws.on_upgrade(|mut socket| async move { while let Some(Ok(message)) = socket.recv().await { let _ = socket.send(message).await; } }) }`
+pub struct WebSocketUpgrade {
+    key: String,
+    /// The `permessage-deflate` parameters the client offered, if any
+    #[cfg_attr(not(feature = "deflate"), allow(dead_code))]
+    deflate_offer: Option<Vec<String>>,
+    config: WebSocketConfig,
+}
+
+/// Splits a header list like `Sec-WebSocket-Extensions` into its comma separated items
+fn header_items<'r, B>(request: &'r Request<B>, name: &http::header::HeaderName) -> impl Iterator<Item = &'r str> {
+    request
+        .headers()
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+}
+
+impl FromRequest for WebSocketUpgrade {
+    fn from_request(request: &Request<Vec<u8>>) -> Result<Self> {
+        if request.method() != Method::GET {
+            return Err(Error::parse("WebSocket handshakes must be GET requests"));
+        }
+        if !header_items(request, &UPGRADE).any(|protocol| protocol.eq_ignore_ascii_case("websocket")) {
+            return Err(Error::parse("Missing Upgrade: websocket"));
+        }
+        if !header_items(request, &CONNECTION).any(|option| option.eq_ignore_ascii_case("upgrade")) {
+            return Err(Error::parse("Missing Connection: upgrade"));
+        }
+        if request.headers().get(SEC_WEBSOCKET_VERSION).map(HeaderValue::as_bytes) != Some(&b"13"[..]) {
+            return Err(Error::parse("Unsupported Sec-WebSocket-Version"));
+        }
+        let key = request
+            .headers()
+            .get(SEC_WEBSOCKET_KEY)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| Error::parse("Missing Sec-WebSocket-Key"))?;
+
+        // each offer is `name; param; param=value`
+        let deflate_offer = header_items(request, &SEC_WEBSOCKET_EXTENSIONS).find_map(|offer| {
+            let mut parts = offer.split(';').map(str::trim);
+            (parts.next() == Some("permessage-deflate")).then(|| parts.map(|param| param.to_ascii_lowercase()).collect())
+        });
+
+        Ok(Self {
+            key: key.trim().to_string(),
+            deflate_offer,
+            config: WebSocketConfig::default(),
+        })
+    }
+}
+
+impl WebSocketUpgrade {
+    /// How long the connection may sit idle before the server pings it; a ping still
+    /// unanswered at the next interval closes the connection. 30 seconds by default
+    pub fn ping_interval(mut self, ping_interval: Option<Duration>) -> Self {
+        self.config.ping_interval = ping_interval;
+        self
+    }
+
+    /// Larger messages, before or after decompression, close the connection with 1009; 16MiB
+    /// by default
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.config.max_message_size = max_message_size;
+        self
+    }
+
+    /// Whether to accept a client's `permessage-deflate` offer; on by default
+    #[cfg(feature = "deflate")]
+    pub fn deflate(mut self, deflate: bool) -> Self {
+        self.config.deflate = deflate;
+        self
+    }
+
+    /// The `101` response completing the handshake; `callback` gets the socket once it is written
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> Response<Body>
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let accept = base64::encode(digest(&SHA1_FOR_LEGACY_USE_ONLY, format!("{}{GUID}", self.key).as_bytes()).as_ref());
+
+        #[cfg(feature = "deflate")]
+        let deflate = match &self.deflate_offer {
+            Some(params) if self.config.deflate => Deflate::negotiate(params),
+            _ => None,
+        };
+        #[cfg(feature = "deflate")]
+        let extensions = deflate.as_ref().map(Deflate::response_header);
+
+        let config = self.config;
+        let mut response = Upgrade::new("websocket", move |connection| {
+            let socket = WebSocket {
+                stream: BufReader::new(connection),
+                config,
+                close_sent: false,
+                closed: false,
+                awaiting_pong: false,
+                #[cfg(feature = "deflate")]
+                deflate,
+            };
+            callback(socket)
+        })
+        .into_response();
+
+        let headers = response.headers_mut();
+        headers.insert(SEC_WEBSOCKET_ACCEPT, HeaderValue::from_str(&accept).expect("base64 is a valid header value"));
+        #[cfg(feature = "deflate")]
+        if let Some(extensions) = extensions {
+            headers.insert(SEC_WEBSOCKET_EXTENSIONS, HeaderValue::from_static(extensions));
+        }
+        response
+    }
+}
+
+struct Frame {
+    fin: bool,
+    compressed: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// An open WebSocket connection. Pings are answered, idle connections pinged, fragmented
+/// messages reassembled and the close handshake completed without the application's help
+pub struct WebSocket {
+    stream: BufReader<Box<dyn AsyncConnection>>,
+    config: WebSocketConfig,
+    close_sent: bool,
+    /// Set once the connection is finished with, either way
+    closed: bool,
+    awaiting_pong: bool,
+    #[cfg(feature = "deflate")]
+    deflate: Option<Deflate>,
+}
+
+impl WebSocket {
+    /// The next message, or `None` once the connection is closed. Protocol violations close
+    /// the connection with the matching code and are returned as an error
+    pub async fn recv(&mut self) -> Option<Result<Message>> {
+        if self.closed {
+            return None;
+        }
+        match self.recv_message().await {
+            Ok(message) => message.map(Ok),
+            Err(err) => {
+                self.closed = true;
+                Some(Err(err))
+            }
+        }
+    }
+
+    pub async fn send(&mut self, message: Message) -> Result<()> {
+        match message {
+            Message::Text(text) => self.send_data(TEXT, text.into_bytes()).await,
+            Message::Binary(bytes) => self.send_data(BINARY, bytes).await,
+        }
+    }
+
+    /// Starts the close handshake and waits briefly for the peer to answer it
+    pub async fn close(mut self, code: u16, reason: &str) -> Result<()> {
+        if !self.close_sent {
+            self.send_close(code, reason).await?;
+        }
+        // anything still arriving is discarded until the peer's close frame
+        let close_timeout = self.config.close_timeout;
+        let _ = with_timeout(Some(close_timeout), "websocket close", async {
+            while !self.closed {
+                match self.read_frame().await? {
+                    Some(frame) if frame.opcode == CLOSE => self.closed = true,
+                    Some(_) => {}
+                    None => self.closed = true,
+                }
+            }
+            Ok::<_, Error>(())
+        })
+        .await;
+        Ok(())
+    }
+
+    async fn send_data(&mut self, opcode: u8, payload: Vec<u8>) -> Result<()> {
+        if self.close_sent {
+            return Err(io::Error::from(io::ErrorKind::NotConnected).into());
+        }
+        #[cfg(feature = "deflate")]
+        if let Some(deflate) = &mut self.deflate {
+            let payload = deflate.compress(&payload)?;
+            return self.write_frame(opcode, true, &payload).await;
+        }
+        self.write_frame(opcode, false, &payload).await
+    }
+
+    async fn send_close(&mut self, code: u16, reason: &str) -> Result<()> {
+        self.close_sent = true;
+        let mut payload = code.to_be_bytes().to_vec();
+        // control frames carry at most 125 bytes
+        let mut reason_len = reason.len().min(123);
+        while !reason.is_char_boundary(reason_len) {
+            reason_len -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..reason_len]);
+        self.write_frame(CLOSE, false, &payload).await
+    }
+
+    /// Closes with `code` because of a protocol violation, returning the error for the caller
+    async fn fail(&mut self, code: u16, message: &'static str) -> Error {
+        if !self.close_sent {
+            let _ = self.send_close(code, message).await;
+        }
+        Error::parse(message)
+    }
+
+    async fn recv_message(&mut self) -> Result<Option<Message>> {
+        // opcode, compressed and payload of a fragmented message still being received
+        let mut partial: Option<(u8, bool, Vec<u8>)> = None;
+        loop {
+            let Some(frame) = self.next_frame().await? else {
+                self.closed = true;
+                return Ok(None);
+            };
+
+            match frame.opcode {
+                PING => self.write_frame(PONG, false, &frame.payload).await?,
+                PONG => self.awaiting_pong = false,
+                CLOSE => {
+                    if !self.close_sent {
+                        let code = match frame.payload.get(..2) {
+                            Some(code) => u16::from_be_bytes([code[0], code[1]]),
+                            None => CLOSE_NORMAL,
+                        };
+                        self.send_close(code, "").await?;
+                    }
+                    self.closed = true;
+                    return Ok(None);
+                }
+                TEXT | BINARY if partial.is_none() => {
+                    if frame.fin {
+                        return self.finish_message(frame.opcode, frame.compressed, frame.payload).await.map(Some);
+                    }
+                    partial = Some((frame.opcode, frame.compressed, frame.payload));
+                }
+                CONTINUATION if partial.is_some() => {
+                    let (opcode, compressed, mut payload) = partial.take().expect("checked above");
+                    if payload.len() + frame.payload.len() > self.config.max_message_size {
+                        return Err(self.fail(CLOSE_TOO_BIG, "Message too big").await);
+                    }
+                    payload.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        return self.finish_message(opcode, compressed, payload).await.map(Some);
+                    }
+                    partial = Some((opcode, compressed, payload));
+                }
+                _ => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Unexpected frame").await),
+            }
+        }
+    }
+
+    async fn finish_message(&mut self, opcode: u8, compressed: bool, payload: Vec<u8>) -> Result<Message> {
+        #[cfg(feature = "deflate")]
+        let payload = match (&mut self.deflate, compressed) {
+            (Some(deflate), true) => match deflate.decompress(&payload, self.config.max_message_size) {
+                Ok(payload) => payload,
+                Err(_) => return Err(self.fail(CLOSE_INVALID_DATA, "Invalid compressed message").await),
+            },
+            _ => payload,
+        };
+        #[cfg(not(feature = "deflate"))]
+        let _ = compressed;
+
+        if opcode == BINARY {
+            return Ok(Message::Binary(payload));
+        }
+        match String::from_utf8(payload) {
+            Ok(text) => Ok(Message::Text(text)),
+            Err(_) => Err(self.fail(CLOSE_INVALID_DATA, "Text message is not UTF-8").await),
+        }
+    }
+
+    /// The next frame, pinging the peer whenever the connection sits idle for the ping interval
+    async fn next_frame(&mut self) -> Result<Option<Frame>> {
+        if let Some(ping_interval) = self.config.ping_interval {
+            loop {
+                // only waiting for data is raced against the timer, so no frame is ever half read
+                let readable = async { Some(self.stream.fill_buf().await.map(|buffered| !buffered.is_empty())) };
+                let idle = async {
+                    runtime::sleep(ping_interval).await;
+                    None
+                };
+                match future::or(readable, idle).await {
+                    Some(Ok(true)) => break,
+                    Some(Ok(false)) => return Ok(None),
+                    Some(Err(err)) => return Err(err.into()),
+                    None if self.awaiting_pong => {
+                        log::debug!("websocket peer stopped answering pings, closing");
+                        return Ok(None);
+                    }
+                    None => {
+                        self.awaiting_pong = true;
+                        self.write_frame(PING, false, &[]).await?;
+                    }
+                }
+            }
+        }
+        self.read_frame().await
+    }
+
+    async fn read_frame(&mut self) -> Result<Option<Frame>> {
+        let mut head = [0u8; 2];
+        match self.stream.read_exact(&mut head).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let fin = head[0] & 0x80 != 0;
+        let rsv1 = head[0] & 0x40 != 0;
+        let opcode = head[0] & 0x0F;
+        let masked = head[1] & 0x80 != 0;
+
+        if head[0] & 0x30 != 0 || !masked {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Invalid frame header").await);
+        }
+        #[cfg(feature = "deflate")]
+        let compression_allowed = self.deflate.is_some() && matches!(opcode, TEXT | BINARY);
+        #[cfg(not(feature = "deflate"))]
+        let compression_allowed = false;
+        if rsv1 && !compression_allowed {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Unexpected RSV1 bit").await);
+        }
+
+        let length = match head[1] & 0x7F {
+            126 => {
+                let mut length = [0u8; 2];
+                self.stream.read_exact(&mut length).await?;
+                u16::from_be_bytes(length) as u64
+            }
+            127 => {
+                let mut length = [0u8; 8];
+                self.stream.read_exact(&mut length).await?;
+                u64::from_be_bytes(length)
+            }
+            length => length as u64,
+        };
+        let is_control = opcode & 0x8 != 0;
+        if is_control && (length > 125 || !fin) {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Invalid control frame").await);
+        }
+        if length > self.config.max_message_size as u64 {
+            return Err(self.fail(CLOSE_TOO_BIG, "Message too big").await);
+        }
+
+        let mut mask = [0u8; 4];
+        self.stream.read_exact(&mut mask).await?;
+        let mut payload = vec![0u8; length as usize];
+        self.stream.read_exact(&mut payload).await?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        Ok(Some(Frame {
+            fin,
+            compressed: rsv1,
+            opcode,
+            payload,
+        }))
+    }
+
+    /// Writes a single unmasked, final frame, as servers send them
+    async fn write_frame(&mut self, opcode: u8, compressed: bool, payload: &[u8]) -> Result<()> {
+        let mut head = Vec::with_capacity(10);
+        head.push(0x80 | if compressed { 0x40 } else { 0 } | opcode);
+        match payload.len() {
+            length @ 0..=125 => head.push(length as u8),
+            length @ 126..=0xFFFF => {
+                head.push(126);
+                head.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                head.push(127);
+                head.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        let stream = self.stream.get_mut();
+        stream.write_all(&head).await?;
+        stream.write_all(payload).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// `permessage-deflate` state (RFC 7692). Both directions keep their compression context
+/// across messages unless the client asked the server not to
+#[cfg(feature = "deflate")]
+struct Deflate {
+    compress: flate2::Compress,
+    decompress: flate2::Decompress,
+    server_no_context_takeover: bool,
+}
+
+#[cfg(feature = "deflate")]
+impl Deflate {
+    /// Accepts an offer unless it limits the server's window below the 15 bits flate2 always uses
+    fn negotiate(params: &[String]) -> Option<Self> {
+        let mut server_no_context_takeover = false;
+        for param in params {
+            match param.split_once('=').map(|(name, value)| (name.trim(), value.trim().trim_matches('"'))) {
+                None if param == "server_no_context_takeover" => server_no_context_takeover = true,
+                None if param == "client_no_context_takeover" || param == "client_max_window_bits" => {}
+                Some(("server_max_window_bits", "15")) | Some(("client_max_window_bits", _)) => {}
+                _ => return None,
+            }
+        }
+        Some(Self {
+            compress: flate2::Compress::new(flate2::Compression::fast(), false),
+            decompress: flate2::Decompress::new(false),
+            server_no_context_takeover,
+        })
+    }
+
+    fn response_header(&self) -> &'static str {
+        if self.server_no_context_takeover {
+            "permessage-deflate; server_no_context_takeover"
+        } else {
+            "permessage-deflate"
+        }
+    }
+
+    fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        if self.server_no_context_takeover {
+            self.compress.reset();
+        }
+        let start = self.compress.total_in();
+        let mut output = Vec::with_capacity(data.len() / 2 + 64);
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            if output.len() == output.capacity() {
+                output.reserve(data.len() / 2 + 64);
+            }
+            self.compress
+                .compress_vec(&data[consumed..], &mut output, flate2::FlushCompress::Sync)
+                .map_err(io::Error::other)?;
+            // a sync flush that leaves spare room in the output has written everything
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == data.len() && output.len() < output.capacity() {
+                break;
+            }
+        }
+        // the empty stored block ending every flush is implied on the wire
+        if output.ends_with(&[0x00, 0x00, 0xFF, 0xFF]) {
+            output.truncate(output.len() - 4);
+        }
+        Ok(output)
+    }
+
+    fn decompress(&mut self, data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        let input = [data, &[0x00, 0x00, 0xFF, 0xFF]].concat();
+        let start = self.decompress.total_in();
+        let mut output = Vec::with_capacity(data.len() * 2 + 64);
+        loop {
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if output.len() == output.capacity() {
+                output.reserve(output.len());
+            }
+            let status = self
+                .decompress
+                .decompress_vec(&input[consumed..], &mut output, flate2::FlushDecompress::Sync)
+                .map_err(|_| Error::parse("Invalid compressed message"))?;
+            if output.len() > max_size {
+                return Err(Error::parse("Message too big"));
+            }
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if status == flate2::Status::StreamEnd || (consumed == input.len() && output.len() < output.capacity()) {
+                break;
+            }
+        }
+        Ok(output)
+    }
+}